@@ -0,0 +1,566 @@
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use futures::SinkExt;
+use tokio::net::UnixStream;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+use crabstore_common::objectid::ObjectId;
+
+// Bytes read or written per request by `dump` and `put`.
+const CHUNK_SIZE: u64 = 4 << 20;
+
+/// Inspects and manages a running store over its socket.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct CliArgs {
+    /// Path of the store's Unix socket.
+    #[arg(short = 's', long)]
+    socket_path: PathBuf,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Shows how much memory is in use and how many objects are held.
+    Stats,
+    /// Lists the objects held, in memory or spilled.
+    List(ListArgs),
+    /// Shows everything the store knows of an object.
+    Inspect {
+        /// ID of the object, in hex.
+        object_id: ObjectId,
+    },
+    /// Deletes an object. Clients using it keep it until they release it.
+    Delete {
+        /// ID of the object, in hex.
+        object_id: ObjectId,
+    },
+    /// Frees memory the way the store does when it runs out, dropping or
+    /// spilling objects nobody is using.
+    Evict {
+        /// How many bytes to free at least.
+        #[arg(long)]
+        bytes: u64,
+    },
+    /// Writes the data of an object, followed by its metadata, to stdout.
+    Dump {
+        /// ID of the object, in hex.
+        object_id: ObjectId,
+        /// How long to wait for the object to be sealed.
+        #[arg(long, default_value_t = 0)]
+        timeout_ms: i64,
+    },
+    /// Creates and seals an object holding what is read from stdin.
+    Put {
+        /// ID of the object, in hex.
+        object_id: ObjectId,
+        /// How many bytes at the end of the input are the object's metadata.
+        #[arg(long, default_value_t = 0)]
+        metadata_size: u64,
+        /// How many stores of the cluster should hold the object, 0 for the
+        /// store's default.
+        #[arg(long, default_value_t = 0)]
+        replication_factor: u32,
+    },
+}
+
+#[derive(Args)]
+struct ListArgs {
+    /// Only objects of at least this many bytes of data and metadata.
+    #[arg(long, default_value_t = 0)]
+    min_size: u64,
+    /// Only objects of at most this many bytes of data and metadata.
+    #[arg(long)]
+    max_size: Option<u64>,
+    /// Only objects created at least this long ago, in milliseconds.
+    #[arg(long, default_value_t = 0)]
+    min_age_ms: u64,
+    /// Only objects created at most this long ago, in milliseconds.
+    #[arg(long)]
+    max_age_ms: Option<u64>,
+    /// Only objects in this state. May be repeated.
+    #[arg(long, value_enum)]
+    state: Vec<State>,
+    /// Only pinned objects.
+    #[arg(long)]
+    pinned: bool,
+    /// Only objects whose ID starts with these hex digits.
+    #[arg(long, value_parser = parse_prefix, default_value = "")]
+    prefix: ::std::vec::Vec<u8>,
+    /// Only objects created by this client of the store. May be repeated.
+    #[arg(long)]
+    owner: Vec<u64>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum State {
+    /// Still being written by the client that created it.
+    Created,
+    /// Sealed and in memory.
+    Sealed,
+    /// Sealed and in the spill directory.
+    Spilled,
+}
+
+impl From<State> for messages::ObjectState {
+    fn from(state: State) -> Self {
+        match state {
+            State::Created => messages::ObjectState::Created,
+            State::Sealed => messages::ObjectState::Sealed,
+            State::Spilled => messages::ObjectState::Spilled,
+        }
+    }
+}
+
+fn parse_prefix(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        return Err("expected an even number of hex digits".to_string());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|e| e.to_string()))
+        .collect()
+}
+
+fn main() {
+    let args = CliArgs::parse();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let result = runtime.block_on(async {
+        let stream = UnixStream::connect(&args.socket_path).await.map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("could not connect to {}: {}", args.socket_path.display(), e),
+            )
+        })?;
+        let mut framed = Framed::new(stream, MessageCodec {});
+        run(&mut framed, args.command).await
+    });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+async fn run(framed: &mut Framed<UnixStream, MessageCodec>, command: Command) -> io::Result<()> {
+    match command {
+        Command::Stats => {
+            let Messages::StatsResponse(sr) =
+                call(framed, Messages::StatsRequest(messages::StatsRequest {})).await?
+            else {
+                return Err(unexpected());
+            };
+            println!("memory capacity:  {} bytes", sr.memory_capacity);
+            println!("memory used:      {} bytes", sr.memory_used);
+            println!("objects:          {}", sr.num_objects);
+            println!("spilled objects:  {}", sr.num_spilled_objects);
+            for ur in sr.under_replicated {
+                println!(
+                    "under-replicated: {} on {} of {} stores",
+                    object_id(&ur.object_id)?,
+                    ur.replicas,
+                    ur.replication_factor
+                );
+            }
+            for namespace in sr.namespaces {
+                let limit = |max: u64| match max {
+                    0 => "unlimited".to_string(),
+                    max => max.to_string(),
+                };
+                println!(
+                    "namespace {:?}: {}/{} bytes, {}/{} objects",
+                    namespace.namespace,
+                    namespace.used_bytes,
+                    limit(namespace.max_bytes),
+                    namespace.num_objects,
+                    limit(namespace.max_objects)
+                );
+            }
+        }
+        Command::List(list) => {
+            let mut request = messages::ListRequest {
+                min_size: list.min_size,
+                max_size: list.max_size.unwrap_or_default(),
+                min_age_ms: list.min_age_ms,
+                max_age_ms: list.max_age_ms.unwrap_or_default(),
+                pinned_only: list.pinned,
+                prefix: list.prefix,
+                owners: list.owner,
+                ..Default::default()
+            };
+            for state in list.state {
+                request.push_states(state.into());
+            }
+            println!(
+                "{:<40}  {:<7}  {:<6}  {:>12}  {:>12}  {:>4}  {:>8}  {:>8}",
+                "OBJECT ID", "STATE", "PINNED", "DATA", "METADATA", "REFS", "AGE", "IDLE"
+            );
+            loop {
+                let Messages::ListResponse(lr) =
+                    call(framed, Messages::ListRequest(request.clone())).await?
+                else {
+                    return Err(unexpected());
+                };
+                for info in lr.objects {
+                    println!(
+                        "{:<40}  {:<7}  {:<6}  {:>12}  {:>12}  {:>4}  {:>8}  {:>8}",
+                        object_id(&info.object_id)?,
+                        state_name(info.state()),
+                        info.pinned,
+                        info.data_size,
+                        info.metadata_size,
+                        info.ref_count,
+                        format_age(info.create_time_us),
+                        format_age(info.last_access_us)
+                    );
+                }
+                if lr.next_cursor.is_empty() {
+                    break;
+                }
+                request.cursor = lr.next_cursor;
+            }
+        }
+        Command::Inspect { object_id } => {
+            let request = messages::InspectRequest {
+                object_id: object_id.binary(),
+            };
+            let Messages::InspectResponse(ir) =
+                call(framed, Messages::InspectRequest(request)).await?
+            else {
+                return Err(unexpected());
+            };
+            check(ir.error())?;
+            let info = ir.info.unwrap_or_default();
+            println!("object id:      {}", object_id);
+            println!("state:          {}", state_name(info.state()));
+            println!("data size:      {} bytes", info.data_size);
+            println!("metadata size:  {} bytes", info.metadata_size);
+            println!("created:        {} ago", format_age(info.create_time_us));
+            println!("last access:    {} ago", format_age(info.last_access_us));
+            println!("created by:     {}", owner_name(info.owner));
+            println!("primary:        {}", info.primary);
+            println!("pinned:         {}", info.pinned);
+            println!("spilled:        {}", ir.spilled);
+            if let Some(spec) = ir.spec {
+                println!("segment:        {}", spec.segment_index);
+                println!("header offset:  {}", spec.header_offset);
+                println!("data offset:    {}", spec.data_offset);
+                println!("allocated size: {} bytes", spec.allocated_size);
+                println!("device:         {}", spec.device_num);
+                println!("mutable:        {}", spec.is_experimental_mutable_object);
+            }
+            if ir.holders.is_empty() {
+                println!("holders:        none");
+            }
+            for holder in ir.holders {
+                println!(
+                    "holder:         client {} with {} gets",
+                    holder.client_id, holder.count
+                );
+            }
+            println!("ref counted:    {}", ir.ref_counted);
+            if ir.ref_counted {
+                match ir.owner_node.as_str() {
+                    "" => println!("counted by:     this store"),
+                    node => println!("counted by:     {}", node),
+                }
+                println!("references:     {} held here", ir.local_references);
+                for remote in ir.remote_references {
+                    println!("references:     {} held on {}", remote.count, remote.node);
+                }
+            }
+        }
+        Command::Delete { object_id } => {
+            let request = messages::DeleteRequest {
+                object_id: object_id.binary(),
+            };
+            let Messages::DeleteResponse(dr) =
+                call(framed, Messages::DeleteRequest(request)).await?
+            else {
+                return Err(unexpected());
+            };
+            check(dr.error())?;
+        }
+        Command::Evict { bytes } => {
+            let request = messages::EvictRequest { bytes };
+            let Messages::EvictResponse(er) = call(framed, Messages::EvictRequest(request)).await?
+            else {
+                return Err(unexpected());
+            };
+            println!(
+                "freed {} bytes, evicted {} objects, spilled {} objects",
+                er.freed, er.evicted, er.spilled
+            );
+            check(er.error())?;
+        }
+        Command::Dump {
+            object_id,
+            timeout_ms,
+        } => {
+            let mut stdout = io::stdout().lock();
+            let mut offset = 0;
+            loop {
+                let request = messages::ReadChunkRequest {
+                    object_id: object_id.binary(),
+                    offset,
+                    length: CHUNK_SIZE,
+                    timeout_ms,
+                };
+                let Messages::ReadChunkResponse(rr) =
+                    call(framed, Messages::ReadChunkRequest(request)).await?
+                else {
+                    return Err(unexpected());
+                };
+                check(rr.error())?;
+                stdout.write_all(&rr.data)?;
+                offset += rr.data.len() as u64;
+                if offset >= rr.data_size + rr.metadata_size {
+                    break;
+                }
+                if rr.data.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "the store sent an empty chunk",
+                    ));
+                }
+            }
+            stdout.flush()?;
+        }
+        Command::Put {
+            object_id,
+            metadata_size,
+            replication_factor,
+        } => {
+            let mut contents = Vec::new();
+            io::stdin().lock().read_to_end(&mut contents)?;
+            let size = contents.len() as u64;
+            if metadata_size > size {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the input is only {} bytes", size),
+                ));
+            }
+            let mut offset = 0;
+            loop {
+                let end = (offset + CHUNK_SIZE).min(size);
+                let request = messages::WriteChunkRequest {
+                    object_id: object_id.binary(),
+                    data_size: size - metadata_size,
+                    metadata_size,
+                    offset,
+                    data: contents[offset as usize..end as usize].to_vec(),
+                    replication_factor,
+                };
+                let Messages::WriteChunkResponse(wr) =
+                    call(framed, Messages::WriteChunkRequest(request)).await?
+                else {
+                    return Err(unexpected());
+                };
+                check(wr.error())?;
+                offset = end;
+                if offset >= size {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// Sends `request` and waits for the store's response.
+async fn call(
+    framed: &mut Framed<UnixStream, MessageCodec>,
+    request: Messages,
+) -> io::Result<Messages> {
+    framed.send(request).await?;
+    loop {
+        match framed.next().await {
+            Some(Ok(Messages::ShutdownNotice(_))) => {
+                eprintln!("warning: the store is shutting down");
+            }
+            Some(Ok(Messages::ErrorResponse(er))) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("the store rejected the request: {}", er.reason),
+                ))
+            }
+            Some(Ok(Messages::Malformed(frame))) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    frame.to_string(),
+                ))
+            }
+            Some(response) => return response,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+}
+
+fn check(error: messages::Error) -> io::Result<()> {
+    match error {
+        messages::Error::Ok => Ok(()),
+        error => Err(io::Error::other(format!("the store answered {:?}", error))),
+    }
+}
+
+fn unexpected() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "unexpected response")
+}
+
+fn object_id(binary: &[u8]) -> io::Result<ObjectId> {
+    ObjectId::try_from_binary(binary).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn state_name(state: messages::ObjectState) -> &'static str {
+    match state {
+        messages::ObjectState::Created => "created",
+        messages::ObjectState::Sealed => "sealed",
+        messages::ObjectState::Spilled => "spilled",
+    }
+}
+
+// Who created an object, as numbered by the store.
+fn owner_name(owner: u64) -> String {
+    match owner {
+        u64::MAX => "the store".to_string(),
+        // As the store numbers chunked uploads.
+        owner if owner == u64::MAX - 1 => "a chunked upload".to_string(),
+        owner => format!("client {}", owner),
+    }
+}
+
+// How long ago `time_us`, in microseconds since the Unix epoch, was.
+fn format_age(time_us: u64) -> String {
+    let now_us = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64;
+    let secs = now_us.saturating_sub(time_us) / 1_000_000;
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m{}s", secs / 60, secs % 60),
+        _ => format!("{}h{}m", secs / 3600, secs / 60 % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list_args() {
+        let args = CliArgs::try_parse_from([
+            "crabstore-cli",
+            "-s",
+            "/tmp/store.sock",
+            "list",
+            "--state",
+            "sealed",
+            "--state",
+            "spilled",
+            "--prefix",
+            "0aFF",
+            "--owner",
+            "3",
+        ])
+        .unwrap();
+        let Command::List(list) = args.command else {
+            panic!("expected a list command");
+        };
+        assert_eq!(list.prefix, [0x0a, 0xff]);
+        assert_eq!(list.owner, [3]);
+        assert_eq!(list.state.len(), 2);
+        assert_eq!(list.max_size, None);
+
+        for prefix in ["abc", "zz", "éé"] {
+            let args = ["crabstore-cli", "-s", "sock", "list", "--prefix", prefix];
+            assert!(CliArgs::try_parse_from(args).is_err(), "{}", prefix);
+        }
+        let args = ["crabstore-cli", "-s", "sock", "inspect", "0123"];
+        assert!(CliArgs::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(owner_name(u64::MAX), "the store");
+        assert_eq!(owner_name(u64::MAX - 1), "a chunked upload");
+        assert_eq!(owner_name(7), "client 7");
+
+        let now_us = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_micros() as u64;
+        assert_eq!(format_age(now_us + 5_000_000), "0s");
+        assert_eq!(format_age(now_us - 90_000_000), "1m30s");
+        assert_eq!(format_age(now_us - 7_260_000_000), "2h1m");
+    }
+
+    // Runs `command` against a fake store that answers every request with
+    // `responses`.
+    async fn run_against(command: Command, responses: Vec<Messages>) -> io::Result<()> {
+        let (client, server) = UnixStream::pair().unwrap();
+        tokio::spawn(async move {
+            let mut framed = Framed::new(server, MessageCodec {});
+            if framed.next().await.is_some() {
+                for response in responses {
+                    framed.send(response).await.unwrap();
+                }
+            }
+        });
+        run(&mut Framed::new(client, MessageCodec {}), command).await
+    }
+
+    #[tokio::test]
+    async fn store_errors() {
+        let object_id = ObjectId::random();
+        let mut response = messages::DeleteResponse {
+            object_id: object_id.binary(),
+            ..Default::default()
+        };
+        assert!(run_against(
+            Command::Delete { object_id },
+            vec![Messages::DeleteResponse(response.clone())]
+        )
+        .await
+        .is_ok());
+        response.set_error(messages::Error::ObjectNonexistent);
+        let e = run_against(
+            Command::Delete { object_id },
+            vec![Messages::DeleteResponse(response)],
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(e.to_string(), "the store answered ObjectNonexistent");
+
+        // Shutdown notices are skipped.
+        let responses = vec![
+            Messages::ShutdownNotice(messages::ShutdownNotice { deadline_ms: 10 }),
+            Messages::ErrorResponse(messages::ErrorResponse {
+                reason: "no".to_string(),
+                ..Default::default()
+            }),
+        ];
+        let e = run_against(Command::Stats, responses).await.unwrap_err();
+        assert_eq!(e.to_string(), "the store rejected the request: no");
+
+        let responses = vec![Messages::StatsResponse(Default::default())];
+        let e = run_against(Command::Evict { bytes: 1 }, responses)
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "unexpected response");
+        let e = run_against(Command::Stats, Vec::new()).await.unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
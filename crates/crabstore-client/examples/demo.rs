@@ -1,16 +1,18 @@
 use std::path::PathBuf;
 
-use crabstore_client::CrabClient;
-use crabstore_common::objectid::ObjectId;
+use crabstore_client::client::{CrabClient, ObjectID};
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let mut client = CrabClient::new(PathBuf::from("sock"));
-    client.connect().await.expect("Couldn't send data");
+    client.connect().expect("Couldn't send data");
 
-    let oid = ObjectId::from_binary(b"00000000000000000000");
+    let oid = ObjectID::from_binary(b"00000000000000000000").expect("Invalid object id");
     client
-        .create(&oid, 20, 20)
-        .await
+        .create(oid, 20, 20, false, 0, false)
         .expect("Couldn't send data");
+
+    let oid = client
+        .put_content_addressed(b"hello crabstore")
+        .expect("Couldn't put object");
+    assert!(client.contains(oid).expect("Couldn't send data"));
 }
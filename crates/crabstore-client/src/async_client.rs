@@ -32,8 +32,10 @@ pub struct ObjectID(crabstore_common::objectid::ObjectId);
 #[pymethods]
 impl ObjectID {
     #[staticmethod]
-    pub fn from_binary(binary: &[u8]) -> Self {
-        ObjectID(crabstore_common::objectid::ObjectId::from_binary(binary))
+    pub fn from_binary(binary: &[u8]) -> PyResult<Self> {
+        crabstore_common::objectid::ObjectId::try_from_binary(binary)
+            .map(ObjectID)
+            .map_err(|e| pyexceptions::PyValueError::new_err(e.to_string()))
     }
 }
 
@@ -73,7 +75,7 @@ impl CrabClient {
         );
         self.framed = Some(Arc::new(Mutex::new(Framed::new(stream, MessageCodec {}))));
 
-        let request = Messages::ConnectRequest(messages::ConnectRequest {});
+        let request = Messages::ConnectRequest(messages::ConnectRequest::default());
         self.send_request(request).await?;
         debug!("Sent CONNECTION request to the server");
 
@@ -88,7 +90,7 @@ impl CrabClient {
                     "Invalid response received from sever",
                 ))
             }
-            Err(_) => Err(pyexceptions::PyConnectionError::new_err("")),
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
         }
     }
 
@@ -100,7 +102,7 @@ impl CrabClient {
         );
         self.framed = Some(Arc::new(Mutex::new(Framed::new(stream, MessageCodec {}))));
 
-        let request = Messages::ConnectRequest(messages::ConnectRequest {});
+        let request = Messages::ConnectRequest(messages::ConnectRequest::default());
         self.send_request(request).await?;
         debug!("Sent CONNECTION request to the server");
 
@@ -115,7 +117,7 @@ impl CrabClient {
                     "Invalid response received from sever",
                 ))
             }
-            Err(_) => Err(pyexceptions::PyConnectionError::new_err("")),
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
         }
     }
     pub async fn create_(
@@ -146,7 +148,7 @@ impl CrabClient {
                     "Invalid response received from sever",
                 ))
             }
-            Err(_) => Err(pyexceptions::PyConnectionError::new_err("")),
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
         }
     }
 }
@@ -1,20 +1,31 @@
 use bytes::BytesMut;
+use crabstore_common::header;
+use crabstore_common::header::VersionCounter;
 use crabstore_common::messages::messages;
 use crabstore_common::messages::MessageCodec;
 use crabstore_common::messages::Messages;
+use crabstore_common::messages::MAX_FRAME_SIZE;
+use crabstore_common::objectid::ObjectId;
 use log::debug;
-use prost::Message;
+use memmap2::MmapMut;
 use pyo3::exceptions as pyexceptions;
 use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::borrow::Cow;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
 use std::io;
 use std::io::Read;
 use std::io::Write;
+use std::net::TcpStream;
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use crate::status;
-use tokio_util::codec::Encoder;
+use tokio_util::codec::{Decoder, Encoder};
 
 #[pyclass]
 #[derive(Clone)]
@@ -23,15 +34,196 @@ pub struct ObjectID(crabstore_common::objectid::ObjectId);
 #[pymethods]
 impl ObjectID {
     #[staticmethod]
-    pub fn from_binary(binary: &[u8]) -> Self {
-        ObjectID(crabstore_common::objectid::ObjectId::from_binary(binary))
+    pub fn from_binary(binary: &[u8]) -> PyResult<Self> {
+        crabstore_common::objectid::ObjectId::try_from_binary(binary)
+            .map(ObjectID)
+            .map_err(|e| pyexceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    #[staticmethod]
+    pub fn from_hex(hex: &str) -> PyResult<Self> {
+        crabstore_common::objectid::ObjectId::from_hex(hex)
+            .map(ObjectID)
+            .map_err(|e| pyexceptions::PyValueError::new_err(e.to_string()))
+    }
+
+    #[staticmethod]
+    pub fn from_random() -> Self {
+        ObjectID(crabstore_common::objectid::ObjectId::random())
+    }
+
+    #[staticmethod]
+    pub fn from_content(content: &[u8]) -> Self {
+        ObjectID(crabstore_common::objectid::ObjectId::from_content(content))
+    }
+
+    pub fn binary<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, self.0.data())
+    }
+
+    pub fn hex(&self) -> String {
+        self.0.hex()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("ObjectID({})", self.0.hex())
+    }
+
+    fn __str__(&self) -> String {
+        self.0.hex()
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+
+    fn __hash__(&self) -> u64 {
+        self.0.hash()
+    }
+}
+
+/// A store of the cluster, as seen by the store a client is connected to.
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct Member {
+    #[pyo3(get)]
+    pub node_id: String,
+    #[pyo3(get)]
+    pub address: String,
+    #[pyo3(get)]
+    pub alive: bool,
+    #[pyo3(get)]
+    pub memory_capacity: u64,
+    #[pyo3(get)]
+    pub memory_used: u64,
+    /// Milliseconds since the store was last heard from.
+    #[pyo3(get)]
+    pub last_heartbeat_ms: u64,
+}
+
+#[pymethods]
+impl Member {
+    fn __repr__(&self) -> String {
+        format!(
+            "Member(node_id={:?}, address={:?}, alive={}, memory_used={}/{})",
+            self.node_id, self.address, self.alive, self.memory_used, self.memory_capacity
+        )
+    }
+}
+
+/// Usage of the store a client is connected to.
+#[pyclass]
+#[derive(Clone)]
+pub struct Stats {
+    #[pyo3(get)]
+    pub memory_capacity: u64,
+    #[pyo3(get)]
+    pub memory_used: u64,
+    /// Objects held in memory, sealed or not.
+    #[pyo3(get)]
+    pub num_objects: u64,
+    /// Objects spilled to disk to make room.
+    #[pyo3(get)]
+    pub num_spilled_objects: u64,
+    /// Objects created in the store that fewer stores hold than they should,
+    /// as (id, stores holding it, replication factor).
+    #[pyo3(get)]
+    pub under_replicated: Vec<(ObjectID, u32, u32)>,
+    /// What the namespaces holding objects or with a quota of their own
+    /// hold, as (namespace, bytes used, objects, max bytes, max objects),
+    /// limits being 0 if there are none.
+    #[pyo3(get)]
+    pub namespaces: Vec<(String, u64, u64, u64, u64)>,
+}
+
+#[pymethods]
+impl Stats {
+    fn __repr__(&self) -> String {
+        format!(
+            "Stats(memory_used={}/{}, num_objects={}, num_spilled_objects={}, under_replicated={})",
+            self.memory_used,
+            self.memory_capacity,
+            self.num_objects,
+            self.num_spilled_objects,
+            self.under_replicated.len()
+        )
+    }
+}
+
+// Object bytes sent per PutChunk by clients that cannot map the store.
+const PUT_CHUNK_SIZE: usize = 1 << 20;
+
+// Object bytes per WriteChunk or ReadChunk unless asked otherwise.
+const CHUNK_SIZE: u64 = 4 << 20;
+
+// Prefix of store addresses that are reached over TCP rather than through a
+// Unix socket.
+const TCP_SCHEME: &str = "tcp://";
+
+enum Connection {
+    Unix(UnixStream),
+    // Remote stores cannot share memory with us, objects are copied.
+    Tcp(TcpStream),
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Connection::Unix(stream) => stream.read(buf),
+            Connection::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Connection::Unix(stream) => stream.write(buf),
+            Connection::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Connection::Unix(stream) => stream.flush(),
+            Connection::Tcp(stream) => stream.flush(),
+        }
     }
 }
 
 #[pyclass]
 pub struct CrabClient {
+    // Path of the store's Unix socket, or tcp://host:port.
     socket_name: PathBuf,
-    stream: Option<Mutex<UnixStream>>,
+    stream: Option<Mutex<Connection>>,
+    // Store segments mapped so far, keyed by segment index.
+    segments: HashMap<i32, MmapMut>,
+    // Objects created by this client that are not sealed yet.
+    created: HashMap<ObjectId, messages::CreateResponse>,
+    // Mutable objects this client writes or reads.
+    mutable_objects: HashMap<ObjectId, MutableObject>,
+    /// Send a checksum of the data and metadata when sealing, so readers can
+    /// verify it.
+    #[pyo3(get, set)]
+    pub checksum_on_seal: bool,
+    /// Recompute the checksum of every object read with `get` and fail if it
+    /// does not match the one recorded by the writer.
+    #[pyo3(get, set)]
+    pub verify_on_get: bool,
+    /// Set once the store said it is shutting down. It closes the
+    /// connection shortly after.
+    #[pyo3(get)]
+    pub store_shutting_down: bool,
+    /// The namespace the objects this client creates are charged to, for
+    /// the store's quotas. Sent on `connect`; empty for the default one.
+    #[pyo3(get, set)]
+    pub namespace: String,
+}
+
+struct MutableObject {
+    spec: messages::ObjectSpec,
+    // Whether this client created the object and may write it.
+    writer: bool,
 }
 
 impl CrabClient {
@@ -50,14 +242,42 @@ impl CrabClient {
         }
     }
 
+    // Reads the answer to the last request, taking note of the notices the
+    // store sends unasked in between.
     fn receive_response(&mut self) -> Result<Messages, io::Error> {
+        loop {
+            match self.receive_message()? {
+                Messages::ShutdownNotice(notice) => {
+                    debug!(
+                        "Store is shutting down, closing in {} ms",
+                        notice.deadline_ms
+                    );
+                    self.store_shutting_down = true;
+                }
+                Messages::ErrorResponse(er) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("the store rejected the request: {}", er.reason),
+                    ))
+                }
+                Messages::Malformed(frame) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        frame.to_string(),
+                    ))
+                }
+                response => return Ok(response),
+            }
+        }
+    }
+
+    fn receive_message(&mut self) -> Result<Messages, io::Error> {
         if let Some(stream_mutex) = &mut self.stream {
             let stream = stream_mutex.get_mut().unwrap();
 
             let mut type_and_size = [0u8; 10];
             stream.read_exact(&mut type_and_size)?;
 
-            let msg_type = u16::from_le_bytes([type_and_size[0], type_and_size[1]]);
             let msg_size = u64::from_le_bytes([
                 type_and_size[2],
                 type_and_size[3],
@@ -69,59 +289,19 @@ impl CrabClient {
                 type_and_size[9],
             ]);
 
-            let mut src = BytesMut::from(&vec![0u8; msg_size as usize][..]);
-            stream.read_exact(src.as_mut())?;
-
-            // Based on the message type, decode the appropriate Protobuf message
-            match msg_type {
-                0 => {
-                    let cr = messages::ConnectRequest::decode(src);
-                    match cr {
-                        Ok(cr) => Ok(Messages::ConnectRequest(cr)),
-                        Err(_) => Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Message decoding failed",
-                        )),
-                    }
-                }
-                1 => {
-                    let cr = messages::ConnectResponse::decode(src);
-                    match cr {
-                        Ok(cr) => Ok(Messages::ConnectResponse(cr)),
-                        Err(_) => Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Message decoding failed",
-                        )),
-                    }
-                }
-                2 => {
-                    let cr = messages::CreateRequest::decode(src);
-                    match cr {
-                        Ok(cr) => Ok(Messages::CreateRequest(cr)),
-                        Err(_) => Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Message decoding failed",
-                        )),
-                    }
-                }
-                3 => {
-                    let cr = messages::CreateResponse::decode(src);
-                    match cr {
-                        Ok(cr) => Ok(Messages::CreateResponse(cr)),
-                        Err(_) => Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            "Message decoding failed",
-                        )),
-                    }
-                }
-                _ => {
-                    // Unknown message type
-                    Err(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "Unknown message type",
-                    ))
-                }
+            if msg_size > MAX_FRAME_SIZE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("frame of {} bytes is larger than the limit", msg_size),
+                ));
             }
+            let mut src = BytesMut::from(&type_and_size[..]);
+            src.resize(10 + msg_size as usize, 0);
+            stream.read_exact(&mut src[10..])?;
+
+            MessageCodec {}.decode(&mut src)?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Message decoding failed")
+            })
         } else {
             Err(io::Error::new(
                 io::ErrorKind::NotConnected,
@@ -129,27 +309,518 @@ impl CrabClient {
             ))
         }
     }
+
+    fn is_remote(&self) -> bool {
+        matches!(&self.stream, Some(stream) if matches!(*stream.lock().unwrap(), Connection::Tcp(_)))
+    }
+
+    // Fails for operations that need the store's memory mapped.
+    fn check_local(&self, oid: &ObjectId) -> PyResult<()> {
+        if self.is_remote() {
+            return Err(pyexceptions::PyNotImplementedError::new_err(format!(
+                "object {} cannot be mapped over a TCP connection, use put and get",
+                oid
+            )));
+        }
+        Ok(())
+    }
+
+    // Uploads an object by copying it to the store, in PUT_CHUNK_SIZE pieces.
+    // Takes the same options as a create.
+    fn put_copy_(
+        &mut self,
+        oid: &ObjectId,
+        data: &[u8],
+        options: messages::CreateRequest,
+    ) -> PyResult<status::Status> {
+        let mut chunks = data.chunks(PUT_CHUNK_SIZE);
+        let request = Messages::PutRequest(messages::PutRequest {
+            object_id: oid.binary(),
+            data_size: data.len() as u64,
+            metadata_size: 0,
+            data: chunks.next().unwrap_or_default().to_vec(),
+            wait_for_seal: options.wait_for_seal,
+            replication_factor: options.replication_factor,
+            ref_counted: options.ref_counted,
+        });
+        self.send_request(request)?;
+        for chunk in chunks {
+            let request = Messages::PutChunk(messages::PutChunk {
+                object_id: oid.binary(),
+                data: chunk.to_vec(),
+            });
+            self.send_request(request)?;
+        }
+        debug!("Sent PUT request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::PutResponse(pr)) => {
+                debug!("PUT response received {:?}", pr);
+                Ok(status::Status::from_store_error(pr.error(), oid))
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    // Downloads a copy of the data of an object.
+    fn fetch_(&mut self, oid: &ObjectId, timeout_ms: i64) -> PyResult<Option<Vec<u8>>> {
+        let request = Messages::FetchRequest(messages::FetchRequest {
+            object_id: oid.binary(),
+            timeout_ms,
+            max_chunk_size: 0,
+            no_pull: false,
+        });
+        self.send_request(request)?;
+        debug!("Sent FETCH request to the server");
+
+        let mut bytes = Vec::new();
+        loop {
+            let fr = match self.receive_response() {
+                Ok(Messages::FetchResponse(fr)) => fr,
+                Ok(r) => {
+                    debug!("Invalid response received {:?}", r);
+                    return Err(pyexceptions::PyValueError::new_err(
+                        "Invalid response received from sever",
+                    ));
+                }
+                Err(e) => return Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+            };
+            match fr.error() {
+                messages::Error::Ok => {}
+                messages::Error::ObjectNonexistent | messages::Error::ObjectNotSealed => {
+                    return Ok(None)
+                }
+                e => return Err(status::Status::from_store_error(e, oid).into()),
+            }
+            if bytes.is_empty() {
+                bytes.reserve((fr.data_size + fr.metadata_size) as usize);
+            }
+            bytes.extend_from_slice(&fr.data);
+            if fr.last {
+                debug!("FETCH of {} complete, {} bytes", oid, bytes.len());
+                bytes.truncate(fr.data_size as usize);
+                return Ok(Some(bytes));
+            }
+        }
+    }
+
+    fn create_(
+        &mut self,
+        oid: &ObjectId,
+        request: messages::CreateRequest,
+    ) -> PyResult<messages::CreateResponse> {
+        let request = Messages::CreateRequest(messages::CreateRequest {
+            object_id: oid.binary(),
+            ..request
+        });
+        self.send_request(request)?;
+        debug!("Sent CREATE request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::CreateResponse(cr)) => {
+                debug!("CREATE response received {:?}", cr);
+                if cr.error() == messages::Error::Ok {
+                    self.created.insert(*oid, cr.clone());
+                }
+                Ok(cr)
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    fn seal_(&mut self, oid: &ObjectId) -> PyResult<status::Status> {
+        let mut digest = Vec::new();
+        if let Some(cr) = self.created.remove(oid) {
+            let spec = cr.plasma_object.unwrap_or_default();
+            if self.checksum_on_seal && !spec.is_experimental_mutable_object {
+                let segment = self.segment(spec.segment_index, &cr.store_path)?;
+                let (data, metadata) = object_buffers(segment, &spec);
+                digest = header::checksum(data, metadata).to_vec();
+            }
+        }
+        let request = Messages::SealRequest(messages::SealRequest {
+            object_id: oid.binary(),
+            digest,
+        });
+        self.send_request(request)?;
+        debug!("Sent SEAL request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::SealResponse(sr)) => {
+                debug!("SEAL response received {:?}", sr);
+                Ok(status::Status::from_store_error(sr.error(), oid))
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    // Gets a reference to `oid` and maps its segment. The caller must release
+    // the reference.
+    fn get_spec_(
+        &mut self,
+        oid: &ObjectId,
+        timeout_ms: i64,
+    ) -> PyResult<Option<messages::ObjectSpec>> {
+        let request = Messages::GetRequest(messages::GetRequest {
+            object_ids: vec![oid.binary()],
+            timeout_ms,
+        });
+        self.send_request(request)?;
+        debug!("Sent GET request to the server");
+
+        let gr = match self.receive_response() {
+            Ok(Messages::GetResponse(gr)) => {
+                debug!("GET response received {:?}", gr);
+                gr
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                return Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ));
+            }
+            Err(e) => return Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        };
+
+        match gr.errors().next() {
+            Some(messages::Error::Ok) => {}
+            Some(messages::Error::ObjectNonexistent) | Some(messages::Error::ObjectNotSealed) => {
+                return Ok(None)
+            }
+            Some(e) => return Err(status::Status::from_store_error(e, oid).into()),
+            None => {
+                return Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+        }
+        let spec = gr.plasma_objects.into_iter().next().unwrap_or_default();
+        self.segment(spec.segment_index, &gr.store_path)?;
+        Ok(Some(spec))
+    }
+
+    fn get_(&mut self, oid: &ObjectId, timeout_ms: i64) -> PyResult<Option<Vec<u8>>> {
+        if self.is_remote() {
+            return self.fetch_(oid, timeout_ms);
+        }
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64);
+        loop {
+            let Some(spec) = self.get_spec_(oid, timeout_ms)? else {
+                return Ok(None);
+            };
+            let segment = &self.segments[&spec.segment_index];
+            let checked = check_object(segment, oid, &spec, self.verify_on_get);
+            let data = checked.map(|()| {
+                if spec.is_experimental_mutable_object {
+                    copy_snapshot(segment, &spec, deadline)
+                } else {
+                    Snapshot::Copied(0, object_buffers(segment, &spec).0.to_vec())
+                }
+            });
+
+            let status = self.release_(oid)?;
+            let data = data.map_err(PyErr::from)?;
+            if !status.is_ok() {
+                return Err(status.into());
+            }
+            match data {
+                Snapshot::Copied(_, data) => return Ok(Some(data)),
+                Snapshot::Busy => return Ok(None),
+                // Resized into a new allocation; ask the store again.
+                Snapshot::Moved => continue,
+            }
+        }
+    }
+
+    fn release_(&mut self, oid: &ObjectId) -> PyResult<status::Status> {
+        let request = Messages::ReleaseRequest(messages::ReleaseRequest {
+            object_id: oid.binary(),
+        });
+        self.send_request(request)?;
+        debug!("Sent RELEASE request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::ReleaseResponse(rr)) => {
+                debug!("RELEASE response received {:?}", rr);
+                Ok(status::Status::from_store_error(rr.error(), oid))
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    fn pin_(&mut self, oid: &ObjectId, pinned: bool) -> PyResult<status::Status> {
+        let object_id = oid.binary();
+        let request = if pinned {
+            Messages::PinRequest(messages::PinRequest { object_id })
+        } else {
+            Messages::UnpinRequest(messages::UnpinRequest { object_id })
+        };
+        self.send_request(request)?;
+        debug!("Sent PIN request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::PinResponse(pr)) if pinned => {
+                debug!("PIN response received {:?}", pr);
+                Ok(status::Status::from_store_error(pr.error(), oid))
+            }
+            Ok(Messages::UnpinResponse(ur)) if !pinned => {
+                debug!("UNPIN response received {:?}", ur);
+                Ok(status::Status::from_store_error(ur.error(), oid))
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    // Sends one of the requests answered with just an error, and returns it.
+    fn simple_request_(&mut self, oid: &ObjectId, request: Messages) -> PyResult<status::Status> {
+        self.send_request(request)?;
+        let error = match self.receive_response() {
+            Ok(Messages::AddReferenceResponse(r)) => r.error(),
+            Ok(Messages::RemoveReferenceResponse(r)) => r.error(),
+            Ok(Messages::DeleteResponse(r)) => r.error(),
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                return Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ));
+            }
+            Err(e) => return Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        };
+        Ok(status::Status::from_store_error(error, oid))
+    }
+
+    // Sends one WriteChunkRequest. Errors of kind InvalidData are answers
+    // that make no sense, the others mean the connection is gone.
+    fn write_chunk_(
+        &mut self,
+        oid: &ObjectId,
+        size: u64,
+        offset: u64,
+        data: &[u8],
+        replication_factor: u32,
+    ) -> io::Result<messages::WriteChunkResponse> {
+        let request = Messages::WriteChunkRequest(messages::WriteChunkRequest {
+            object_id: oid.binary(),
+            data_size: size,
+            metadata_size: 0,
+            offset,
+            data: data.to_vec(),
+            replication_factor,
+        });
+        self.send_request(request)?;
+        match self.receive_response()? {
+            Messages::WriteChunkResponse(wr) => Ok(wr),
+            r => {
+                debug!("Invalid response received {:?}", r);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid response received from sever",
+                ))
+            }
+        }
+    }
+
+    // Sends one ReadChunkRequest, with errors as for write_chunk_.
+    fn read_chunk_(
+        &mut self,
+        oid: &ObjectId,
+        offset: u64,
+        length: u64,
+        timeout_ms: i64,
+    ) -> io::Result<messages::ReadChunkResponse> {
+        let request = Messages::ReadChunkRequest(messages::ReadChunkRequest {
+            object_id: oid.binary(),
+            offset,
+            length,
+            timeout_ms,
+        });
+        self.send_request(request)?;
+        match self.receive_response()? {
+            Messages::ReadChunkResponse(rr) => Ok(rr),
+            r => {
+                debug!("Invalid response received {:?}", r);
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid response received from sever",
+                ))
+            }
+        }
+    }
+
+    // Reconnects after `e` broke off a chunked transfer, unless `retries`
+    // are used up or the store's answer was the problem.
+    fn reconnect_(&mut self, e: io::Error, retries: &mut u32) -> PyResult<()> {
+        if *retries == 0 || e.kind() == io::ErrorKind::InvalidData {
+            return Err(e.into());
+        }
+        *retries -= 1;
+        debug!("Connection lost ({}), reconnecting to resume", e);
+        self.connect()?;
+        Ok(())
+    }
+
+    fn version_counter(&self, oid: &ObjectId) -> PyResult<(VersionCounter<'_>, &MutableObject)> {
+        let object = self.mutable_objects.get(oid).ok_or_else(|| {
+            pyexceptions::PyKeyError::new_err(format!("mutable object {} is not mapped", oid))
+        })?;
+        let segment = &self.segments[&object.spec.segment_index];
+        let header = segment[object.spec.header_offset as usize..].as_ptr();
+        // SAFETY: the header lies inside a segment we keep mapped for the
+        // lifetime of the client, and allocations are 64 byte aligned.
+        Ok((unsafe { VersionCounter::from_header(header) }, object))
+    }
+
+    // Maps a mutable object for reading, if this client has not already.
+    fn map_mutable_(&mut self, oid: &ObjectId, timeout_ms: i64) -> PyResult<()> {
+        if self.mutable_objects.contains_key(oid) {
+            return Ok(());
+        }
+        self.check_local(oid)?;
+        let spec = self.get_spec_(oid, timeout_ms)?.ok_or_else(|| {
+            PyErr::from(status::Status::object_not_found(format!(
+                "object {} does not exist",
+                oid
+            )))
+        })?;
+        if !spec.is_experimental_mutable_object {
+            self.release_(oid)?;
+            return Err(pyexceptions::PyTypeError::new_err(format!(
+                "object {} is not mutable",
+                oid
+            )));
+        }
+        let segment = &self.segments[&spec.segment_index];
+        if let Err(status) = check_object(segment, oid, &spec, false) {
+            self.release_(oid)?;
+            return Err(status.into());
+        }
+        self.mutable_objects.insert(
+            *oid,
+            MutableObject {
+                spec,
+                writer: false,
+            },
+        );
+        Ok(())
+    }
+
+    // Follows a mutable object that its writer moved to a new allocation,
+    // trading the reference on the old copy for one on the new.
+    fn remap_mutable_(&mut self, oid: &ObjectId) -> PyResult<()> {
+        debug!("Mutable object {} moved, mapping it again", oid);
+        let Some(spec) = self.get_spec_(oid, 0)? else {
+            self.mutable_objects.remove(oid);
+            return Err(
+                status::Status::object_not_found(format!("object {} does not exist", oid)).into(),
+            );
+        };
+        let status = self.release_(oid)?;
+        if let Some(object) = self.mutable_objects.get_mut(oid) {
+            object.spec = spec;
+        }
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status.into())
+        }
+    }
+
+    fn segment(&mut self, segment_index: i32, store_path: &str) -> io::Result<&mut MmapMut> {
+        match self.segments.entry(segment_index) {
+            Entry::Occupied(e) => Ok(e.into_mut()),
+            Entry::Vacant(e) => {
+                let file = OpenOptions::new().read(true).write(true).open(store_path)?;
+                // SAFETY: the store never truncates a segment while it is
+                // serving clients, and objects are only written by their
+                // creator before seal.
+                let mmap = unsafe { MmapMut::map_mut(&file)? };
+                debug!("Mapped segment {} from {}", segment_index, store_path);
+                Ok(e.insert(mmap))
+            }
+        }
+    }
 }
 
 #[pymethods]
 impl CrabClient {
+    /// `socket_name` is the path of the store's Unix socket, or
+    /// `tcp://host:port` for a store listening on TCP. Objects of a TCP
+    /// connected store are copied rather than mapped, so only `put`, `get`
+    /// and `contains` work over TCP.
     #[new]
     pub fn new(socket_name: PathBuf) -> Self {
         CrabClient {
             socket_name,
             stream: None,
+            segments: HashMap::new(),
+            created: HashMap::new(),
+            mutable_objects: HashMap::new(),
+            checksum_on_seal: false,
+            verify_on_get: false,
+            store_shutting_down: false,
+            namespace: String::new(),
         }
     }
 
     pub fn connect(&mut self) -> PyResult<status::Status> {
-        let stream = UnixStream::connect(&self.socket_name)?;
+        let stream = match self
+            .socket_name
+            .to_str()
+            .and_then(|s| s.strip_prefix(TCP_SCHEME))
+        {
+            Some(address) => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                Connection::Tcp(stream)
+            }
+            None => Connection::Unix(UnixStream::connect(&self.socket_name)?),
+        };
         debug!(
             "Connection with server established on socket_path = {:?}",
             &self.socket_name
         );
         self.stream = Some(Mutex::new(stream));
+        self.store_shutting_down = false;
+        // The store may have restarted since the last connection, with a
+        // new pool file, and it aborted the objects this client had not
+        // sealed and released those it held when the connection closed.
+        self.segments.clear();
+        self.created.clear();
+        self.mutable_objects.clear();
 
-        let request = Messages::ConnectRequest(messages::ConnectRequest {});
+        let request = Messages::ConnectRequest(messages::ConnectRequest {
+            namespace: self.namespace.clone(),
+        });
         self.send_request(request)?;
         debug!("Sent CONNECTION request to the server");
 
@@ -164,31 +835,498 @@ impl CrabClient {
                     "Invalid response received from sever",
                 ))
             }
-            Err(_) => Err(pyexceptions::PyConnectionError::new_err("")),
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
         }
     }
 
+    /// Creates `oid`. If another client is creating the same id, returns
+    /// `ObjectExists` right away, or with `wait_for_seal` only once that
+    /// client has sealed it. Should the other client abort instead, this
+    /// call becomes the creator. If neither happens within a minute, it
+    /// returns `ObjectInUse`.
+    ///
+    /// Once sealed, the object is copied to other stores of the cluster
+    /// until `replication_factor` stores hold it, 0 meaning the store's
+    /// default.
+    ///
+    /// A `ref_counted` object is deleted from every store once no client
+    /// anywhere holds a reference to it. This client holds the first one,
+    /// until `remove_reference` or until it disconnects.
+    #[pyo3(signature = (oid, data_size, metadata_size, wait_for_seal=false, replication_factor=0, ref_counted=false))]
     pub fn create(
         &mut self,
         oid: ObjectID,
         data_size: u64,
         metadata_size: u64,
+        wait_for_seal: bool,
+        replication_factor: u32,
+        ref_counted: bool,
     ) -> PyResult<status::Status> {
-        let request = Messages::CreateRequest(messages::CreateRequest {
+        self.check_local(&oid.0)?;
+        let request = messages::CreateRequest {
+            data_size,
+            metadata_size,
+            wait_for_seal,
+            replication_factor,
+            ref_counted,
+            ..Default::default()
+        };
+        let cr = self.create_(&oid.0, request)?;
+        Ok(status::Status::from_store_error(cr.error(), &oid.0))
+    }
+
+    pub fn seal(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        self.seal_(&oid.0)
+    }
+
+    pub fn contains(&mut self, oid: ObjectID) -> PyResult<bool> {
+        let request = Messages::ContainsRequest(messages::ContainsRequest {
+            object_id: oid.0.binary(),
+        });
+        self.send_request(request)?;
+        debug!("Sent CONTAINS request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::ContainsResponse(cr)) => {
+                debug!("CONTAINS response received {:?}", cr);
+                Ok(cr.has_object)
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    /// Returns a copy of the data of `oid`, waiting up to `timeout_ms` for
+    /// it to be sealed, or `None` if it is not available in time.
+    #[pyo3(signature = (oid, timeout_ms=0))]
+    pub fn get(&mut self, oid: ObjectID, timeout_ms: i64) -> PyResult<Option<Cow<'static, [u8]>>> {
+        Ok(self.get_(&oid.0, timeout_ms)?.map(Cow::Owned))
+    }
+
+    /// Creates `oid`, copies `data` into it and seals it. See `create` for
+    /// `replication_factor` and `ref_counted`.
+    #[pyo3(signature = (oid, data, wait_for_seal=false, replication_factor=0, ref_counted=false))]
+    pub fn put(
+        &mut self,
+        oid: ObjectID,
+        data: &[u8],
+        wait_for_seal: bool,
+        replication_factor: u32,
+        ref_counted: bool,
+    ) -> PyResult<status::Status> {
+        let request = messages::CreateRequest {
+            data_size: data.len() as u64,
+            wait_for_seal,
+            replication_factor,
+            ref_counted,
+            ..Default::default()
+        };
+        if self.is_remote() {
+            return self.put_copy_(&oid.0, data, request);
+        }
+        let cr = self.create_(&oid.0, request)?;
+        if cr.error() != messages::Error::Ok {
+            return Ok(status::Status::from_store_error(cr.error(), &oid.0));
+        }
+        let spec = cr.plasma_object.unwrap_or_default();
+        let segment = self.segment(spec.segment_index, &cr.store_path)?;
+        let offset = spec.data_offset as usize;
+        segment[offset..offset + data.len()].copy_from_slice(data);
+
+        self.seal_(&oid.0)
+    }
+
+    /// Uploads `data` as `oid` in pieces of at most `chunk_size` bytes, so
+    /// that no single message has to hold a huge object. `progress`, if
+    /// given, is called with the bytes the store has and the total after
+    /// each piece. Should the connection drop, the client reconnects up to
+    /// `max_retries` times and resumes where the store got to. Calling this
+    /// again after it failed resumes the same way, if the store has not
+    /// given up on the upload in the meantime.
+    #[pyo3(signature = (oid, data, chunk_size=CHUNK_SIZE, replication_factor=0, progress=None, max_retries=3))]
+    pub fn put_chunked(
+        &mut self,
+        oid: ObjectID,
+        data: &[u8],
+        chunk_size: u64,
+        replication_factor: u32,
+        progress: Option<PyObject>,
+        mut max_retries: u32,
+    ) -> PyResult<status::Status> {
+        if chunk_size == 0 {
+            return Err(pyexceptions::PyValueError::new_err(
+                "chunk_size must be at least 1",
+            ));
+        }
+        let size = data.len() as u64;
+        let mut resumed = false;
+        // Unknown until the store says how far an earlier attempt got, which
+        // an empty chunk asks for.
+        let mut received = None;
+        loop {
+            let (offset, chunk) = match received {
+                None => (0, &[][..]),
+                Some(offset) => (
+                    offset,
+                    &data[offset as usize..size.min(offset + chunk_size) as usize],
+                ),
+            };
+            let wr = match self.write_chunk_(&oid.0, size, offset, chunk, replication_factor) {
+                Ok(wr) => wr,
+                Err(e) => {
+                    self.reconnect_(e, &mut max_retries)?;
+                    resumed = true;
+                    received = None;
+                    continue;
+                }
+            };
+            match wr.error() {
+                messages::Error::Ok => {}
+                // Someone else wrote the same piece first, go on from theirs.
+                messages::Error::InvalidRequest if wr.received > offset => {}
+                // The answer to the last piece was lost with the connection.
+                messages::Error::ObjectExists if resumed => return Ok(status::Status::ok()),
+                e => return Ok(status::Status::from_store_error(e, &oid.0)),
+            }
+            received = Some(wr.received);
+            if let Some(progress) = &progress {
+                Python::with_gil(|py| progress.call1(py, (wr.received, size)))?;
+            }
+            if wr.received == size {
+                debug!("Chunked upload of {} complete", oid.0);
+                return Ok(status::Status::ok());
+            }
+        }
+    }
+
+    /// Returns a copy of the data of `oid` read in pieces of at most
+    /// `chunk_size` bytes, or `None` if it is not sealed within
+    /// `timeout_ms`. `progress` and `max_retries` work as in `put_chunked`.
+    #[pyo3(signature = (oid, timeout_ms=0, chunk_size=CHUNK_SIZE, progress=None, max_retries=3))]
+    pub fn get_chunked(
+        &mut self,
+        oid: ObjectID,
+        timeout_ms: i64,
+        chunk_size: u64,
+        progress: Option<PyObject>,
+        mut max_retries: u32,
+    ) -> PyResult<Option<Cow<'static, [u8]>>> {
+        if chunk_size == 0 {
+            return Err(pyexceptions::PyValueError::new_err(
+                "chunk_size must be at least 1",
+            ));
+        }
+        let mut bytes = Vec::new();
+        loop {
+            let offset = bytes.len() as u64;
+            let rr = match self.read_chunk_(&oid.0, offset, chunk_size, timeout_ms) {
+                Ok(rr) => rr,
+                Err(e) => {
+                    self.reconnect_(e, &mut max_retries)?;
+                    continue;
+                }
+            };
+            match rr.error() {
+                messages::Error::Ok => {}
+                messages::Error::ObjectNonexistent | messages::Error::ObjectNotSealed => {
+                    return Ok(None)
+                }
+                e => return Err(status::Status::from_store_error(e, &oid.0).into()),
+            }
+            let size = rr.data_size + rr.metadata_size;
+            if offset < size && rr.data.is_empty() {
+                return Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ));
+            }
+            bytes.extend_from_slice(&rr.data);
+            if let Some(progress) = &progress {
+                Python::with_gil(|py| progress.call1(py, (bytes.len(), size)))?;
+            }
+            if bytes.len() as u64 >= size {
+                debug!("Chunked download of {} complete", oid.0);
+                bytes.truncate(rr.data_size as usize);
+                return Ok(Some(Cow::Owned(bytes)));
+            }
+        }
+    }
+
+    /// Creates a mutable object and seals it right away, so readers can map
+    /// it. Its contents start zeroed at version 0 and are then updated with
+    /// `update` or `write_acquire`/`write_release`.
+    #[pyo3(signature = (oid, data_size, metadata_size=0))]
+    pub fn create_mutable(
+        &mut self,
+        oid: ObjectID,
+        data_size: u64,
+        metadata_size: u64,
+    ) -> PyResult<status::Status> {
+        self.check_local(&oid.0)?;
+        let request = messages::CreateRequest {
+            data_size,
+            metadata_size,
+            is_mutable: true,
+            ..Default::default()
+        };
+        let cr = self.create_(&oid.0, request)?;
+        if cr.error() != messages::Error::Ok {
+            return Ok(status::Status::from_store_error(cr.error(), &oid.0));
+        }
+        let spec = cr.plasma_object.unwrap_or_default();
+        self.segment(spec.segment_index, &cr.store_path)?;
+        let status = self.seal_(&oid.0)?;
+        if status.is_ok() {
+            self.mutable_objects
+                .insert(oid.0, MutableObject { spec, writer: true });
+        }
+        Ok(status)
+    }
+
+    /// Starts an update of a mutable object created by this client. Readers
+    /// retry until `write_release` publishes the new version. Returns the odd
+    /// version marking the write.
+    pub fn write_acquire(&mut self, oid: ObjectID) -> PyResult<u64> {
+        let (counter, object) = self.version_counter(&oid.0)?;
+        if !object.writer {
+            return Err(pyexceptions::PyPermissionError::new_err(format!(
+                "object {} was not created by this client",
+                oid.0
+            )));
+        }
+        counter
+            .write_acquire()
+            .map_err(|e| pyexceptions::PyRuntimeError::new_err(e.to_string()))
+    }
+
+    /// Publishes the update started by `write_acquire` and returns the new
+    /// version.
+    pub fn write_release(&mut self, oid: ObjectID) -> PyResult<u64> {
+        let (counter, _) = self.version_counter(&oid.0)?;
+        counter
+            .write_release()
+            .map_err(|e| pyexceptions::PyRuntimeError::new_err(e.to_string()))
+    }
+
+    /// Replaces the data of a mutable object created by this client and
+    /// returns the new version. `data` must fill the object exactly.
+    pub fn update(&mut self, oid: ObjectID, data: &[u8]) -> PyResult<u64> {
+        let spec = match self.mutable_objects.get(&oid.0) {
+            Some(object) => object.spec,
+            None => {
+                return Err(pyexceptions::PyKeyError::new_err(format!(
+                    "mutable object {} is not mapped",
+                    oid.0
+                )))
+            }
+        };
+        if data.len() as u64 != spec.data_size {
+            return Err(pyexceptions::PyValueError::new_err(format!(
+                "object {} holds {} bytes, got {}",
+                oid.0,
+                spec.data_size,
+                data.len()
+            )));
+        }
+        self.write_acquire(oid.clone())?;
+        let segment = self.segments.get_mut(&spec.segment_index).unwrap();
+        let offset = spec.data_offset as usize;
+        segment[offset..offset + data.len()].copy_from_slice(data);
+        self.write_release(oid)
+    }
+
+    /// Changes the data and metadata sizes of a mutable object created by
+    /// this client. Shrinking, or growing within the space allocated for the
+    /// object, happens in place. Growing beyond it needs `allow_reallocate`:
+    /// the object is then copied to a new allocation, keeping as much of its
+    /// contents as fits, and readers follow it there on their next read.
+    #[pyo3(signature = (oid, data_size, metadata_size=0, allow_reallocate=false))]
+    pub fn resize(
+        &mut self,
+        oid: ObjectID,
+        data_size: u64,
+        metadata_size: u64,
+        allow_reallocate: bool,
+    ) -> PyResult<status::Status> {
+        if !self.mutable_objects.get(&oid.0).is_some_and(|o| o.writer) {
+            return Err(pyexceptions::PyPermissionError::new_err(format!(
+                "object {} was not created by this client",
+                oid.0
+            )));
+        }
+        let request = Messages::ResizeRequest(messages::ResizeRequest {
             object_id: oid.0.binary(),
-            is_mutable: false,
             data_size,
             metadata_size,
-            device_num: 0,
-            try_immediately: false,
+            allow_reallocate,
         });
         self.send_request(request)?;
-        debug!("Sent CREATE request to the server");
+        debug!("Sent RESIZE request to the server");
+
+        let rr = match self.receive_response() {
+            Ok(Messages::ResizeResponse(rr)) => {
+                debug!("RESIZE response received {:?}", rr);
+                rr
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                return Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ));
+            }
+            Err(e) => return Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        };
+        if rr.error() != messages::Error::Ok {
+            return Ok(status::Status::from_store_error(rr.error(), &oid.0));
+        }
+        let spec = rr.plasma_object.unwrap_or_default();
+        self.segment(spec.segment_index, &rr.store_path)?;
+        if let Some(object) = self.mutable_objects.get_mut(&oid.0) {
+            object.spec = spec;
+        }
+        Ok(status::Status::ok())
+    }
+
+    /// Waits up to `timeout_ms` for no write to be in progress on a mutable
+    /// object and returns its current version. Anything read afterwards is
+    /// only a consistent snapshot if `read_release` with that version returns
+    /// true.
+    #[pyo3(signature = (oid, timeout_ms=0))]
+    pub fn read_acquire(&mut self, oid: ObjectID, timeout_ms: i64) -> PyResult<u64> {
+        self.map_mutable_(&oid.0, timeout_ms)?;
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64);
+        loop {
+            let (counter, object) = self.version_counter(&oid.0)?;
+            if let Some(version) = counter.read_acquire() {
+                let segment = &self.segments[&object.spec.segment_index];
+                if !read_header(segment, &object.spec).is_some_and(|h| h.moved) {
+                    return Ok(version);
+                }
+                self.remap_mutable_(&oid.0)?;
+                continue;
+            }
+            if Instant::now() >= deadline {
+                return Err(pyexceptions::PyTimeoutError::new_err(format!(
+                    "object {} is being written",
+                    oid.0
+                )));
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    pub fn read_release(&mut self, oid: ObjectID, version: u64) -> PyResult<bool> {
+        let (counter, _) = self.version_counter(&oid.0)?;
+        Ok(counter.read_release(version))
+    }
+
+    /// Copies a consistent snapshot of a mutable object's data, returning it
+    /// with its version.
+    #[pyo3(signature = (oid, timeout_ms=0))]
+    pub fn read_snapshot(
+        &mut self,
+        oid: ObjectID,
+        timeout_ms: i64,
+    ) -> PyResult<(u64, Cow<'static, [u8]>)> {
+        self.map_mutable_(&oid.0, timeout_ms)?;
+        let deadline = Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64);
+        loop {
+            let spec = &self.mutable_objects[&oid.0].spec;
+            match copy_snapshot(&self.segments[&spec.segment_index], spec, deadline) {
+                Snapshot::Copied(version, data) => return Ok((version, Cow::Owned(data))),
+                Snapshot::Moved => self.remap_mutable_(&oid.0)?,
+                Snapshot::Busy => {
+                    return Err(pyexceptions::PyTimeoutError::new_err(format!(
+                        "object {} is being written",
+                        oid.0
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Unmaps a mutable object, releasing the reference taken when it was
+    /// first read.
+    pub fn release(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        match self.mutable_objects.remove(&oid.0) {
+            Some(object) if !object.writer => self.release_(&oid.0),
+            Some(_) => Ok(status::Status::ok()),
+            None => Ok(status::Status::object_not_found(format!(
+                "object {} is not mapped",
+                oid.0
+            ))),
+        }
+    }
+
+    /// Keeps the store's copy of `oid` from being evicted when memory runs
+    /// out; it is spilled to disk instead, if the store can. Objects start
+    /// out pinned in the store they were created in.
+    pub fn pin(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        self.pin_(&oid.0, true)
+    }
+
+    /// Lets the store evict its copy of `oid` when it needs the memory.
+    pub fn unpin(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        self.pin_(&oid.0, false)
+    }
+
+    /// Takes a reference to the reference counted object `oid`, which the
+    /// connected store must hold. The object is kept on every store until
+    /// all references to it are removed.
+    pub fn add_reference(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        let request = Messages::AddReferenceRequest(messages::AddReferenceRequest {
+            object_id: oid.0.binary(),
+        });
+        debug!("Sending ADD_REFERENCE request to the server");
+        self.simple_request_(&oid.0, request)
+    }
+
+    /// Drops a reference taken with `add_reference`, or the one a client
+    /// holds on a reference counted object it created.
+    pub fn remove_reference(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        let request = Messages::RemoveReferenceRequest(messages::RemoveReferenceRequest {
+            object_id: oid.0.binary(),
+        });
+        debug!("Sending REMOVE_REFERENCE request to the server");
+        self.simple_request_(&oid.0, request)
+    }
+
+    /// Deletes the store's copy of the sealed object `oid`. Clients still
+    /// using it keep it until they release it, but it can no longer be
+    /// gotten. Copies on other stores are left alone.
+    pub fn delete(&mut self, oid: ObjectID) -> PyResult<status::Status> {
+        let request = Messages::DeleteRequest(messages::DeleteRequest {
+            object_id: oid.0.binary(),
+        });
+        debug!("Sending DELETE request to the server");
+        self.simple_request_(&oid.0, request)
+    }
+
+    /// Lists the stores of the cluster, the one this client is connected to
+    /// first. Empty if that store has no peers.
+    pub fn members(&mut self) -> PyResult<Vec<Member>> {
+        self.send_request(Messages::MembersRequest(messages::MembersRequest {}))?;
+        debug!("Sent MEMBERS request to the server");
 
         match self.receive_response() {
-            Ok(Messages::CreateResponse(cr)) => {
-                debug!("CREATE response received {:?}", cr);
-                Ok(status::Status::ok())
+            Ok(Messages::MembersResponse(mr)) => {
+                debug!("MEMBERS response received {:?}", mr);
+                Ok(mr
+                    .members
+                    .into_iter()
+                    .map(|m| Member {
+                        node_id: m.node_id,
+                        address: m.address,
+                        alive: m.alive,
+                        memory_capacity: m.memory_capacity,
+                        memory_used: m.memory_used,
+                        last_heartbeat_ms: m.last_heartbeat_ms,
+                    })
+                    .collect())
             }
             Ok(r) => {
                 debug!("Invalid response received {:?}", r);
@@ -196,7 +1334,207 @@ impl CrabClient {
                     "Invalid response received from sever",
                 ))
             }
-            Err(_) => Err(pyexceptions::PyConnectionError::new_err("")),
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    pub fn stats(&mut self) -> PyResult<Stats> {
+        self.send_request(Messages::StatsRequest(messages::StatsRequest {}))?;
+        debug!("Sent STATS request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::StatsResponse(sr)) => {
+                debug!("STATS response received {:?}", sr);
+                Ok(Stats {
+                    memory_capacity: sr.memory_capacity,
+                    memory_used: sr.memory_used,
+                    num_objects: sr.num_objects,
+                    num_spilled_objects: sr.num_spilled_objects,
+                    under_replicated: sr
+                        .under_replicated
+                        .into_iter()
+                        .map(|u| {
+                            let oid = ObjectID::from_binary(&u.object_id)?;
+                            Ok((oid, u.replicas, u.replication_factor))
+                        })
+                        .collect::<PyResult<_>>()?,
+                    namespaces: sr
+                        .namespaces
+                        .into_iter()
+                        .map(|n| {
+                            (
+                                n.namespace,
+                                n.used_bytes,
+                                n.num_objects,
+                                n.max_bytes,
+                                n.max_objects,
+                            )
+                        })
+                        .collect(),
+                })
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    /// Copies the sealed object `oid` from the connected store to the stores
+    /// at `nodes` (their TCP addresses). The store sends it to `fanout` of
+    /// them, each of which passes it on to its share of the rest, so a
+    /// fanout of 1 copies it along a chain. Returns once every node is done,
+    /// with the outcome for each.
+    #[pyo3(signature = (oid, nodes, fanout=2))]
+    pub fn push(
+        &mut self,
+        oid: ObjectID,
+        nodes: Vec<String>,
+        fanout: u32,
+    ) -> PyResult<HashMap<String, status::Status>> {
+        if fanout == 0 {
+            return Err(pyexceptions::PyValueError::new_err(
+                "fanout must be at least 1",
+            ));
+        }
+        let request = Messages::PushRequest(messages::PushRequest {
+            object_id: oid.0.binary(),
+            nodes,
+            fanout,
+            source: String::new(),
+        });
+        self.send_request(request)?;
+        debug!("Sent PUSH request to the server");
+
+        match self.receive_response() {
+            Ok(Messages::PushResponse(pr)) => {
+                debug!("PUSH response received {:?}", pr);
+                Ok(pr
+                    .results
+                    .into_iter()
+                    .map(|r| {
+                        let status = status::Status::from_store_error(r.error(), &oid.0);
+                        (r.node, status)
+                    })
+                    .collect())
+            }
+            Ok(r) => {
+                debug!("Invalid response received {:?}", r);
+                Err(pyexceptions::PyValueError::new_err(
+                    "Invalid response received from sever",
+                ))
+            }
+            Err(e) => Err(pyexceptions::PyConnectionError::new_err(e.to_string())),
+        }
+    }
+
+    /// Stores `data` under the id derived from its content and returns that
+    /// id. The upload is skipped when the store already holds the object.
+    pub fn put_content_addressed(&mut self, data: &[u8]) -> PyResult<ObjectID> {
+        let oid = ObjectID(ObjectId::from_content(data));
+        if self.contains(oid.clone())? {
+            debug!("{} already in the store, skipping upload", oid.0);
+            return Ok(oid);
+        }
+
+        // If someone else is uploading the same content right now, wait for
+        // their copy, which is byte-for-byte identical to ours.
+        let status = self.put(oid.clone(), data, true, 0, false)?;
+        if status.is_ok() || status.is_object_exists() {
+            Ok(oid)
+        } else {
+            Err(status.into())
+        }
+    }
+}
+
+// Splits the mapped object described by `spec` into its data and metadata.
+fn object_buffers<'a>(segment: &'a [u8], spec: &messages::ObjectSpec) -> (&'a [u8], &'a [u8]) {
+    let data = spec.data_offset as usize;
+    let metadata = spec.metadata_offset as usize;
+    (
+        &segment[data..data + spec.data_size as usize],
+        &segment[metadata..metadata + spec.metadata_size as usize],
+    )
+}
+
+fn read_header(segment: &[u8], spec: &messages::ObjectSpec) -> Option<header::ObjectHeader> {
+    header::ObjectHeader::read_from(&segment[spec.header_offset as usize..]).ok()
+}
+
+enum Snapshot {
+    Copied(u64, Vec<u8>),
+    // The object was resized into a new allocation.
+    Moved,
+    // A write was still in progress at the deadline.
+    Busy,
+}
+
+// Copies the data of a mutable object, retrying until the copy is not torn
+// by a concurrent write. Gives up once `deadline` passes.
+fn copy_snapshot(segment: &[u8], spec: &messages::ObjectSpec, deadline: Instant) -> Snapshot {
+    // SAFETY: the header lies inside `segment`, which outlives the counter,
+    // and allocations are 64 byte aligned.
+    let counter =
+        unsafe { VersionCounter::from_header(segment[spec.header_offset as usize..].as_ptr()) };
+    loop {
+        if let Some(version) = counter.read_acquire() {
+            // The writer may have resized the object since `spec` was
+            // handed out, so take the size from the header. It is only
+            // trusted once the version turns out unchanged.
+            let header = read_header(segment, spec);
+            let snapshot = match &header {
+                Some(header) if header.moved => Snapshot::Moved,
+                Some(header) => {
+                    let len = header.data_size.min(spec.allocated_size) as usize;
+                    let data = spec.data_offset as usize;
+                    Snapshot::Copied(version, segment[data..data + len].to_vec())
+                }
+                None => Snapshot::Copied(version, object_buffers(segment, spec).0.to_vec()),
+            };
+            if counter.read_release(version) {
+                return snapshot;
+            }
+        }
+        if Instant::now() >= deadline {
+            return Snapshot::Busy;
+        }
+        std::thread::yield_now();
+    }
+}
+
+// Checks that the header the store wrote in front of a gotten object matches
+// what we asked for, and optionally that its contents match the checksum.
+fn check_object(
+    segment: &[u8],
+    oid: &ObjectId,
+    spec: &messages::ObjectSpec,
+    verify_checksum: bool,
+) -> Result<(), status::Status> {
+    let header = header::ObjectHeader::read_from(&segment[spec.header_offset as usize..])
+        .map_err(|e| status::Status::io_error(format!("object {}: {}", oid, e)))?;
+    // Mutable objects can be resized by their writer at any time.
+    let sizes_match = spec.is_experimental_mutable_object
+        || (header.data_size == spec.data_size && header.metadata_size == spec.metadata_size);
+    if header.object_id != *oid || !sizes_match || !header.sealed {
+        return Err(status::Status::io_error(format!(
+            "object {}: header does not match the store's reply: {:?}",
+            oid, header
+        )));
+    }
+    if verify_checksum {
+        if let Some(expected) = header.checksum {
+            let (data, metadata) = object_buffers(segment, spec);
+            if header::checksum(data, metadata) != expected {
+                return Err(status::Status::io_error(format!(
+                    "object {}: checksum mismatch, the object was modified after seal",
+                    oid
+                )));
+            }
         }
     }
+    Ok(())
 }
@@ -1,3 +1,5 @@
+// pyo3's generated wrappers trip this lint on `PyResult` return types.
+#![allow(clippy::useless_conversion)]
 pub mod client;
 mod status;
 use pyo3::prelude::*;
@@ -6,5 +8,7 @@ use pyo3::prelude::*;
 fn crabstore_client(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<client::ObjectID>()?;
     m.add_class::<client::CrabClient>()?;
+    m.add_class::<client::Member>()?;
+    m.add_class::<client::Stats>()?;
     m.add_class::<status::Status>()
 }
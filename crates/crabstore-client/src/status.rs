@@ -1,3 +1,6 @@
+use crabstore_common::messages::messages;
+use crabstore_common::objectid::ObjectId;
+use pyo3::exceptions as pyexceptions;
 use pyo3::prelude::*;
 use std::fmt;
 
@@ -12,6 +15,10 @@ pub enum StatusCode {
     Invalid,
     IOError,
     InvalidArgument,
+    ObjectExists,
+    ObjectNotFound,
+    ObjectAlreadySealed,
+    QuotaExceeded,
 }
 
 #[pyclass]
@@ -60,6 +67,64 @@ impl Status {
         Status::from_error(StatusCode::TypeError, msg, -1)
     }
 
+    pub fn io_error(msg: String) -> Self {
+        Status::from_error(StatusCode::IOError, msg, -1)
+    }
+
+    pub fn object_exists(msg: String) -> Self {
+        Status::from_error(StatusCode::ObjectExists, msg, -1)
+    }
+
+    pub fn object_not_found(msg: String) -> Self {
+        Status::from_error(StatusCode::ObjectNotFound, msg, -1)
+    }
+
+    pub fn object_already_sealed(msg: String) -> Self {
+        Status::from_error(StatusCode::ObjectAlreadySealed, msg, -1)
+    }
+
+    // Translate an error code sent by the store for an operation on `oid`.
+    pub fn from_store_error(error: messages::Error, oid: &ObjectId) -> Self {
+        match error {
+            messages::Error::Ok => Status::ok(),
+            messages::Error::ObjectExists => {
+                Status::object_exists(format!("object {} already exists", oid))
+            }
+            messages::Error::ObjectNonexistent => {
+                Status::object_not_found(format!("object {} does not exist", oid))
+            }
+            messages::Error::OutOfMemory => {
+                Status::out_of_memory(format!("not enough memory to create {}", oid))
+            }
+            messages::Error::QuotaExceeded => Status::from_error(
+                StatusCode::QuotaExceeded,
+                format!("creating {} would exceed the quota of the namespace", oid),
+                -1,
+            ),
+            messages::Error::ObjectSealed => {
+                Status::object_already_sealed(format!("object {} is already sealed", oid))
+            }
+            messages::Error::InvalidRequest => Status::from_error(
+                StatusCode::InvalidArgument,
+                format!("invalid request for object {}", oid),
+                -1,
+            ),
+            messages::Error::NodeUnreachable => {
+                Status::io_error(format!("could not reach a store holding {}", oid))
+            }
+            messages::Error::OutOfDisk => Status::from_error(
+                StatusCode::OutOfDisk,
+                format!("not enough disk space to create {}", oid),
+                -1,
+            ),
+            e => Status::from_error(
+                StatusCode::Invalid,
+                format!("{} failed: {}", oid, e.as_str_name()),
+                -1,
+            ),
+        }
+    }
+
     pub fn is_ok(&self) -> bool {
         self.state.is_none()
     }
@@ -68,6 +133,10 @@ impl Status {
         self.code() == StatusCode::OutOfMemory
     }
 
+    pub fn is_quota_exceeded(&self) -> bool {
+        self.code() == StatusCode::QuotaExceeded
+    }
+
     pub fn is_key_error(&self) -> bool {
         self.code() == StatusCode::KeyError
     }
@@ -80,6 +149,14 @@ impl Status {
         self.code() == StatusCode::TypeError
     }
 
+    pub fn is_object_exists(&self) -> bool {
+        self.code() == StatusCode::ObjectExists
+    }
+
+    pub fn is_object_not_found(&self) -> bool {
+        self.code() == StatusCode::ObjectNotFound
+    }
+
     pub fn code(&self) -> StatusCode {
         self.state
             .as_ref()
@@ -105,6 +182,10 @@ impl Status {
             StatusCode::Invalid => "Invalid".to_string(),
             StatusCode::IOError => "IOError".to_string(),
             StatusCode::InvalidArgument => "InvalidArgument".to_string(),
+            StatusCode::ObjectExists => "ObjectExists".to_string(),
+            StatusCode::ObjectNotFound => "ObjectNotFound".to_string(),
+            StatusCode::ObjectAlreadySealed => "ObjectAlreadySealed".to_string(),
+            StatusCode::QuotaExceeded => "QuotaExceeded".to_string(),
             // Add more cases here as needed
         }
     }
@@ -118,3 +199,20 @@ impl fmt::Display for Status {
         }
     }
 }
+
+impl From<Status> for PyErr {
+    fn from(status: Status) -> PyErr {
+        let msg = status.to_string();
+        match status.code() {
+            StatusCode::OutOfMemory | StatusCode::QuotaExceeded => {
+                pyexceptions::PyMemoryError::new_err(msg)
+            }
+            StatusCode::KeyError | StatusCode::ObjectNotFound => {
+                pyexceptions::PyKeyError::new_err(msg)
+            }
+            StatusCode::TypeError => pyexceptions::PyTypeError::new_err(msg),
+            StatusCode::IOError | StatusCode::OutOfDisk => pyexceptions::PyIOError::new_err(msg),
+            _ => pyexceptions::PyValueError::new_err(msg),
+        }
+    }
+}
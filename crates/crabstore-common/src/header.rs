@@ -0,0 +1,349 @@
+use std::fmt;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+
+use crate::objectid::{ObjectId, UNIQUE_ID_SIZE};
+
+// Every allocation in a store segment starts with a fixed size header that is
+// written by the store. Clients read it to check that what they mapped is the
+// object they asked for.
+//
+// Layout (all integers little endian):
+//
+//   0  magic            u32
+//   4  header_version   u16
+//   6  flags            u16
+//   8  object_id        [u8; 20]
+//  32  data_size        u64
+//  40  metadata_size    u64
+//  48  create_time_us   u64   microseconds since the Unix epoch
+//  56  version          u64   write counter of mutable objects, see below
+//  64  checksum         [u8; 32]  BLAKE3 of data followed by metadata
+//  96  reserved         [u8; 32]
+
+pub const HEADER_MAGIC: u32 = u32::from_le_bytes(*b"CRAB");
+pub const HEADER_VERSION: u16 = 1;
+pub const HEADER_SIZE: u64 = 128;
+pub const CHECKSUM_SIZE: usize = 32;
+
+const FLAG_SEALED: u16 = 1;
+const FLAG_HAS_CHECKSUM: u16 = 1 << 1;
+const FLAG_MUTABLE: u16 = 1 << 2;
+const FLAG_MOVED: u16 = 1 << 3;
+const VERSION_OFFSET: usize = 56;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectHeader {
+    pub object_id: ObjectId,
+    pub data_size: u64,
+    pub metadata_size: u64,
+    pub create_time_us: u64,
+    pub sealed: bool,
+    pub mutable: bool,
+    // Set on the old copy when a mutable object is reallocated, so readers
+    // know to fetch the new location from the store.
+    pub moved: bool,
+    pub version: u64,
+    pub checksum: Option<[u8; CHECKSUM_SIZE]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    TooShort(usize),
+    BadMagic(u32),
+    UnsupportedVersion(u16),
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::TooShort(len) => {
+                write!(f, "object header needs {} bytes, got {}", HEADER_SIZE, len)
+            }
+            HeaderError::BadMagic(magic) => write!(f, "bad object header magic {:#010x}", magic),
+            HeaderError::UnsupportedVersion(version) => {
+                write!(f, "unsupported object header version {}", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HeaderError {}
+
+impl ObjectHeader {
+    pub fn new(object_id: ObjectId, data_size: u64, metadata_size: u64) -> Self {
+        let create_time_us = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as u64);
+        ObjectHeader {
+            object_id,
+            data_size,
+            metadata_size,
+            create_time_us,
+            sealed: false,
+            mutable: false,
+            moved: false,
+            version: 0,
+            checksum: None,
+        }
+    }
+
+    /// Serializes the header into a fresh allocation, including the version
+    /// counter.
+    pub fn init(&self, buf: &mut [u8]) {
+        buf[VERSION_OFFSET..VERSION_OFFSET + 8].copy_from_slice(&self.version.to_le_bytes());
+        self.write_to(buf);
+    }
+
+    /// Serializes the header into the first `HEADER_SIZE` bytes of `buf`,
+    /// leaving the version counter alone: once clients can see the object it
+    /// is only accessed through `VersionCounter`.
+    pub fn write_to(&self, buf: &mut [u8]) {
+        let buf = &mut buf[..HEADER_SIZE as usize];
+        buf[..VERSION_OFFSET].fill(0);
+        buf[VERSION_OFFSET + 8..].fill(0);
+
+        let mut flags = 0;
+        if self.sealed {
+            flags |= FLAG_SEALED;
+        }
+        if self.checksum.is_some() {
+            flags |= FLAG_HAS_CHECKSUM;
+        }
+        if self.mutable {
+            flags |= FLAG_MUTABLE;
+        }
+        if self.moved {
+            flags |= FLAG_MOVED;
+        }
+        buf[0..4].copy_from_slice(&HEADER_MAGIC.to_le_bytes());
+        buf[4..6].copy_from_slice(&HEADER_VERSION.to_le_bytes());
+        buf[6..8].copy_from_slice(&flags.to_le_bytes());
+        buf[8..8 + UNIQUE_ID_SIZE].copy_from_slice(self.object_id.data());
+        buf[32..40].copy_from_slice(&self.data_size.to_le_bytes());
+        buf[40..48].copy_from_slice(&self.metadata_size.to_le_bytes());
+        buf[48..56].copy_from_slice(&self.create_time_us.to_le_bytes());
+        if let Some(checksum) = &self.checksum {
+            buf[64..64 + CHECKSUM_SIZE].copy_from_slice(checksum);
+        }
+    }
+
+    pub fn read_from(buf: &[u8]) -> Result<Self, HeaderError> {
+        if buf.len() < HEADER_SIZE as usize {
+            return Err(HeaderError::TooShort(buf.len()));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes(buf[i..i + 2].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(buf[i..i + 8].try_into().unwrap());
+
+        let magic = u32::from_le_bytes(buf[0..4].try_into().unwrap());
+        if magic != HEADER_MAGIC {
+            return Err(HeaderError::BadMagic(magic));
+        }
+        let version = u16_at(4);
+        if version != HEADER_VERSION {
+            return Err(HeaderError::UnsupportedVersion(version));
+        }
+        let flags = u16_at(6);
+        let checksum = (flags & FLAG_HAS_CHECKSUM != 0)
+            .then(|| buf[64..64 + CHECKSUM_SIZE].try_into().unwrap());
+
+        Ok(ObjectHeader {
+            object_id: ObjectId::try_from_binary(&buf[8..8 + UNIQUE_ID_SIZE]).unwrap(),
+            data_size: u64_at(32),
+            metadata_size: u64_at(40),
+            create_time_us: u64_at(48),
+            sealed: flags & FLAG_SEALED != 0,
+            mutable: flags & FLAG_MUTABLE != 0,
+            moved: flags & FLAG_MOVED != 0,
+            version: u64_at(VERSION_OFFSET),
+            checksum,
+        })
+    }
+}
+
+/// Checksum stored in the header of a sealed object.
+pub fn checksum(data: &[u8], metadata: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(data);
+    hasher.update(metadata);
+    *hasher.finalize().as_bytes()
+}
+
+// Mutable objects are updated in place by a single writer while readers may
+// be copying them. The version counter in the header works like a seqlock:
+// it is even while the contents are stable and odd while a write is in
+// progress. A reader's copy is a consistent snapshot if the counter was even
+// and unchanged before and after the copy.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionError {
+    /// Another write holds the object, at this (odd) version.
+    WriteInProgress(u64),
+    /// The caller does not hold a write at this version.
+    NotWriting(u64),
+}
+
+impl fmt::Display for VersionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionError::WriteInProgress(version) => {
+                write!(f, "a write is already in progress (version {})", version)
+            }
+            VersionError::NotWriting(version) => {
+                write!(f, "no write in progress (version {})", version)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VersionError {}
+
+/// The version counter inside a mapped object header.
+pub struct VersionCounter<'a>(&'a AtomicU64);
+
+impl<'a> VersionCounter<'a> {
+    /// # Safety
+    ///
+    /// `header` must point to an object header of at least `HEADER_SIZE`
+    /// bytes, aligned to 8 bytes, that stays mapped for `'a`. Other processes
+    /// may only access the counter atomically.
+    pub unsafe fn from_header(header: *const u8) -> Self {
+        let counter = header.add(VERSION_OFFSET) as *mut u64;
+        VersionCounter(AtomicU64::from_ptr(counter))
+    }
+
+    pub fn load(&self) -> u64 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Starts a write, returning the odd version that marks it.
+    pub fn write_acquire(&self) -> Result<u64, VersionError> {
+        let current = self.0.load(Ordering::Relaxed);
+        if current % 2 == 1 {
+            return Err(VersionError::WriteInProgress(current));
+        }
+        self.0
+            .compare_exchange(current, current + 1, Ordering::Acquire, Ordering::Relaxed)
+            .map_err(VersionError::WriteInProgress)?;
+        // Keep the data writes from being reordered before the counter.
+        fence(Ordering::Release);
+        Ok(current + 1)
+    }
+
+    /// Publishes the write started by `write_acquire`, returning the new
+    /// stable version.
+    pub fn write_release(&self) -> Result<u64, VersionError> {
+        let current = self.0.load(Ordering::Relaxed);
+        if current % 2 == 0 {
+            return Err(VersionError::NotWriting(current));
+        }
+        self.0.store(current + 1, Ordering::Release);
+        Ok(current + 1)
+    }
+
+    /// Returns the current version if no write is in progress.
+    pub fn read_acquire(&self) -> Option<u64> {
+        let version = self.0.load(Ordering::Acquire);
+        (version % 2 == 0).then_some(version)
+    }
+
+    /// Whether everything read since `read_acquire` returned `version` is a
+    /// consistent snapshot of that version.
+    pub fn read_release(&self, version: u64) -> bool {
+        fence(Ordering::Acquire);
+        self.0.load(Ordering::Relaxed) == version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An allocation's worth of header, as aligned as in a store segment.
+    #[repr(align(64))]
+    struct Buffer([u8; HEADER_SIZE as usize]);
+
+    fn header() -> ObjectHeader {
+        let mut header = ObjectHeader::new(ObjectId::random(), 1000, 24);
+        header.sealed = true;
+        header.version = 6;
+        header.checksum = Some(checksum(b"data", b"metadata"));
+        header
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = Buffer([0xff; HEADER_SIZE as usize]);
+        let header = header();
+        header.init(&mut buffer.0);
+        assert_eq!(ObjectHeader::read_from(&buffer.0), Ok(header.clone()));
+        assert_eq!(&buffer.0[96..], &[0; 32]);
+
+        let mut header = ObjectHeader::new(ObjectId::random(), 0, 0);
+        header.mutable = true;
+        header.moved = true;
+        header.init(&mut buffer.0);
+        assert_eq!(ObjectHeader::read_from(&buffer.0), Ok(header));
+    }
+
+    #[test]
+    fn write_to_keeps_the_version() {
+        let mut buffer = Buffer([0; HEADER_SIZE as usize]);
+        let mut header = header();
+        header.init(&mut buffer.0);
+        header.version = 100;
+        header.data_size = 2000;
+        header.write_to(&mut buffer.0);
+        let read = ObjectHeader::read_from(&buffer.0).unwrap();
+        assert_eq!((read.version, read.data_size), (6, 2000));
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let mut buffer = Buffer([0; HEADER_SIZE as usize]);
+        header().init(&mut buffer.0);
+        assert_eq!(
+            ObjectHeader::read_from(&buffer.0[..HEADER_SIZE as usize - 1]),
+            Err(HeaderError::TooShort(HEADER_SIZE as usize - 1))
+        );
+        assert_eq!(ObjectHeader::read_from(&[]), Err(HeaderError::TooShort(0)));
+
+        let mut bad = Buffer(buffer.0);
+        bad.0[4..6].copy_from_slice(&(HEADER_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            ObjectHeader::read_from(&bad.0),
+            Err(HeaderError::UnsupportedVersion(HEADER_VERSION + 1))
+        );
+        bad.0[0..4].copy_from_slice(b"BARC");
+        assert_eq!(
+            ObjectHeader::read_from(&bad.0),
+            Err(HeaderError::BadMagic(u32::from_le_bytes(*b"BARC")))
+        );
+    }
+
+    #[test]
+    fn version_counter() {
+        let mut buffer = Buffer([0; HEADER_SIZE as usize]);
+        header().init(&mut buffer.0);
+        // SAFETY: the buffer is aligned and outlives the counter.
+        let counter = unsafe { VersionCounter::from_header(buffer.0.as_ptr()) };
+        assert_eq!(counter.load(), 6);
+        assert_eq!(counter.write_release(), Err(VersionError::NotWriting(6)));
+
+        let version = counter.read_acquire().unwrap();
+        assert_eq!(version, 6);
+        assert!(counter.read_release(version));
+
+        assert_eq!(counter.write_acquire(), Ok(7));
+        assert_eq!(
+            counter.write_acquire(),
+            Err(VersionError::WriteInProgress(7))
+        );
+        assert_eq!(counter.read_acquire(), None);
+        // A read that started before the write saw a torn copy.
+        assert!(!counter.read_release(version));
+        assert_eq!(counter.write_release(), Ok(8));
+
+        assert_eq!(counter.read_acquire(), Some(8));
+        assert!(counter.read_release(8));
+    }
+}
@@ -1,9 +1,11 @@
 use prost::bytes::{Buf, BufMut};
 use prost::Message;
+use std::fmt;
 use std::io;
 use tokio_util::codec::{Decoder, Encoder};
 
 // Include the `items` module, which is generated from items.proto.
+#[allow(clippy::module_inception)]
 pub mod messages {
     include!(concat!(env!("OUT_DIR"), "/message.rs"));
 }
@@ -14,6 +16,57 @@ enum MessageType {
     ConnectResponseMT = 1,
     CreateRequestMT = 2,
     CreateResponseMT = 3,
+    SealRequestMT = 4,
+    SealResponseMT = 5,
+    ContainsRequestMT = 6,
+    ContainsResponseMT = 7,
+    GetRequestMT = 8,
+    GetResponseMT = 9,
+    ReleaseRequestMT = 10,
+    ReleaseResponseMT = 11,
+    ResizeRequestMT = 12,
+    ResizeResponseMT = 13,
+    PutRequestMT = 14,
+    PutChunkMT = 15,
+    PutResponseMT = 16,
+    FetchRequestMT = 17,
+    FetchResponseMT = 18,
+    DirectoryUpdateRequestMT = 19,
+    DirectoryUpdateResponseMT = 20,
+    LocateRequestMT = 21,
+    LocateResponseMT = 22,
+    HeartbeatRequestMT = 23,
+    HeartbeatResponseMT = 24,
+    MembersRequestMT = 25,
+    MembersResponseMT = 26,
+    PushRequestMT = 27,
+    PushResponseMT = 28,
+    StatsRequestMT = 29,
+    StatsResponseMT = 30,
+    PinRequestMT = 31,
+    PinResponseMT = 32,
+    UnpinRequestMT = 33,
+    UnpinResponseMT = 34,
+    AddReferenceRequestMT = 35,
+    AddReferenceResponseMT = 36,
+    RemoveReferenceRequestMT = 37,
+    RemoveReferenceResponseMT = 38,
+    ReferenceUpdateRequestMT = 39,
+    ReferenceUpdateResponseMT = 40,
+    DeleteRequestMT = 41,
+    DeleteResponseMT = 42,
+    WriteChunkRequestMT = 43,
+    WriteChunkResponseMT = 44,
+    ReadChunkRequestMT = 45,
+    ReadChunkResponseMT = 46,
+    ShutdownNoticeMT = 47,
+    ErrorResponseMT = 48,
+    ListRequestMT = 49,
+    ListResponseMT = 50,
+    InspectRequestMT = 51,
+    InspectResponseMT = 52,
+    EvictRequestMT = 53,
+    EvictResponseMT = 54,
 }
 
 #[derive(Debug)]
@@ -22,8 +75,219 @@ pub enum Messages {
     ConnectResponse(messages::ConnectResponse),
     CreateRequest(messages::CreateRequest),
     CreateResponse(messages::CreateResponse),
+    SealRequest(messages::SealRequest),
+    SealResponse(messages::SealResponse),
+    ContainsRequest(messages::ContainsRequest),
+    ContainsResponse(messages::ContainsResponse),
+    GetRequest(messages::GetRequest),
+    GetResponse(messages::GetResponse),
+    ReleaseRequest(messages::ReleaseRequest),
+    ReleaseResponse(messages::ReleaseResponse),
+    ResizeRequest(messages::ResizeRequest),
+    ResizeResponse(messages::ResizeResponse),
+    PutRequest(messages::PutRequest),
+    PutChunk(messages::PutChunk),
+    PutResponse(messages::PutResponse),
+    FetchRequest(messages::FetchRequest),
+    FetchResponse(messages::FetchResponse),
+    DirectoryUpdateRequest(messages::DirectoryUpdateRequest),
+    DirectoryUpdateResponse(messages::DirectoryUpdateResponse),
+    LocateRequest(messages::LocateRequest),
+    LocateResponse(messages::LocateResponse),
+    HeartbeatRequest(messages::HeartbeatRequest),
+    HeartbeatResponse(messages::HeartbeatResponse),
+    MembersRequest(messages::MembersRequest),
+    MembersResponse(messages::MembersResponse),
+    PushRequest(messages::PushRequest),
+    PushResponse(messages::PushResponse),
+    StatsRequest(messages::StatsRequest),
+    StatsResponse(messages::StatsResponse),
+    PinRequest(messages::PinRequest),
+    PinResponse(messages::PinResponse),
+    UnpinRequest(messages::UnpinRequest),
+    UnpinResponse(messages::UnpinResponse),
+    AddReferenceRequest(messages::AddReferenceRequest),
+    AddReferenceResponse(messages::AddReferenceResponse),
+    RemoveReferenceRequest(messages::RemoveReferenceRequest),
+    RemoveReferenceResponse(messages::RemoveReferenceResponse),
+    ReferenceUpdateRequest(messages::ReferenceUpdateRequest),
+    ReferenceUpdateResponse(messages::ReferenceUpdateResponse),
+    DeleteRequest(messages::DeleteRequest),
+    DeleteResponse(messages::DeleteResponse),
+    WriteChunkRequest(messages::WriteChunkRequest),
+    WriteChunkResponse(messages::WriteChunkResponse),
+    ReadChunkRequest(messages::ReadChunkRequest),
+    ReadChunkResponse(messages::ReadChunkResponse),
+    ShutdownNotice(messages::ShutdownNotice),
+    ErrorResponse(messages::ErrorResponse),
+    ListRequest(messages::ListRequest),
+    ListResponse(messages::ListResponse),
+    InspectRequest(messages::InspectRequest),
+    InspectResponse(messages::InspectResponse),
+    EvictRequest(messages::EvictRequest),
+    EvictResponse(messages::EvictResponse),
+    // Not sent over the wire: what the codec makes of a frame it could not
+    // decode.
+    Malformed(MalformedFrame),
 }
 
+impl Messages {
+    /// The type of the message, as in its frame header.
+    pub fn message_type(&self) -> u16 {
+        match self {
+            Messages::ConnectRequest(_) => MessageType::ConnectRequestMT as u16,
+            Messages::ConnectResponse(_) => MessageType::ConnectResponseMT as u16,
+            Messages::CreateRequest(_) => MessageType::CreateRequestMT as u16,
+            Messages::CreateResponse(_) => MessageType::CreateResponseMT as u16,
+            Messages::SealRequest(_) => MessageType::SealRequestMT as u16,
+            Messages::SealResponse(_) => MessageType::SealResponseMT as u16,
+            Messages::ContainsRequest(_) => MessageType::ContainsRequestMT as u16,
+            Messages::ContainsResponse(_) => MessageType::ContainsResponseMT as u16,
+            Messages::GetRequest(_) => MessageType::GetRequestMT as u16,
+            Messages::GetResponse(_) => MessageType::GetResponseMT as u16,
+            Messages::ReleaseRequest(_) => MessageType::ReleaseRequestMT as u16,
+            Messages::ReleaseResponse(_) => MessageType::ReleaseResponseMT as u16,
+            Messages::ResizeRequest(_) => MessageType::ResizeRequestMT as u16,
+            Messages::ResizeResponse(_) => MessageType::ResizeResponseMT as u16,
+            Messages::PutRequest(_) => MessageType::PutRequestMT as u16,
+            Messages::PutChunk(_) => MessageType::PutChunkMT as u16,
+            Messages::PutResponse(_) => MessageType::PutResponseMT as u16,
+            Messages::FetchRequest(_) => MessageType::FetchRequestMT as u16,
+            Messages::FetchResponse(_) => MessageType::FetchResponseMT as u16,
+            Messages::DirectoryUpdateRequest(_) => MessageType::DirectoryUpdateRequestMT as u16,
+            Messages::DirectoryUpdateResponse(_) => MessageType::DirectoryUpdateResponseMT as u16,
+            Messages::LocateRequest(_) => MessageType::LocateRequestMT as u16,
+            Messages::LocateResponse(_) => MessageType::LocateResponseMT as u16,
+            Messages::HeartbeatRequest(_) => MessageType::HeartbeatRequestMT as u16,
+            Messages::HeartbeatResponse(_) => MessageType::HeartbeatResponseMT as u16,
+            Messages::MembersRequest(_) => MessageType::MembersRequestMT as u16,
+            Messages::MembersResponse(_) => MessageType::MembersResponseMT as u16,
+            Messages::PushRequest(_) => MessageType::PushRequestMT as u16,
+            Messages::PushResponse(_) => MessageType::PushResponseMT as u16,
+            Messages::StatsRequest(_) => MessageType::StatsRequestMT as u16,
+            Messages::StatsResponse(_) => MessageType::StatsResponseMT as u16,
+            Messages::PinRequest(_) => MessageType::PinRequestMT as u16,
+            Messages::PinResponse(_) => MessageType::PinResponseMT as u16,
+            Messages::UnpinRequest(_) => MessageType::UnpinRequestMT as u16,
+            Messages::UnpinResponse(_) => MessageType::UnpinResponseMT as u16,
+            Messages::AddReferenceRequest(_) => MessageType::AddReferenceRequestMT as u16,
+            Messages::AddReferenceResponse(_) => MessageType::AddReferenceResponseMT as u16,
+            Messages::RemoveReferenceRequest(_) => MessageType::RemoveReferenceRequestMT as u16,
+            Messages::RemoveReferenceResponse(_) => MessageType::RemoveReferenceResponseMT as u16,
+            Messages::ReferenceUpdateRequest(_) => MessageType::ReferenceUpdateRequestMT as u16,
+            Messages::ReferenceUpdateResponse(_) => MessageType::ReferenceUpdateResponseMT as u16,
+            Messages::DeleteRequest(_) => MessageType::DeleteRequestMT as u16,
+            Messages::DeleteResponse(_) => MessageType::DeleteResponseMT as u16,
+            Messages::WriteChunkRequest(_) => MessageType::WriteChunkRequestMT as u16,
+            Messages::WriteChunkResponse(_) => MessageType::WriteChunkResponseMT as u16,
+            Messages::ReadChunkRequest(_) => MessageType::ReadChunkRequestMT as u16,
+            Messages::ReadChunkResponse(_) => MessageType::ReadChunkResponseMT as u16,
+            Messages::ShutdownNotice(_) => MessageType::ShutdownNoticeMT as u16,
+            Messages::ErrorResponse(_) => MessageType::ErrorResponseMT as u16,
+            Messages::ListRequest(_) => MessageType::ListRequestMT as u16,
+            Messages::ListResponse(_) => MessageType::ListResponseMT as u16,
+            Messages::InspectRequest(_) => MessageType::InspectRequestMT as u16,
+            Messages::InspectResponse(_) => MessageType::InspectResponseMT as u16,
+            Messages::EvictRequest(_) => MessageType::EvictRequestMT as u16,
+            Messages::EvictResponse(_) => MessageType::EvictResponseMT as u16,
+            Messages::Malformed(frame) => frame.message_type,
+        }
+    }
+}
+
+/// The name of the message of type `message_type`, if there is one.
+pub fn message_name(message_type: u16) -> Option<&'static str> {
+    match message_type {
+        0 => Some("ConnectRequest"),
+        1 => Some("ConnectResponse"),
+        2 => Some("CreateRequest"),
+        3 => Some("CreateResponse"),
+        4 => Some("SealRequest"),
+        5 => Some("SealResponse"),
+        6 => Some("ContainsRequest"),
+        7 => Some("ContainsResponse"),
+        8 => Some("GetRequest"),
+        9 => Some("GetResponse"),
+        10 => Some("ReleaseRequest"),
+        11 => Some("ReleaseResponse"),
+        12 => Some("ResizeRequest"),
+        13 => Some("ResizeResponse"),
+        14 => Some("PutRequest"),
+        15 => Some("PutChunk"),
+        16 => Some("PutResponse"),
+        17 => Some("FetchRequest"),
+        18 => Some("FetchResponse"),
+        19 => Some("DirectoryUpdateRequest"),
+        20 => Some("DirectoryUpdateResponse"),
+        21 => Some("LocateRequest"),
+        22 => Some("LocateResponse"),
+        23 => Some("HeartbeatRequest"),
+        24 => Some("HeartbeatResponse"),
+        25 => Some("MembersRequest"),
+        26 => Some("MembersResponse"),
+        27 => Some("PushRequest"),
+        28 => Some("PushResponse"),
+        29 => Some("StatsRequest"),
+        30 => Some("StatsResponse"),
+        31 => Some("PinRequest"),
+        32 => Some("PinResponse"),
+        33 => Some("UnpinRequest"),
+        34 => Some("UnpinResponse"),
+        35 => Some("AddReferenceRequest"),
+        36 => Some("AddReferenceResponse"),
+        37 => Some("RemoveReferenceRequest"),
+        38 => Some("RemoveReferenceResponse"),
+        39 => Some("ReferenceUpdateRequest"),
+        40 => Some("ReferenceUpdateResponse"),
+        41 => Some("DeleteRequest"),
+        42 => Some("DeleteResponse"),
+        43 => Some("WriteChunkRequest"),
+        44 => Some("WriteChunkResponse"),
+        45 => Some("ReadChunkRequest"),
+        46 => Some("ReadChunkResponse"),
+        47 => Some("ShutdownNotice"),
+        48 => Some("ErrorResponse"),
+        49 => Some("ListRequest"),
+        50 => Some("ListResponse"),
+        51 => Some("InspectRequest"),
+        52 => Some("InspectResponse"),
+        53 => Some("EvictRequest"),
+        54 => Some("EvictResponse"),
+        _ => None,
+    }
+}
+
+/// A frame whose body did not decode as its message type, or whose type is
+/// unknown. It was read whole, so the frames after it can still be decoded.
+#[derive(Debug)]
+pub struct MalformedFrame {
+    pub message_type: u16,
+    pub reason: String,
+}
+
+impl MalformedFrame {
+    fn new(message_type: u16, reason: impl ToString) -> Self {
+        MalformedFrame {
+            message_type,
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MalformedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "malformed frame of type {}: {}",
+            self.message_type, self.reason
+        )
+    }
+}
+
+/// The largest frame body the codec accepts. Object data travels in chunks
+/// well below it.
+pub const MAX_FRAME_SIZE: u64 = 128 << 20;
+
 pub struct MessageCodec;
 
 impl Decoder for MessageCodec {
@@ -39,50 +303,420 @@ impl Decoder for MessageCodec {
             return Ok(None); // Not enough data yet, return None to indicate we need more data
         }
 
+        // Wait until the whole frame has arrived, otherwise the body decode
+        // below would run over a partial message.
+        let message_size = u64::from_le_bytes(src[2..10].try_into().unwrap());
+        if message_size > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "frame of {} bytes is larger than the limit of {}",
+                    message_size, MAX_FRAME_SIZE
+                ),
+            ));
+        }
+        let message_size = message_size as usize;
+        if src.len() < 10 + message_size {
+            src.reserve(10 + message_size - src.len());
+            return Ok(None);
+        }
+
         // Read the message type from the buffer
         let message_type = src.get_u16_le();
-        let _message_size = src.get_u64_le();
+        src.advance(8);
+        let src = &mut src.split_to(message_size);
 
         // Based on the message type, decode the appropriate Protobuf message
         let message = match message_type {
             0 => {
                 let cr = messages::ConnectRequest::decode(src);
                 match cr {
-                    Ok(cr) => Some(Messages::ConnectRequest(cr)),
-                    Err(_) => None,
+                    Ok(cr) => Messages::ConnectRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
                 }
             }
             1 => {
                 let cr = messages::ConnectResponse::decode(src);
                 match cr {
-                    Ok(cr) => Some(Messages::ConnectResponse(cr)),
-                    Err(_) => None,
+                    Ok(cr) => Messages::ConnectResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
                 }
             }
             2 => {
                 let cr = messages::CreateRequest::decode(src);
                 match cr {
-                    Ok(cr) => Some(Messages::CreateRequest(cr)),
-                    Err(_) => None,
+                    Ok(cr) => Messages::CreateRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
                 }
             }
             3 => {
                 let cr = messages::CreateResponse::decode(src);
                 match cr {
-                    Ok(cr) => Some(Messages::CreateResponse(cr)),
-                    Err(_) => None,
+                    Ok(cr) => Messages::CreateResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            4 => {
+                let cr = messages::SealRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::SealRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            5 => {
+                let cr = messages::SealResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::SealResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            6 => {
+                let cr = messages::ContainsRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ContainsRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            7 => {
+                let cr = messages::ContainsResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ContainsResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            8 => {
+                let cr = messages::GetRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::GetRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            9 => {
+                let cr = messages::GetResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::GetResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            10 => {
+                let cr = messages::ReleaseRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ReleaseRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            11 => {
+                let cr = messages::ReleaseResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ReleaseResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            12 => {
+                let cr = messages::ResizeRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ResizeRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            13 => {
+                let cr = messages::ResizeResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ResizeResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            14 => {
+                let cr = messages::PutRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PutRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            15 => {
+                let cr = messages::PutChunk::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PutChunk(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            16 => {
+                let cr = messages::PutResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PutResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            17 => {
+                let cr = messages::FetchRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::FetchRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            18 => {
+                let cr = messages::FetchResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::FetchResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            19 => {
+                let cr = messages::DirectoryUpdateRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::DirectoryUpdateRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            20 => {
+                let cr = messages::DirectoryUpdateResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::DirectoryUpdateResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            21 => {
+                let cr = messages::LocateRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::LocateRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            22 => {
+                let cr = messages::LocateResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::LocateResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            23 => {
+                let cr = messages::HeartbeatRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::HeartbeatRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            24 => {
+                let cr = messages::HeartbeatResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::HeartbeatResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            25 => {
+                let cr = messages::MembersRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::MembersRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            26 => {
+                let cr = messages::MembersResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::MembersResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            27 => {
+                let cr = messages::PushRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PushRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            28 => {
+                let cr = messages::PushResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PushResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            29 => {
+                let cr = messages::StatsRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::StatsRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            30 => {
+                let cr = messages::StatsResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::StatsResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            31 => {
+                let cr = messages::PinRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PinRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            32 => {
+                let cr = messages::PinResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::PinResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            33 => {
+                let cr = messages::UnpinRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::UnpinRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            34 => {
+                let cr = messages::UnpinResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::UnpinResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            35 => {
+                let cr = messages::AddReferenceRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::AddReferenceRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            36 => {
+                let cr = messages::AddReferenceResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::AddReferenceResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            37 => {
+                let cr = messages::RemoveReferenceRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::RemoveReferenceRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            38 => {
+                let cr = messages::RemoveReferenceResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::RemoveReferenceResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            39 => {
+                let cr = messages::ReferenceUpdateRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ReferenceUpdateRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            40 => {
+                let cr = messages::ReferenceUpdateResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ReferenceUpdateResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            41 => {
+                let cr = messages::DeleteRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::DeleteRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            42 => {
+                let cr = messages::DeleteResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::DeleteResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            43 => {
+                let cr = messages::WriteChunkRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::WriteChunkRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
                 }
             }
-            _ => {
-                // Unknown message type
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "Unknown message type",
-                ));
+            44 => {
+                let cr = messages::WriteChunkResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::WriteChunkResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            45 => {
+                let cr = messages::ReadChunkRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ReadChunkRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            46 => {
+                let cr = messages::ReadChunkResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ReadChunkResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            47 => {
+                let cr = messages::ShutdownNotice::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ShutdownNotice(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            48 => {
+                let cr = messages::ErrorResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ErrorResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            49 => {
+                let cr = messages::ListRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ListRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            50 => {
+                let cr = messages::ListResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::ListResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
             }
+            51 => {
+                let cr = messages::InspectRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::InspectRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            52 => {
+                let cr = messages::InspectResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::InspectResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            53 => {
+                let cr = messages::EvictRequest::decode(src);
+                match cr {
+                    Ok(cr) => Messages::EvictRequest(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            54 => {
+                let cr = messages::EvictResponse::decode(src);
+                match cr {
+                    Ok(cr) => Messages::EvictResponse(cr),
+                    Err(e) => Messages::Malformed(MalformedFrame::new(message_type, e)),
+                }
+            }
+            _ => Messages::Malformed(MalformedFrame::new(message_type, "unknown message type")),
         };
 
-        Ok(message)
+        Ok(Some(message))
     }
 }
 
@@ -127,6 +761,418 @@ impl Encoder<Messages> for MessageCodec {
                 cr.encode(dst)?;
                 Ok(())
             }
+            Messages::SealRequest(cr) => {
+                let message_type = MessageType::SealRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::SealRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::SealResponse(cr) => {
+                let message_type = MessageType::SealResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::SealResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ContainsRequest(cr) => {
+                let message_type = MessageType::ContainsRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ContainsRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ContainsResponse(cr) => {
+                let message_type = MessageType::ContainsResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ContainsResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::GetRequest(cr) => {
+                let message_type = MessageType::GetRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::GetRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::GetResponse(cr) => {
+                let message_type = MessageType::GetResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::GetResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ReleaseRequest(cr) => {
+                let message_type = MessageType::ReleaseRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ReleaseRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ReleaseResponse(cr) => {
+                let message_type = MessageType::ReleaseResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ReleaseResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ResizeRequest(cr) => {
+                let message_type = MessageType::ResizeRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ResizeRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ResizeResponse(cr) => {
+                let message_type = MessageType::ResizeResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ResizeResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PutRequest(cr) => {
+                let message_type = MessageType::PutRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PutRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PutChunk(cr) => {
+                let message_type = MessageType::PutChunkMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PutChunk::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PutResponse(cr) => {
+                let message_type = MessageType::PutResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PutResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::FetchRequest(cr) => {
+                let message_type = MessageType::FetchRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::FetchRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::FetchResponse(cr) => {
+                let message_type = MessageType::FetchResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::FetchResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::DirectoryUpdateRequest(cr) => {
+                let message_type = MessageType::DirectoryUpdateRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::DirectoryUpdateRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::DirectoryUpdateResponse(cr) => {
+                let message_type = MessageType::DirectoryUpdateResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::DirectoryUpdateResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::LocateRequest(cr) => {
+                let message_type = MessageType::LocateRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::LocateRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::LocateResponse(cr) => {
+                let message_type = MessageType::LocateResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::LocateResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::HeartbeatRequest(cr) => {
+                let message_type = MessageType::HeartbeatRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::HeartbeatRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::HeartbeatResponse(cr) => {
+                let message_type = MessageType::HeartbeatResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::HeartbeatResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::MembersRequest(cr) => {
+                let message_type = MessageType::MembersRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::MembersRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::MembersResponse(cr) => {
+                let message_type = MessageType::MembersResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::MembersResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PushRequest(cr) => {
+                let message_type = MessageType::PushRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PushRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PushResponse(cr) => {
+                let message_type = MessageType::PushResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PushResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::StatsRequest(cr) => {
+                let message_type = MessageType::StatsRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::StatsRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::StatsResponse(cr) => {
+                let message_type = MessageType::StatsResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::StatsResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PinRequest(cr) => {
+                let message_type = MessageType::PinRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PinRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::PinResponse(cr) => {
+                let message_type = MessageType::PinResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::PinResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::UnpinRequest(cr) => {
+                let message_type = MessageType::UnpinRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::UnpinRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::UnpinResponse(cr) => {
+                let message_type = MessageType::UnpinResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::UnpinResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::AddReferenceRequest(cr) => {
+                let message_type = MessageType::AddReferenceRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::AddReferenceRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::AddReferenceResponse(cr) => {
+                let message_type = MessageType::AddReferenceResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::AddReferenceResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::RemoveReferenceRequest(cr) => {
+                let message_type = MessageType::RemoveReferenceRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::RemoveReferenceRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::RemoveReferenceResponse(cr) => {
+                let message_type = MessageType::RemoveReferenceResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::RemoveReferenceResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ReferenceUpdateRequest(cr) => {
+                let message_type = MessageType::ReferenceUpdateRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ReferenceUpdateRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ReferenceUpdateResponse(cr) => {
+                let message_type = MessageType::ReferenceUpdateResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ReferenceUpdateResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::DeleteRequest(cr) => {
+                let message_type = MessageType::DeleteRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::DeleteRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::DeleteResponse(cr) => {
+                let message_type = MessageType::DeleteResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::DeleteResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::WriteChunkRequest(cr) => {
+                let message_type = MessageType::WriteChunkRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::WriteChunkRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::WriteChunkResponse(cr) => {
+                let message_type = MessageType::WriteChunkResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::WriteChunkResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ReadChunkRequest(cr) => {
+                let message_type = MessageType::ReadChunkRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ReadChunkRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ReadChunkResponse(cr) => {
+                let message_type = MessageType::ReadChunkResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ReadChunkResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ShutdownNotice(cr) => {
+                let message_type = MessageType::ShutdownNoticeMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ShutdownNotice::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ErrorResponse(cr) => {
+                let message_type = MessageType::ErrorResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ErrorResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ListRequest(cr) => {
+                let message_type = MessageType::ListRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ListRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::ListResponse(cr) => {
+                let message_type = MessageType::ListResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::ListResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::InspectRequest(cr) => {
+                let message_type = MessageType::InspectRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::InspectRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::InspectResponse(cr) => {
+                let message_type = MessageType::InspectResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::InspectResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::EvictRequest(cr) => {
+                let message_type = MessageType::EvictRequestMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::EvictRequest::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::EvictResponse(cr) => {
+                let message_type = MessageType::EvictResponseMT as u16;
+                dst.put_u16_le(message_type);
+                dst.put_u64_le(messages::EvictResponse::encoded_len(&cr) as u64);
+
+                cr.encode(dst)?;
+                Ok(())
+            }
+            Messages::Malformed(frame) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot encode a {}", frame),
+            )),
         }
     }
 }
@@ -1,17 +1,95 @@
+use rand::RngCore;
 use std::fmt;
 use std::fmt::Write;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 pub const UNIQUE_ID_SIZE: usize = 20; // or whatever kUniqueIDSize is in your C++ code
 
-#[derive(Clone, Copy, PartialEq, Eq)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ObjectId {
     id: [u8; UNIQUE_ID_SIZE],
 }
 
+/// Reasons an [`ObjectId`] could not be built from user supplied input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectIdError {
+    /// The binary input was not exactly `UNIQUE_ID_SIZE` bytes.
+    InvalidLength(usize),
+    /// The hex input was not exactly `2 * UNIQUE_ID_SIZE` characters.
+    InvalidHexLength(usize),
+    /// The hex input contained a character outside `[0-9a-fA-F]`.
+    InvalidHexCharacter(char),
+}
+
+impl fmt::Display for ObjectIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectIdError::InvalidLength(len) => {
+                write!(f, "object id must be {} bytes, got {}", UNIQUE_ID_SIZE, len)
+            }
+            ObjectIdError::InvalidHexLength(len) => write!(
+                f,
+                "object id must be {} hex digits, got {}",
+                UNIQUE_ID_SIZE * 2,
+                len
+            ),
+            ObjectIdError::InvalidHexCharacter(c) => {
+                write!(f, "invalid hex character {:?} in object id", c)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ObjectIdError {}
+
 impl ObjectId {
+    /// Builds an id from exactly `UNIQUE_ID_SIZE` bytes.
+    ///
+    /// Panics if `binary` has any other length.
+    #[deprecated(note = "use ObjectId::try_from_binary, which does not panic")]
     pub fn from_binary(binary: &[u8]) -> Self {
+        match ObjectId::try_from_binary(binary) {
+            Ok(id) => id,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Builds an id from exactly `UNIQUE_ID_SIZE` bytes.
+    pub fn try_from_binary(binary: &[u8]) -> Result<Self, ObjectIdError> {
+        let id: [u8; UNIQUE_ID_SIZE] = binary
+            .try_into()
+            .map_err(|_| ObjectIdError::InvalidLength(binary.len()))?;
+        Ok(ObjectId { id })
+    }
+
+    /// Parses the representation produced by [`ObjectId::hex`].
+    /// Both upper and lower case digits are accepted.
+    pub fn from_hex(hex: &str) -> Result<Self, ObjectIdError> {
+        if let Some(c) = hex.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(ObjectIdError::InvalidHexCharacter(c));
+        }
+        if hex.len() != UNIQUE_ID_SIZE * 2 {
+            return Err(ObjectIdError::InvalidHexLength(hex.len()));
+        }
+        let mut id = [0u8; UNIQUE_ID_SIZE];
+        for (byte, pair) in id.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = (hex_value(pair[0]) << 4) | hex_value(pair[1]);
+        }
+        Ok(ObjectId { id })
+    }
+
+    /// Generates a random id from the thread local CSPRNG.
+    pub fn random() -> Self {
+        let mut id = [0u8; UNIQUE_ID_SIZE];
+        rand::thread_rng().fill_bytes(&mut id);
+        ObjectId { id }
+    }
+
+    /// Derives the id from the BLAKE3 hash of `content`, truncated to
+    /// `UNIQUE_ID_SIZE` bytes, so identical inputs always map to the same id.
+    pub fn from_content(content: &[u8]) -> Self {
         let mut id = [0u8; UNIQUE_ID_SIZE];
-        id.copy_from_slice(&binary[..UNIQUE_ID_SIZE]);
+        id.copy_from_slice(&blake3::hash(content).as_bytes()[..UNIQUE_ID_SIZE]);
         ObjectId { id }
     }
 
@@ -46,14 +124,219 @@ impl ObjectId {
     }
 }
 
+// Callers have already checked that `c` is an ASCII hex digit.
+fn hex_value(c: u8) -> u8 {
+    (c as char).to_digit(16).unwrap_or(0) as u8
+}
+
 impl fmt::Debug for ObjectId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "UniqueID({})", self.hex())
     }
 }
 
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.hex())
+    }
+}
+
+impl FromStr for ObjectId {
+    type Err = ObjectIdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ObjectId::from_hex(s)
+    }
+}
+
+impl TryFrom<&[u8]> for ObjectId {
+    type Error = ObjectIdError;
+
+    fn try_from(binary: &[u8]) -> Result<Self, Self::Error> {
+        ObjectId::try_from_binary(binary)
+    }
+}
+
+impl AsRef<[u8]> for ObjectId {
+    fn as_ref(&self) -> &[u8] {
+        &self.id
+    }
+}
+
 impl Hash for ObjectId {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.id.hash(state);
     }
 }
+
+// Human readable formats (JSON, TOML) get the hex string, binary formats get
+// the raw bytes.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ObjectId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&self.hex())
+        } else {
+            serializer.serialize_bytes(&self.id)
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for ObjectId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ObjectIdVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ObjectIdVisitor {
+            type Value = ObjectId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a hex string or {} bytes", UNIQUE_ID_SIZE)
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<ObjectId, E> {
+                ObjectId::from_hex(v).map_err(E::custom)
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<ObjectId, E> {
+                ObjectId::try_from_binary(v).map_err(E::custom)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<ObjectId, A::Error> {
+                let mut id = [0u8; UNIQUE_ID_SIZE];
+                for (i, byte) in id.iter_mut().enumerate() {
+                    *byte = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                }
+                let mut len = UNIQUE_ID_SIZE;
+                while seq.next_element::<serde::de::IgnoredAny>()?.is_some() {
+                    len += 1;
+                }
+                if len > UNIQUE_ID_SIZE {
+                    return Err(serde::de::Error::invalid_length(len, &self));
+                }
+                Ok(ObjectId { id })
+            }
+        }
+
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_str(ObjectIdVisitor)
+        } else {
+            deserializer.deserialize_bytes(ObjectIdVisitor)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binary_of_the_wrong_length() {
+        assert_eq!(
+            ObjectId::try_from_binary(&[0; UNIQUE_ID_SIZE - 1]),
+            Err(ObjectIdError::InvalidLength(UNIQUE_ID_SIZE - 1))
+        );
+        assert_eq!(
+            ObjectId::try_from_binary(&[0; UNIQUE_ID_SIZE + 1]),
+            Err(ObjectIdError::InvalidLength(UNIQUE_ID_SIZE + 1))
+        );
+        assert_eq!(
+            ObjectId::try_from_binary(&[]),
+            Err(ObjectIdError::InvalidLength(0))
+        );
+        let id = ObjectId::try_from_binary(&[7; UNIQUE_ID_SIZE]).unwrap();
+        assert_eq!(id.data(), &[7; UNIQUE_ID_SIZE]);
+        assert_eq!(ObjectId::try_from(&[7; UNIQUE_ID_SIZE][..]), Ok(id));
+    }
+
+    #[test]
+    fn invalid_hex() {
+        let hex = "0123456789abcdef0123456789abcdef01234567";
+        assert_eq!(
+            ObjectId::from_hex(&hex[1..]),
+            Err(ObjectIdError::InvalidHexLength(UNIQUE_ID_SIZE * 2 - 1))
+        );
+        assert_eq!(
+            ObjectId::from_hex(&format!("{}0", hex)),
+            Err(ObjectIdError::InvalidHexLength(UNIQUE_ID_SIZE * 2 + 1))
+        );
+        assert_eq!(
+            ObjectId::from_hex(&format!("{}00", hex)),
+            Err(ObjectIdError::InvalidHexLength(UNIQUE_ID_SIZE * 2 + 2))
+        );
+        assert_eq!(
+            ObjectId::from_hex(&format!("{}0", hex))
+                .unwrap_err()
+                .to_string(),
+            "object id must be 40 hex digits, got 41"
+        );
+        assert_eq!(
+            ObjectId::from_hex(""),
+            Err(ObjectIdError::InvalidHexLength(0))
+        );
+        assert_eq!(
+            ObjectId::from_hex(&hex.replace('a', "g")),
+            Err(ObjectIdError::InvalidHexCharacter('g'))
+        );
+        assert_eq!(
+            ObjectId::from_hex(&format!("0x{}", &hex[2..])),
+            Err(ObjectIdError::InvalidHexCharacter('x'))
+        );
+        assert_eq!(
+            ObjectId::from_hex(&hex.replace('a', "é")),
+            Err(ObjectIdError::InvalidHexCharacter('é'))
+        );
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        let hex = "0123456789abcdef0123456789abcdef01234567";
+        let id = ObjectId::from_hex(hex).unwrap();
+        assert_eq!(id.hex(), hex);
+        assert_eq!(id.to_string(), hex);
+        assert_eq!(id.binary()[..3], [0x01, 0x23, 0x45]);
+        assert_eq!(ObjectId::from_hex(&hex.to_uppercase()), Ok(id));
+        assert_eq!(hex.parse::<ObjectId>(), Ok(id));
+
+        let id = ObjectId::random();
+        assert_eq!(id.to_string().parse::<ObjectId>(), Ok(id));
+        assert_eq!(ObjectId::try_from_binary(&id.binary()), Ok(id));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    #[should_panic(expected = "object id must be 20 bytes, got 21")]
+    fn from_binary_rejects_long_input() {
+        ObjectId::from_binary(&[0; UNIQUE_ID_SIZE + 1]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_a_sequence() {
+        use serde::de::value::{Error, SeqDeserializer};
+        use serde::Deserialize;
+
+        let deserialize = |len: usize| {
+            let bytes = SeqDeserializer::<_, Error>::new(0..len as u8);
+            ObjectId::deserialize(bytes)
+        };
+        let id = deserialize(UNIQUE_ID_SIZE).unwrap();
+        assert_eq!(id.data()[UNIQUE_ID_SIZE - 1], UNIQUE_ID_SIZE as u8 - 1);
+        assert!(deserialize(UNIQUE_ID_SIZE - 1).is_err());
+        let err = deserialize(UNIQUE_ID_SIZE + 2).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid length 22, expected a hex string or 20 bytes"
+        );
+    }
+
+    #[test]
+    fn random_ids_differ() {
+        assert_ne!(ObjectId::random(), ObjectId::random());
+    }
+}
@@ -0,0 +1,105 @@
+use std::io;
+
+use futures::SinkExt;
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+use crabstore_common::objectid::ObjectId;
+
+/// Connection to a directory served by another process. Reconnects on the
+/// next call after a failure.
+pub struct DirectoryClient {
+    address: String,
+    connection: Mutex<Option<Framed<TcpStream, MessageCodec>>>,
+}
+
+impl DirectoryClient {
+    pub fn new(address: String) -> Self {
+        DirectoryClient {
+            address,
+            connection: Mutex::new(None),
+        }
+    }
+
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Records that `node` now holds `added` and no longer holds `removed`.
+    pub async fn update(
+        &self,
+        node: &str,
+        added: &[ObjectId],
+        removed: &[ObjectId],
+    ) -> io::Result<()> {
+        let request = Messages::DirectoryUpdateRequest(messages::DirectoryUpdateRequest {
+            node: node.to_string(),
+            added: added.iter().map(ObjectId::binary).collect(),
+            removed: removed.iter().map(ObjectId::binary).collect(),
+            purge: false,
+        });
+        self.send_update(request).await
+    }
+
+    /// Forgets every object `node` was holding.
+    pub async fn purge(&self, node: &str) -> io::Result<()> {
+        let request = Messages::DirectoryUpdateRequest(messages::DirectoryUpdateRequest {
+            node: node.to_string(),
+            purge: true,
+            ..Default::default()
+        });
+        self.send_update(request).await
+    }
+
+    async fn send_update(&self, request: Messages) -> io::Result<()> {
+        match self.call(request).await? {
+            Messages::DirectoryUpdateResponse(ur) if ur.error() == messages::Error::Ok => Ok(()),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    /// Stores holding `object_id`.
+    pub async fn locate(&self, object_id: &ObjectId) -> io::Result<Vec<String>> {
+        let request = Messages::LocateRequest(messages::LocateRequest {
+            object_id: object_id.binary(),
+        });
+        match self.call(request).await? {
+            Messages::LocateResponse(lr) => Ok(lr.nodes),
+            response => Err(unexpected(response)),
+        }
+    }
+
+    async fn call(&self, request: Messages) -> io::Result<Messages> {
+        let mut connection = self.connection.lock().await;
+        if connection.is_none() {
+            let stream = TcpStream::connect(&self.address).await?;
+            stream.set_nodelay(true)?;
+            *connection = Some(Framed::new(stream, MessageCodec {}));
+        }
+        let framed = connection.as_mut().unwrap();
+        let result = async {
+            framed.send(request).await?;
+            framed
+                .next()
+                .await
+                .unwrap_or_else(|| Err(io::ErrorKind::UnexpectedEof.into()))
+        }
+        .await;
+        if result.is_err() {
+            *connection = None;
+        }
+        result
+    }
+}
+
+fn unexpected(response: Messages) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected directory response {:?}", response),
+    )
+}
@@ -0,0 +1,91 @@
+use std::collections::{HashMap, HashSet};
+
+use crabstore_common::objectid::ObjectId;
+
+/// Which stores hold which objects. Stores are identified by the TCP
+/// address they serve their objects on.
+#[derive(Default)]
+pub struct ObjectDirectory {
+    locations: HashMap<ObjectId, HashSet<String>>,
+}
+
+impl ObjectDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, object_id: ObjectId, node: &str) {
+        self.locations
+            .entry(object_id)
+            .or_default()
+            .insert(node.to_string());
+    }
+
+    pub fn remove(&mut self, object_id: &ObjectId, node: &str) {
+        if let Some(nodes) = self.locations.get_mut(object_id) {
+            nodes.remove(node);
+            if nodes.is_empty() {
+                self.locations.remove(object_id);
+            }
+        }
+    }
+
+    /// Forgets every object `node` was holding.
+    pub fn remove_node(&mut self, node: &str) {
+        self.locations.retain(|_, nodes| {
+            nodes.remove(node);
+            !nodes.is_empty()
+        });
+    }
+
+    /// Stores holding `object_id`, in no particular order.
+    pub fn lookup(&self, object_id: &ObjectId) -> Vec<String> {
+        self.locations
+            .get(object_id)
+            .map(|nodes| nodes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut nodes: Vec<String>) -> Vec<String> {
+        nodes.sort();
+        nodes
+    }
+
+    #[test]
+    fn add_and_remove() {
+        let mut directory = ObjectDirectory::new();
+        let object_id = ObjectId::random();
+        assert!(directory.lookup(&object_id).is_empty());
+
+        directory.add(object_id, "b:1");
+        directory.add(object_id, "a:1");
+        directory.add(object_id, "a:1");
+        assert_eq!(sorted(directory.lookup(&object_id)), ["a:1", "b:1"]);
+
+        directory.remove(&object_id, "a:1");
+        directory.remove(&object_id, "c:1");
+        assert_eq!(directory.lookup(&object_id), ["b:1"]);
+        directory.remove(&object_id, "b:1");
+        assert!(directory.locations.is_empty());
+    }
+
+    #[test]
+    fn remove_node() {
+        let mut directory = ObjectDirectory::new();
+        let shared = ObjectId::random();
+        let only_on_a = ObjectId::random();
+        directory.add(shared, "a:1");
+        directory.add(shared, "b:1");
+        directory.add(only_on_a, "a:1");
+
+        directory.remove_node("a:1");
+        assert_eq!(directory.lookup(&shared), ["b:1"]);
+        assert!(directory.lookup(&only_on_a).is_empty());
+        assert_eq!(directory.locations.len(), 1);
+    }
+}
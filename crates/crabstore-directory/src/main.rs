@@ -0,0 +1,32 @@
+use std::sync::{Arc, Mutex};
+
+use clap::Parser;
+use log::error;
+use tokio::net::TcpListener;
+
+use crabstore_directory::directory::ObjectDirectory;
+use crabstore_directory::server;
+
+/// Runs the object directory of a cluster of stores on its own.
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct CliArgs {
+    /// TCP address to serve the directory on.
+    #[arg(short = 'l', long)]
+    listen_address: String,
+}
+
+fn main() {
+    env_logger::init();
+    let args = CliArgs::parse();
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = runtime.block_on(async {
+        let listener = TcpListener::bind(&args.listen_address).await?;
+        server::serve(listener, Arc::new(Mutex::new(ObjectDirectory::new()))).await
+    });
+    if let Err(e) = result {
+        error!("Directory exited with error: {}", e);
+        std::process::exit(1);
+    }
+}
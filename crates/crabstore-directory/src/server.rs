@@ -0,0 +1,151 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::SinkExt;
+use log::{debug, error, info};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::directory::ObjectDirectory;
+
+/// Answers a directory request, or returns `None` if `request` is not one.
+/// Stores that embed the directory call this for requests arriving on their
+/// own connections.
+pub fn handle_request(directory: &Mutex<ObjectDirectory>, request: &Messages) -> Option<Messages> {
+    match request {
+        Messages::DirectoryUpdateRequest(ur) => {
+            debug!(
+                "Directory update from {}: {} added, {} removed",
+                ur.node,
+                ur.added.len(),
+                ur.removed.len()
+            );
+            let mut directory = directory.lock().unwrap();
+            if ur.purge {
+                directory.remove_node(&ur.node);
+            }
+            let mut error = messages::Error::Ok;
+            for raw_id in &ur.added {
+                match ObjectId::try_from_binary(raw_id) {
+                    Ok(object_id) => directory.add(object_id, &ur.node),
+                    Err(_) => error = messages::Error::InvalidRequest,
+                }
+            }
+            for raw_id in &ur.removed {
+                match ObjectId::try_from_binary(raw_id) {
+                    Ok(object_id) => directory.remove(&object_id, &ur.node),
+                    Err(_) => error = messages::Error::InvalidRequest,
+                }
+            }
+            let mut response = messages::DirectoryUpdateResponse::default();
+            response.set_error(error);
+            Some(Messages::DirectoryUpdateResponse(response))
+        }
+        Messages::LocateRequest(lr) => {
+            let nodes = ObjectId::try_from_binary(&lr.object_id)
+                .map(|object_id| directory.lock().unwrap().lookup(&object_id))
+                .unwrap_or_default();
+            Some(Messages::LocateResponse(messages::LocateResponse {
+                object_id: lr.object_id.clone(),
+                nodes,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Serves the directory on its own, for clusters that do not embed it in
+/// one of their stores.
+pub async fn serve(
+    listener: TcpListener,
+    directory: Arc<Mutex<ObjectDirectory>>,
+) -> io::Result<()> {
+    info!("Object directory listening on {}", listener.local_addr()?);
+    loop {
+        let (stream, address) = listener.accept().await?;
+        debug!("Directory client connected from {}", address);
+        let directory = directory.clone();
+        tokio::spawn(async move {
+            if let Err(e) = serve_connection(stream, &directory).await {
+                error!("Directory connection from {} failed: {}", address, e);
+            }
+        });
+    }
+}
+
+async fn serve_connection(stream: TcpStream, directory: &Mutex<ObjectDirectory>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut framed = Framed::new(stream, MessageCodec {});
+    while let Some(request) = framed.next().await {
+        let request = request?;
+        match handle_request(directory, &request) {
+            Some(response) => framed.send(response).await?,
+            None => error!("Invalid Request = {:?}", request),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::DirectoryClient;
+
+    #[tokio::test]
+    async fn serve_clients() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let directory = Arc::new(Mutex::new(ObjectDirectory::new()));
+        tokio::spawn(serve(listener, directory.clone()));
+
+        let client = DirectoryClient::new(address);
+        let kept = ObjectId::random();
+        let dropped = ObjectId::random();
+        client.update("a:1", &[kept, dropped], &[]).await.unwrap();
+        client.update("b:1", &[kept], &[]).await.unwrap();
+        client.update("a:1", &[], &[dropped]).await.unwrap();
+        let mut nodes = client.locate(&kept).await.unwrap();
+        nodes.sort();
+        assert_eq!(nodes, ["a:1", "b:1"]);
+        assert!(client.locate(&dropped).await.unwrap().is_empty());
+
+        client.purge("a:1").await.unwrap();
+        assert_eq!(client.locate(&kept).await.unwrap(), ["b:1"]);
+        assert_eq!(directory.lock().unwrap().lookup(&kept), ["b:1"]);
+    }
+
+    #[test]
+    fn invalid_ids() {
+        let directory = Mutex::new(ObjectDirectory::new());
+        let object_id = ObjectId::random();
+        let request = Messages::DirectoryUpdateRequest(messages::DirectoryUpdateRequest {
+            node: "a:1".to_string(),
+            added: vec![object_id.binary(), vec![1, 2, 3]],
+            ..Default::default()
+        });
+        match handle_request(&directory, &request) {
+            Some(Messages::DirectoryUpdateResponse(response)) => {
+                assert_eq!(response.error(), messages::Error::InvalidRequest)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        // The valid ones are still recorded.
+        assert_eq!(directory.lock().unwrap().lookup(&object_id), ["a:1"]);
+
+        let request = Messages::LocateRequest(messages::LocateRequest {
+            object_id: vec![1, 2, 3],
+        });
+        match handle_request(&directory, &request) {
+            Some(Messages::LocateResponse(response)) => assert!(response.nodes.is_empty()),
+            other => panic!("unexpected response {:?}", other),
+        }
+        let request = Messages::ListRequest(messages::ListRequest::default());
+        assert!(handle_request(&directory, &request).is_none());
+    }
+}
@@ -1,27 +1,315 @@
-pub struct Allocation {}
-trait Allocator {
-    fn allocate(size: i64) -> Allocation;
-    fn free(allocation: Allocation);
-    fn total_allocated() -> i64;
+use memmap2::{MmapMut, MmapOptions};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+// Every allocation starts on a cache line boundary.
+const ALIGNMENT: u64 = 64;
+
+/// Name of the pool file of a recoverable store, in its mem_mapped_dir.
+pub const POOL_FILE: &str = "crabstore-pool";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pub segment_index: i32,
+    pub offset: u64,
+    pub size: u64,
+}
+
+pub trait Allocator {
+    fn allocate(&mut self, size: u64) -> Option<Allocation>;
+    fn free(&mut self, allocation: Allocation);
+    fn total_allocated(&self) -> u64;
+    fn capacity(&self) -> u64;
+}
+
+/// First-fit allocator over a single memory mapped file that is shared with
+/// the clients.
+pub struct RamAllocator {
+    path: PathBuf,
+    _file: File,
+    // Left in place on exit, for the next store to reattach to.
+    recoverable: bool,
+    mmap: MmapMut,
+    capacity: u64,
+    allocated: u64,
+    // Free ranges keyed by offset. Adjacent ranges are always merged.
+    free_list: BTreeMap<u64, u64>,
 }
-pub struct RamAllocator {}
 
 impl RamAllocator {
-    pub fn new() -> Self {
-        RamAllocator {}
+    /// Creates the file backing the store's memory in `mem_mapped_dir`.
+    /// With `prefault`, its space is reserved and mapped in up front, so a
+    /// full file system fails here rather than on the first write to it.
+    pub fn new(mem_mapped_dir: &Path, capacity: u64, prefault: bool) -> io::Result<Self> {
+        let path = mem_mapped_dir.join(format!("crabstore-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len(capacity)?;
+        Self::map(path, file, capacity, prefault, false)
+    }
+
+    /// Maps the pool file of a recoverable store in `mem_mapped_dir`, keeping
+    /// the contents left by a previous store, or creates it. Everything in
+    /// it starts out free: the objects to keep are claimed back afterwards.
+    pub fn reattach(mem_mapped_dir: &Path, capacity: u64, prefault: bool) -> io::Result<Self> {
+        let path = mem_mapped_dir.join(POOL_FILE);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        // SAFETY: plain call on a file descriptor we own.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use by another store", path.display()),
+            ));
+        }
+        let len = file.metadata()?.len();
+        if len == 0 {
+            file.set_len(capacity)?;
+        } else if len != capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} holds a pool of {} bytes, set sys_memory to that or remove it",
+                    path.display(),
+                    len
+                ),
+            ));
+        }
+        Self::map(path, file, capacity, prefault, true)
+    }
+
+    fn map(
+        path: PathBuf,
+        file: File,
+        capacity: u64,
+        prefault: bool,
+        recoverable: bool,
+    ) -> io::Result<Self> {
+        let mut options = MmapOptions::new();
+        if prefault && capacity > 0 {
+            // SAFETY: plain call on a file descriptor we own.
+            let error =
+                unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, capacity as libc::off_t) };
+            if error != 0 {
+                return Err(io::Error::from_raw_os_error(error));
+            }
+            options.populate();
+        }
+        // SAFETY: the file is ours, and never truncated while mapped.
+        let mmap = unsafe { options.map_mut(&file)? };
+
+        let mut free_list = BTreeMap::new();
+        if capacity > 0 {
+            free_list.insert(0, capacity);
+        }
+        Ok(RamAllocator {
+            path,
+            _file: file,
+            recoverable,
+            mmap,
+            capacity,
+            allocated: 0,
+            free_list,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Takes the `size` bytes at `offset` out of the free space, e.g. for an
+    /// object recovered from a previous store. Returns `None` if any of them
+    /// is allocated already or outside the pool.
+    pub fn claim(&mut self, offset: u64, size: u64) -> Option<Allocation> {
+        if offset % ALIGNMENT != 0 || size == 0 || size % ALIGNMENT != 0 {
+            return None;
+        }
+        let end = offset.checked_add(size)?;
+        let (&free_offset, &free_size) = self.free_list.range(..=offset).next_back()?;
+        if free_offset + free_size < end {
+            return None;
+        }
+        self.free_list.remove(&free_offset);
+        if free_offset < offset {
+            self.free_list.insert(free_offset, offset - free_offset);
+        }
+        if end < free_offset + free_size {
+            self.free_list.insert(end, free_offset + free_size - end);
+        }
+        self.allocated += size;
+        Some(Allocation {
+            segment_index: 0,
+            offset,
+            size,
+        })
+    }
+
+    /// The store's own view of the memory backing `allocation`.
+    pub fn buffer_mut(&mut self, allocation: &Allocation) -> &mut [u8] {
+        let start = allocation.offset as usize;
+        &mut self.mmap[start..start + allocation.size as usize]
+    }
+
+    /// Copies `len` bytes between two allocations, at the given offsets
+    /// relative to their starts.
+    pub fn copy(
+        &mut self,
+        src: &Allocation,
+        src_offset: u64,
+        dst: &Allocation,
+        dst_offset: u64,
+        len: u64,
+    ) {
+        let from = (src.offset + src_offset) as usize;
+        let to = (dst.offset + dst_offset) as usize;
+        self.mmap.copy_within(from..from + len as usize, to);
+    }
+}
+
+impl Drop for RamAllocator {
+    // Clients that still have the memory mapped keep it until they unmap it.
+    fn drop(&mut self) {
+        if !self.recoverable {
+            let _ = std::fs::remove_file(&self.path);
+        }
     }
 }
 
 impl Allocator for RamAllocator {
-    fn allocate(size: i64) -> Allocation {
-        todo!()
+    fn allocate(&mut self, size: u64) -> Option<Allocation> {
+        // Also keeps the rounding below from overflowing.
+        if size > self.capacity {
+            return None;
+        }
+        let size = size.max(1).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let (&offset, &free_size) = self.free_list.iter().find(|(_, &s)| s >= size)?;
+
+        self.free_list.remove(&offset);
+        if free_size > size {
+            self.free_list.insert(offset + size, free_size - size);
+        }
+        self.allocated += size;
+        Some(Allocation {
+            segment_index: 0,
+            offset,
+            size,
+        })
+    }
+
+    fn free(&mut self, allocation: Allocation) {
+        let mut offset = allocation.offset;
+        let mut size = allocation.size;
+        self.allocated -= size;
+
+        if let Some((&prev_offset, &prev_size)) = self.free_list.range(..offset).next_back() {
+            if prev_offset + prev_size == offset {
+                self.free_list.remove(&prev_offset);
+                offset = prev_offset;
+                size += prev_size;
+            }
+        }
+        if let Some(next_size) = self.free_list.remove(&(offset + size)) {
+            size += next_size;
+        }
+        self.free_list.insert(offset, size);
     }
 
-    fn free(allocation: Allocation) {
-        todo!()
+    fn total_allocated(&self) -> u64 {
+        self.allocated
+    }
+
+    fn capacity(&self) -> u64 {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn claims_do_not_overlap() {
+        let dir = TempDir::new();
+        let mut allocator = RamAllocator::reattach(dir.path(), 4096, false).unwrap();
+        assert!(allocator.claim(128, 256).is_some());
+        assert!(allocator.claim(64, 128).is_none());
+        assert!(allocator.claim(320, 64).is_none());
+        assert!(allocator.claim(192, 64).is_none());
+        assert!(allocator.claim(0, 128).is_some());
+        assert!(allocator.claim(384, 64).is_some());
+        assert_eq!(allocator.total_allocated(), 448);
+
+        // Allocations go around what was claimed.
+        assert_eq!(allocator.allocate(64).unwrap().offset, 448);
+    }
+
+    #[test]
+    fn claims_outside_the_pool() {
+        let dir = TempDir::new();
+        let mut allocator = RamAllocator::reattach(dir.path(), 4096, false).unwrap();
+        assert!(allocator.claim(4096 - 64, 128).is_none());
+        assert!(allocator.claim(4096, 64).is_none());
+        assert!(allocator.claim(u64::MAX - 63, 64).is_none());
+        assert!(allocator.claim(64, u64::MAX - 63).is_none());
+        assert!(allocator.claim(32, 64).is_none());
+        assert!(allocator.claim(64, 32).is_none());
+        assert!(allocator.claim(64, 0).is_none());
+        assert_eq!(allocator.total_allocated(), 0);
+        assert!(allocator.claim(0, 4096).is_some());
+    }
+
+    #[test]
+    fn claimed_space_is_freed() {
+        let dir = TempDir::new();
+        let mut allocator = RamAllocator::reattach(dir.path(), 4096, false).unwrap();
+        let claimed = allocator.claim(1024, 1024).unwrap();
+        allocator.free(claimed);
+        assert_eq!(allocator.total_allocated(), 0);
+        assert_eq!(allocator.allocate(4096).unwrap().offset, 0);
+    }
+
+    #[test]
+    fn reattach_to_a_resized_pool() {
+        let dir = TempDir::new();
+        let mut allocator = RamAllocator::reattach(dir.path(), 4096, false).unwrap();
+        let allocation = allocator.allocate(64).unwrap();
+        allocator.buffer_mut(&allocation).copy_from_slice(&[7; 64]);
+        drop(allocator);
+
+        let err = RamAllocator::reattach(dir.path(), 8192, false)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = RamAllocator::reattach(dir.path(), 2048, false)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // The contents survive the failed attempts.
+        let mut allocator = RamAllocator::reattach(dir.path(), 4096, false).unwrap();
+        let allocation = allocator.claim(0, 64).unwrap();
+        assert_eq!(allocator.buffer_mut(&allocation), &[7; 64]);
     }
 
-    fn total_allocated() -> i64 {
-        todo!()
+    #[test]
+    fn reattach_to_a_pool_in_use() {
+        let dir = TempDir::new();
+        let _allocator = RamAllocator::reattach(dir.path(), 4096, false).unwrap();
+        let err = RamAllocator::reattach(dir.path(), 4096, false)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
     }
 }
@@ -0,0 +1,186 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use log::debug;
+use tokio::time::{Duration, Instant};
+
+use crabstore_common::messages::messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::lock::LockExt;
+use crate::object_store::{ObjectState, ObjectStore, UPLOAD_CLIENT_ID};
+use crate::replication::Replicator;
+use crate::store::MAX_CHUNK_SIZE;
+
+// Objects uploaded with WriteChunkRequests are created by the store rather
+// than by the client sending them, so they outlive the connection. A client
+// that lost its connection reconnects, asks how far the upload got with an
+// empty chunk, and sends the rest from there. Uploads nobody comes back to
+// are aborted once they have been idle for a while.
+
+/// How long an unfinished chunked upload waits for its next chunk.
+pub const UPLOAD_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+struct Upload {
+    received: u64,
+    size: u64,
+    last_write: Instant,
+}
+
+/// The chunked uploads in progress on this store.
+pub struct Uploads {
+    object_store: Arc<Mutex<ObjectStore>>,
+    replicator: Arc<Replicator>,
+    uploads: Mutex<HashMap<ObjectId, Upload>>,
+}
+
+impl Uploads {
+    pub fn new(object_store: Arc<Mutex<ObjectStore>>, replicator: Arc<Replicator>) -> Arc<Self> {
+        Arc::new(Uploads {
+            object_store,
+            replicator,
+            uploads: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Writes the chunk of `wr`, creating the object for the first one,
+    /// charged to `namespace`, and sealing it after the last one.
+    pub fn write_chunk(
+        &self,
+        wr: messages::WriteChunkRequest,
+        namespace: &str,
+    ) -> messages::WriteChunkResponse {
+        let mut response = messages::WriteChunkResponse {
+            object_id: wr.object_id.clone(),
+            ..Default::default()
+        };
+        let Ok(object_id) = ObjectId::try_from_binary(&wr.object_id) else {
+            response.set_error(messages::Error::ObjectNonexistent);
+            return response;
+        };
+        if wr.data.len() as u64 > MAX_CHUNK_SIZE {
+            response.set_error(messages::Error::InvalidRequest);
+            return response;
+        }
+
+        let mut created = false;
+        let result = self.write(object_id, &wr, namespace, &mut created);
+        if created {
+            self.replicator
+                .track(object_id, wr.replication_factor, false);
+        }
+        match result {
+            Ok(received) => response.received = received,
+            Err((e, received)) => {
+                response.set_error(e);
+                response.received = received;
+            }
+        }
+        response
+    }
+
+    // Returns how many bytes of the object the store has, along with the
+    // error if the chunk could not be written.
+    fn write(
+        &self,
+        object_id: ObjectId,
+        wr: &messages::WriteChunkRequest,
+        namespace: &str,
+        created: &mut bool,
+    ) -> Result<u64, (messages::Error, u64)> {
+        let mut uploads = self.uploads.locked();
+        let mut object_store = self.object_store.locked();
+        let upload = match uploads.entry(object_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                match object_store.state(&object_id) {
+                    // Finished already, or being created some other way.
+                    Some(ObjectState::Sealed) | Some(ObjectState::Created) => {
+                        return Err((messages::Error::ObjectExists, 0))
+                    }
+                    // Only the first chunk starts an upload. A later one is
+                    // for an upload that was aborted, and has to start over.
+                    None if wr.offset != 0 => return Err((messages::Error::ObjectNonexistent, 0)),
+                    None => {}
+                }
+                object_store
+                    .create_object(
+                        object_id,
+                        wr.data_size,
+                        wr.metadata_size,
+                        0,
+                        false,
+                        UPLOAD_CLIENT_ID,
+                        Some(namespace),
+                    )
+                    .map_err(|e| (e, 0))?;
+                *created = true;
+                debug!("Started chunked upload of {}", object_id);
+                entry.insert(Upload {
+                    received: 0,
+                    size: wr.data_size + wr.metadata_size,
+                    last_write: Instant::now(),
+                })
+            }
+        };
+
+        if wr.data.is_empty() && upload.received < upload.size {
+            return Ok(upload.received);
+        }
+        if wr.offset != upload.received {
+            debug!(
+                "Chunk of {} at {} while the upload is at {}",
+                object_id, wr.offset, upload.received
+            );
+            return Err((messages::Error::InvalidRequest, upload.received));
+        }
+        let result = object_store
+            .write_object(&object_id, wr.offset, &wr.data)
+            .and_then(|()| {
+                upload.received += wr.data.len() as u64;
+                upload.last_write = Instant::now();
+                if upload.received < upload.size {
+                    return Ok(false);
+                }
+                object_store.seal_object(&object_id, None).map(|()| true)
+            });
+        let received = upload.received;
+        match result {
+            Ok(false) => return Ok(received),
+            Ok(true) => debug!("Finished chunked upload of {}", object_id),
+            Err(e) => {
+                debug!("Chunked upload of {} failed: {:?}", object_id, e);
+                let _ = object_store.abort_object(&object_id);
+            }
+        }
+        uploads.remove(&object_id);
+        result.map(|_| received).map_err(|e| (e, 0))
+    }
+
+    // Aborts the uploads that have not received a chunk in `timeout`.
+    fn expire(&self, timeout: Duration) {
+        let mut uploads = self.uploads.locked();
+        let mut object_store = self.object_store.locked();
+        uploads.retain(|object_id, upload| {
+            if upload.last_write.elapsed() < timeout {
+                return true;
+            }
+            debug!(
+                "Aborting chunked upload of {} idle at {} of {} bytes",
+                object_id, upload.received, upload.size
+            );
+            let _ = object_store.abort_object(object_id);
+            false
+        });
+    }
+}
+
+/// Aborts chunked uploads left idle for longer than `timeout`.
+pub async fn expire_uploads(uploads: Arc<Uploads>, timeout: Duration) {
+    let mut interval = tokio::time::interval(timeout / 2);
+    loop {
+        interval.tick().await;
+        uploads.expire(timeout);
+    }
+}
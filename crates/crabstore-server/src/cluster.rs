@@ -0,0 +1,167 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+use log::{debug, warn};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crabstore_common::objectid::ObjectId;
+use crabstore_directory::client::DirectoryClient;
+use crabstore_directory::directory::ObjectDirectory;
+
+use crate::lock::LockExt;
+use crate::object_store::ObjectEvent;
+use crate::pull_manager::ObjectLocator;
+use crate::refcount::LostObjectPolicy;
+
+// Wait before reporting to an unreachable directory again.
+const REPORT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// How this store takes part in a cluster of stores.
+pub struct ClusterConfig {
+    /// Serve clients and peers over TCP on this address.
+    pub tcp_address: Option<String>,
+    /// Address peers reach this store on, if not `tcp_address`.
+    pub advertise_address: Option<String>,
+    /// Peers to pull missing objects from, after those the directory knows.
+    pub pull_from: Vec<String>,
+    pub max_concurrent_pulls: usize,
+    /// Host the cluster's object directory in this store.
+    pub embed_directory: bool,
+    /// TCP address of the object directory hosted elsewhere.
+    pub directory_address: Option<String>,
+    /// Name of this store in the cluster, if not its address.
+    pub node_id: Option<String>,
+    /// TCP addresses of the other stores to exchange heartbeats with.
+    pub peers: Vec<String>,
+    pub heartbeat_interval: Duration,
+    /// Peers not heard from for this long are considered dead.
+    pub dead_node_timeout: Duration,
+    /// How many stores should hold objects created here, unless their
+    /// creator asks otherwise.
+    pub replication_factor: u32,
+    /// What to do with copies of objects whose owning store died.
+    pub lost_object_policy: LostObjectPolicy,
+}
+
+impl ClusterConfig {
+    /// The address this store is known by in the directory.
+    pub fn node_address(&self) -> Option<&str> {
+        self.advertise_address
+            .as_deref()
+            .or(self.tcp_address.as_deref())
+    }
+}
+
+/// The object directory this store reports to and looks objects up in.
+pub enum Directory {
+    Embedded(Arc<Mutex<ObjectDirectory>>),
+    Remote(DirectoryClient),
+}
+
+impl Directory {
+    async fn update(&self, node: &str, added: &[ObjectId], removed: &[ObjectId]) -> io::Result<()> {
+        match self {
+            Directory::Embedded(directory) => {
+                let mut directory = directory.locked();
+                for object_id in added {
+                    directory.add(*object_id, node);
+                }
+                for object_id in removed {
+                    directory.remove(object_id, node);
+                }
+                Ok(())
+            }
+            Directory::Remote(client) => client.update(node, added, removed).await,
+        }
+    }
+
+    pub async fn purge(&self, node: &str) -> io::Result<()> {
+        match self {
+            Directory::Embedded(directory) => {
+                directory.locked().remove_node(node);
+                Ok(())
+            }
+            Directory::Remote(client) => client.purge(node).await,
+        }
+    }
+
+    async fn locate(&self, object_id: &ObjectId) -> io::Result<Vec<String>> {
+        match self {
+            Directory::Embedded(directory) => Ok(directory.locked().lookup(object_id)),
+            Directory::Remote(client) => client.locate(object_id).await,
+        }
+    }
+}
+
+/// Reports every object sealed in, or removed from, this store to the
+/// directory, batching those that change while a report is in flight.
+pub async fn report_locations(
+    directory: Arc<Directory>,
+    node: String,
+    mut events: mpsc::UnboundedReceiver<ObjectEvent>,
+) {
+    while let Some(event) = events.recv().await {
+        let mut batch = vec![event];
+        while let Ok(event) = events.try_recv() {
+            batch.push(event);
+        }
+        // Events are applied in order, so only the last one per object
+        // matters.
+        let mut added = Vec::new();
+        let mut removed = Vec::new();
+        for event in batch {
+            match event {
+                ObjectEvent::Sealed(object_id) => {
+                    removed.retain(|id| *id != object_id);
+                    added.push(object_id);
+                }
+                ObjectEvent::Removed(object_id) => {
+                    added.retain(|id| *id != object_id);
+                    removed.push(object_id);
+                }
+            }
+        }
+        while let Err(e) = directory.update(&node, &added, &removed).await {
+            warn!(
+                "Reporting {} objects to the directory failed: {}",
+                added.len() + removed.len(),
+                e
+            );
+            tokio::time::sleep(REPORT_RETRY_DELAY).await;
+        }
+        debug!(
+            "Reported {} new and {} removed objects to the directory",
+            added.len(),
+            removed.len()
+        );
+    }
+}
+
+/// Looks objects up in the directory, then falls back to a fixed list of
+/// peers.
+pub struct DirectoryLocator {
+    pub directory: Arc<Directory>,
+    // This store, which the directory may still list for objects it lost.
+    pub node: String,
+    pub fallback: Vec<String>,
+}
+
+impl ObjectLocator for DirectoryLocator {
+    fn locate<'a>(&'a self, object_id: &'a ObjectId) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(async move {
+            let mut nodes = self.directory.locate(object_id).await.unwrap_or_else(|e| {
+                warn!("Looking up {} in the directory failed: {}", object_id, e);
+                Vec::new()
+            });
+            nodes.retain(|node| *node != self.node);
+            for peer in &self.fallback {
+                if !nodes.contains(peer) {
+                    nodes.push(peer.clone());
+                }
+            }
+            nodes
+        })
+    }
+}
@@ -0,0 +1,853 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{CommandFactory, Parser};
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::Deserialize;
+
+use crate::cluster::ClusterConfig;
+use crate::object_store::EvictionPolicy;
+use crate::quota::{Quota, DEFAULT_NAMESPACE};
+use crate::refcount::LostObjectPolicy;
+use crate::runner::StoreConfig;
+
+// Every setting can be given as a command line flag, as an environment
+// variable named after it (CRABSTORE_SYS_MEMORY for --sys-memory), or as a
+// key of the TOML file passed with --config (sys_memory = "8GiB"). Flags win
+// over the environment, which wins over the file. Other CRABSTORE_* variables
+// are left alone, with a warning, as they may be meant for something else.
+
+const ENV_PREFIX: &str = "CRABSTORE_";
+
+const DEFAULT_SYS_MEMORY: u64 = 1_000_000;
+const DEFAULT_MAX_CONCURRENT_PULLS: usize = 4;
+const DEFAULT_HEARTBEAT_INTERVAL_MS: u64 = 1000;
+const DEFAULT_DEAD_NODE_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 5000;
+
+/// Server for storing and sharing large binary object. Every setting can
+/// also be set with a CRABSTORE_* environment variable (e.g.
+/// CRABSTORE_SYS_MEMORY=8GiB) or in the TOML file given with --config (e.g.
+/// sys_memory = "8GiB"), flags taking precedence over the environment and
+/// the environment over the file.
+#[derive(Parser, Deserialize, Default)]
+#[command(author, version, long_about = None)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// TOML file to read settings from.
+    #[arg(long)]
+    #[serde(skip)]
+    config: Option<PathBuf>,
+
+    #[arg(short = 's', long)]
+    socket_path: Option<PathBuf>,
+
+    /// Permissions of the socket, in octal (e.g. 660). Defaults to those
+    /// allowed by the umask.
+    #[arg(long)]
+    socket_mode: Option<Mode>,
+
+    /// Memory to hold objects in, e.g. 8GiB or 512MB. Defaults to 1MB.
+    #[arg(short = 'm', long)]
+    sys_memory: Option<Size>,
+
+    /// Directory to create the file holding the objects in, normally on a
+    /// tmpfs such as /dev/shm.
+    #[arg(short = 'd', long)]
+    mem_mapped_dir: Option<PathBuf>,
+
+    /// Reserve and map in all of sys_memory at startup, rather than as
+    /// objects are written.
+    #[arg(long)]
+    prefault: bool,
+
+    /// Directory to spill pinned objects to when memory runs out. Without
+    /// it, pinned objects are never evicted.
+    #[arg(long)]
+    spill_dir: Option<PathBuf>,
+
+    /// The most object bytes to keep in the spill directory, e.g. 100GiB.
+    /// Pinned objects that do not fit stay in memory. Unlimited by default.
+    #[arg(long)]
+    spill_limit: Option<Size>,
+
+    /// Which objects to evict first when memory runs out. Defaults to lru.
+    #[arg(long, value_enum)]
+    eviction_policy: Option<EvictionPolicy>,
+
+    /// On SIGTERM or Ctrl-C, how long to keep serving connected clients so
+    /// that their requests can finish. Defaults to 5000.
+    #[arg(long)]
+    shutdown_timeout_ms: Option<u64>,
+
+    /// Move the objects to the spill directory when shutting down.
+    #[arg(long)]
+    spill_on_shutdown: bool,
+
+    /// Keep the memory pool in a fixed file in mem_mapped_dir, along with a
+    /// journal of the objects in it, so that a store restarted after a
+    /// crash takes over the sealed objects without copying them.
+    #[arg(long)]
+    recoverable: bool,
+
+    /// Keep sealed objects across restarts, in persist_dir or else in a
+    /// crabstore-persist directory inside mem_mapped_dir.
+    #[arg(long)]
+    persist: bool,
+
+    /// Directory to write the objects and their index to on shutdown, and to
+    /// restore them from as they are used after a restart. Implies persist,
+    /// and doubles as the spill directory.
+    #[arg(long)]
+    persist_dir: Option<PathBuf>,
+
+    /// Quota of a namespace clients name when they connect, as
+    /// NAMESPACE:MAX_BYTES:MAX_OBJECTS (e.g. team-a:8GiB:10000, team-b:1GiB
+    /// or team-c::500). Creates past it fail with QuotaExceeded. May be
+    /// given several times. Namespaces without a quota of their own,
+    /// including that of clients naming none, share the quota of the
+    /// namespace *.
+    #[arg(long)]
+    #[serde(deserialize_with = "list")]
+    quota: Vec<String>,
+
+    /// Quota of each client connection, over the objects it created that
+    /// are still in memory, as MAX_BYTES:MAX_OBJECTS (e.g. 1GiB:1000, 1GiB
+    /// or :1000). Unlimited by default.
+    #[arg(long)]
+    client_quota: Option<String>,
+
+    /// Serve Prometheus metrics over HTTP at /metrics on this address, e.g.
+    /// 0.0.0.0:9100.
+    #[arg(long)]
+    metrics_address: Option<String>,
+
+    /// Also accept clients over TCP on this address, e.g. 0.0.0.0:6380.
+    /// They cannot map the store's memory and copy objects instead.
+    #[arg(short = 't', long)]
+    tcp_address: Option<String>,
+
+    /// Address other stores reach this one on, if not --tcp-address (e.g.
+    /// when listening on 0.0.0.0).
+    #[arg(long)]
+    advertise_address: Option<String>,
+
+    /// TCP address of a peer store to pull objects missing here from. May be
+    /// given several times; peers are asked in order, after any the object
+    /// directory knows of.
+    #[arg(long)]
+    #[serde(deserialize_with = "list")]
+    pull_from: Vec<String>,
+
+    /// How many objects may be pulled from peers at the same time. Defaults
+    /// to 4.
+    #[arg(long)]
+    max_concurrent_pulls: Option<usize>,
+
+    /// Host the cluster's object directory in this store, on its TCP
+    /// address.
+    #[arg(long)]
+    embed_directory: bool,
+
+    /// TCP address of the cluster's object directory, hosted by another
+    /// store or by crabstore-directory.
+    #[arg(long)]
+    directory_address: Option<String>,
+
+    /// Name of this store in the cluster. Defaults to its address.
+    #[arg(long)]
+    node_id: Option<String>,
+
+    /// Comma separated TCP addresses of the other stores of the cluster,
+    /// which this one exchanges heartbeats with.
+    #[arg(long, value_delimiter = ',')]
+    #[serde(deserialize_with = "list")]
+    peers: Vec<String>,
+
+    /// Defaults to 1000.
+    #[arg(long)]
+    heartbeat_interval_ms: Option<u64>,
+
+    /// Peers not heard from for this long are considered dead and their
+    /// objects are dropped from the directory. Defaults to 5000.
+    #[arg(long)]
+    dead_node_timeout_ms: Option<u64>,
+
+    /// How many stores of the cluster should hold each object created here,
+    /// this one included, unless its creator asks otherwise. Copies are
+    /// made on the live peers with the most free memory, and made again
+    /// when one of them dies. Defaults to 1.
+    #[arg(long)]
+    replication_factor: Option<u32>,
+
+    /// What to do with the copies held here of reference counted objects
+    /// whose owning store died. Defaults to keep.
+    #[arg(long, value_enum)]
+    lost_object_policy: Option<LostObjectPolicy>,
+
+    /// What to log, in the syntax of RUST_LOG (e.g. info or
+    /// crabstore_store=debug). Defaults to RUST_LOG.
+    #[arg(long)]
+    log: Option<String>,
+}
+
+/// Everything the store is started with.
+pub struct Config {
+    pub store: StoreConfig,
+    pub cluster: ClusterConfig,
+    /// Log filter overriding RUST_LOG, if set.
+    pub log: Option<String>,
+    /// What to warn about once logging is set up.
+    pub warnings: Vec<String>,
+}
+
+/// A setting that is missing or has a bad value.
+#[derive(Debug)]
+pub struct ConfigError {
+    // The setting, along with where it came from.
+    key: String,
+    message: String,
+}
+
+impl ConfigError {
+    fn new(key: impl Into<String>, message: impl fmt::Display) -> Self {
+        ConfigError {
+            key: key.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid setting {}: {}", self.key, self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Settings {
+    /// Reads the command line, the environment and the config file, and
+    /// checks the settings they make up together.
+    pub fn load() -> Result<Config, ConfigError> {
+        Settings::load_from(Settings::parse(), std::env::vars())
+    }
+
+    // Does what `load` does, with the given flags and environment.
+    fn load_from(
+        flags: Settings,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Config, ConfigError> {
+        let env: Vec<(String, String)> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .collect();
+        let config_file = flags.config.clone().or_else(|| {
+            env.iter()
+                .find(|(name, _)| name == "CRABSTORE_CONFIG")
+                .map(|(_, path)| PathBuf::from(path))
+        });
+
+        let mut settings = match &config_file {
+            Some(path) => Settings::from_file(path)?,
+            None => Settings::default(),
+        };
+        let command = Settings::command();
+        let mut warnings = Vec::new();
+        for (name, value) in env {
+            if name == "CRABSTORE_CONFIG" {
+                continue;
+            }
+            let key = name[ENV_PREFIX.len()..].to_ascii_lowercase();
+            if !command
+                .get_arguments()
+                .any(|arg| arg.get_id() == key.as_str())
+            {
+                warnings.push(format!("Ignoring {}, which is not a setting", name));
+                continue;
+            }
+            let value = from_env(&key, &value).map_err(|e| ConfigError::new(&name, e))?;
+            settings = settings.merge(value);
+        }
+        let mut config = settings.merge(flags).resolve()?;
+        config.warnings = warnings;
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let origin = |key: &str| format!("{} in {}", key, path.display());
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::new(origin("config"), e))?;
+        let table: toml::Table = text
+            .parse()
+            .map_err(|e: toml::de::Error| ConfigError::new(origin("config"), e))?;
+        let mut settings = Settings::default();
+        for (key, value) in table {
+            let mut single = toml::Table::new();
+            single.insert(key.clone(), value);
+            let value = toml::Value::Table(single)
+                .try_into()
+                .map_err(|e: toml::de::Error| ConfigError::new(origin(&key), e.message()))?;
+            settings = settings.merge(value);
+        }
+        Ok(settings)
+    }
+
+    // The settings of `self`, overridden by those set in `over`.
+    fn merge(self, over: Settings) -> Settings {
+        let list = |base: Vec<String>, over: Vec<String>| match over.is_empty() {
+            true => base,
+            false => over,
+        };
+        Settings {
+            config: over.config.or(self.config),
+            socket_path: over.socket_path.or(self.socket_path),
+            socket_mode: over.socket_mode.or(self.socket_mode),
+            sys_memory: over.sys_memory.or(self.sys_memory),
+            mem_mapped_dir: over.mem_mapped_dir.or(self.mem_mapped_dir),
+            prefault: over.prefault || self.prefault,
+            spill_dir: over.spill_dir.or(self.spill_dir),
+            spill_limit: over.spill_limit.or(self.spill_limit),
+            eviction_policy: over.eviction_policy.or(self.eviction_policy),
+            shutdown_timeout_ms: over.shutdown_timeout_ms.or(self.shutdown_timeout_ms),
+            spill_on_shutdown: over.spill_on_shutdown || self.spill_on_shutdown,
+            quota: list(self.quota, over.quota),
+            client_quota: over.client_quota.or(self.client_quota),
+            metrics_address: over.metrics_address.or(self.metrics_address),
+            recoverable: over.recoverable || self.recoverable,
+            persist: over.persist || self.persist,
+            persist_dir: over.persist_dir.or(self.persist_dir),
+            tcp_address: over.tcp_address.or(self.tcp_address),
+            advertise_address: over.advertise_address.or(self.advertise_address),
+            pull_from: list(self.pull_from, over.pull_from),
+            max_concurrent_pulls: over.max_concurrent_pulls.or(self.max_concurrent_pulls),
+            embed_directory: over.embed_directory || self.embed_directory,
+            directory_address: over.directory_address.or(self.directory_address),
+            node_id: over.node_id.or(self.node_id),
+            peers: list(self.peers, over.peers),
+            heartbeat_interval_ms: over.heartbeat_interval_ms.or(self.heartbeat_interval_ms),
+            dead_node_timeout_ms: over.dead_node_timeout_ms.or(self.dead_node_timeout_ms),
+            replication_factor: over.replication_factor.or(self.replication_factor),
+            lost_object_policy: over.lost_object_policy.or(self.lost_object_policy),
+            log: over.log.or(self.log),
+        }
+    }
+
+    fn resolve(self) -> Result<Config, ConfigError> {
+        let required = |value: Option<PathBuf>, key: &str| {
+            value.ok_or_else(|| {
+                ConfigError::new(
+                    key,
+                    format!(
+                        "missing, pass --{}, set {}{} or add {} to the config file",
+                        key.replace('_', "-"),
+                        ENV_PREFIX,
+                        key.to_ascii_uppercase(),
+                        key
+                    ),
+                )
+            })
+        };
+        let sys_memory = self.sys_memory.map_or(DEFAULT_SYS_MEMORY, |size| size.0);
+        if sys_memory == 0 {
+            return Err(ConfigError::new("sys_memory", "must not be 0"));
+        }
+        let replication_factor = self.replication_factor.unwrap_or(1);
+        if replication_factor == 0 {
+            return Err(ConfigError::new("replication_factor", "must be at least 1"));
+        }
+        let max_concurrent_pulls = self
+            .max_concurrent_pulls
+            .unwrap_or(DEFAULT_MAX_CONCURRENT_PULLS);
+        if max_concurrent_pulls == 0 {
+            return Err(ConfigError::new(
+                "max_concurrent_pulls",
+                "must be at least 1",
+            ));
+        }
+        let heartbeat_interval_ms = self
+            .heartbeat_interval_ms
+            .unwrap_or(DEFAULT_HEARTBEAT_INTERVAL_MS);
+        if heartbeat_interval_ms == 0 {
+            return Err(ConfigError::new("heartbeat_interval_ms", "must not be 0"));
+        }
+        if self.embed_directory && self.directory_address.is_some() {
+            return Err(ConfigError::new(
+                "directory_address",
+                "cannot be set along with embed_directory",
+            ));
+        }
+        let mem_mapped_dir = required(self.mem_mapped_dir, "mem_mapped_dir")?;
+        let persist = self.persist || self.persist_dir.is_some();
+        let persist_dir = self
+            .persist_dir
+            .or_else(|| persist.then(|| mem_mapped_dir.join("crabstore-persist")));
+        if persist_dir.is_some() && self.spill_dir.is_some() {
+            return Err(ConfigError::new(
+                "spill_dir",
+                "cannot be set along with persist, which spills to its persist_dir",
+            ));
+        }
+        let spill_dir = self.spill_dir.or(persist_dir);
+        if self.spill_limit.is_some() && spill_dir.is_none() {
+            return Err(ConfigError::new("spill_limit", "needs a spill_dir"));
+        }
+        if self.spill_on_shutdown && spill_dir.is_none() {
+            return Err(ConfigError::new("spill_on_shutdown", "needs a spill_dir"));
+        }
+        let mut quotas = HashMap::new();
+        for quota in &self.quota {
+            let (namespace, quota) =
+                parse_quota(quota).map_err(|e| ConfigError::new("quota", e))?;
+            if quotas.insert(namespace.clone(), quota).is_some() {
+                return Err(ConfigError::new(
+                    "quota",
+                    format!("namespace {:?} is given twice", namespace),
+                ));
+            }
+        }
+        let client_quota = match &self.client_quota {
+            Some(quota) => {
+                parse_client_quota(quota).map_err(|e| ConfigError::new("client_quota", e))?
+            }
+            None => Quota::default(),
+        };
+
+        Ok(Config {
+            store: StoreConfig {
+                socket_path: required(self.socket_path, "socket_path")?,
+                socket_mode: self.socket_mode.map(|mode| mode.0),
+                sys_memory,
+                mem_mapped_dir,
+                prefault: self.prefault,
+                spill_dir,
+                spill_limit: self.spill_limit.map(|size| size.0),
+                eviction_policy: self.eviction_policy.unwrap_or_default(),
+                shutdown_timeout: Duration::from_millis(
+                    self.shutdown_timeout_ms
+                        .unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_MS),
+                ),
+                spill_on_shutdown: self.spill_on_shutdown,
+                metrics_address: self.metrics_address,
+                recoverable: self.recoverable,
+                persist,
+                quotas,
+                client_quota,
+            },
+            cluster: ClusterConfig {
+                tcp_address: self.tcp_address,
+                advertise_address: self.advertise_address,
+                pull_from: self.pull_from,
+                max_concurrent_pulls,
+                embed_directory: self.embed_directory,
+                directory_address: self.directory_address,
+                node_id: self.node_id,
+                peers: self.peers,
+                heartbeat_interval: Duration::from_millis(heartbeat_interval_ms),
+                dead_node_timeout: Duration::from_millis(
+                    self.dead_node_timeout_ms
+                        .unwrap_or(DEFAULT_DEAD_NODE_TIMEOUT_MS),
+                ),
+                replication_factor,
+                lost_object_policy: self.lost_object_policy.unwrap_or(LostObjectPolicy::Keep),
+            },
+            log: self.log,
+            warnings: Vec::new(),
+        })
+    }
+}
+
+// Reads a quota written NAMESPACE:MAX_BYTES:MAX_OBJECTS, where either limit
+// may be left out, but not both.
+fn parse_quota(s: &str) -> Result<(String, Quota), String> {
+    let invalid = || {
+        format!(
+            "invalid quota {:?}, expected e.g. team-a:8GiB:10000, team-a:8GiB or team-a::10000",
+            s
+        )
+    };
+    let (namespace, limits) = s.trim().split_once(':').ok_or_else(invalid)?;
+    if namespace.is_empty() {
+        return Err(format!(
+            "invalid quota {:?}, clients naming no namespace are in {}",
+            s, DEFAULT_NAMESPACE
+        ));
+    }
+    Ok((namespace.to_string(), parse_limits(limits, invalid)?))
+}
+
+// Reads a client quota written MAX_BYTES:MAX_OBJECTS, where either limit may
+// be left out, but not both.
+fn parse_client_quota(s: &str) -> Result<Quota, String> {
+    parse_limits(s, || {
+        format!(
+            "invalid quota {:?}, expected e.g. 1GiB:1000, 1GiB or :1000",
+            s
+        )
+    })
+}
+
+// Reads the MAX_BYTES:MAX_OBJECTS limits of a quota, failing with `invalid`
+// if they do not parse or are both left out.
+fn parse_limits(s: &str, invalid: impl Fn() -> String) -> Result<Quota, String> {
+    let mut parts = s.trim().splitn(2, ':');
+    let max_bytes = match parts.next().map(str::trim) {
+        None | Some("") => None,
+        Some(size) => Some(size.parse::<Size>()?.0),
+    };
+    let max_objects = match parts.next().map(str::trim) {
+        None | Some("") => None,
+        Some(count) => Some(count.parse::<u64>().map_err(|_| invalid())?),
+    };
+    if max_bytes.is_none() && max_objects.is_none() {
+        return Err(invalid());
+    }
+    Ok(Quota {
+        max_bytes,
+        max_objects,
+    })
+}
+
+// Reads the setting `key` from the text of an environment variable. Most
+// settings are strings; the others are read as they would be written in the
+// config file, so that e.g. 4 is a number and true a boolean.
+fn from_env(key: &str, value: &str) -> Result<Settings, String> {
+    let parse = |value: toml::Value| {
+        let mut single = toml::Table::new();
+        single.insert(key.to_string(), value);
+        toml::Value::Table(single)
+            .try_into::<Settings>()
+            .map_err(|e| e.message().to_string())
+    };
+    parse(toml::Value::String(value.to_string())).or_else(|e| {
+        let typed = format!("value = {}", value)
+            .parse::<toml::Table>()
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+        match typed {
+            Some(typed) => parse(typed).map_err(|_| e),
+            None => Err(e),
+        }
+    })
+}
+
+// Lists are written as arrays in the config file, and comma separated in
+// the environment.
+fn list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    struct ListVisitor;
+
+    impl<'de> Visitor<'de> for ListVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a list of strings or a comma separated string")
+        }
+
+        fn visit_str<E: de::Error>(self, joined: &str) -> Result<Vec<String>, E> {
+            Ok(joined
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<String>, A::Error> {
+            let mut items = Vec::new();
+            while let Some(item) = seq.next_element()? {
+                items.push(item);
+            }
+            Ok(items)
+        }
+    }
+
+    deserializer.deserialize_any(ListVisitor)
+}
+
+// Reads values written either as a number or as a string to parse, such as
+// sizes and modes.
+struct NumberOrText<T>(&'static str, fn(u64) -> Result<T, String>);
+
+impl<'de, T: FromStr<Err = String>> Visitor<'de> for NumberOrText<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+
+    fn visit_u64<E: de::Error>(self, number: u64) -> Result<T, E> {
+        (self.1)(number).map_err(E::custom)
+    }
+
+    fn visit_i64<E: de::Error>(self, number: i64) -> Result<T, E> {
+        match u64::try_from(number) {
+            Ok(number) => self.visit_u64(number),
+            Err(_) => Err(E::invalid_value(de::Unexpected::Signed(number), &self)),
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, text: &str) -> Result<T, E> {
+        text.parse().map_err(E::custom)
+    }
+}
+
+/// A number of bytes, written either as a plain number or with a unit such
+/// as 512MB (powers of 1000) or 8GiB (powers of 1024).
+#[derive(Debug, Clone, Copy)]
+struct Size(u64);
+
+impl FromStr for Size {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid size {:?}, expected e.g. 8GiB or 512MB", s);
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1_000,
+            "m" | "mb" => 1_000_000,
+            "g" | "gb" => 1_000_000_000,
+            "t" | "tb" => 1_000_000_000_000,
+            "ki" | "kib" => 1 << 10,
+            "mi" | "mib" => 1 << 20,
+            "gi" | "gib" => 1 << 30,
+            "ti" | "tib" => 1 << 40,
+            _ => return Err(invalid()),
+        };
+        let bytes = match number.parse::<u64>() {
+            Ok(number) => number.checked_mul(multiplier),
+            Err(_) => number
+                .parse::<f64>()
+                .ok()
+                .map(|number| number * multiplier as f64)
+                .filter(|bytes| bytes.is_finite() && *bytes < u64::MAX as f64)
+                .map(|bytes| bytes as u64),
+        };
+        bytes.map(Size).ok_or_else(invalid)
+    }
+}
+
+impl<'de> Deserialize<'de> for Size {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NumberOrText("a size such as 8GiB", |bytes| Ok(Size(bytes))))
+    }
+}
+
+/// Unix permission bits, written in octal like chmod takes them. In the
+/// config file either a string ("660") or an octal number (0o660).
+#[derive(Debug, Clone, Copy)]
+struct Mode(u32);
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        u32::from_str_radix(s.trim(), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .map(Mode)
+            .ok_or_else(|| format!("invalid mode {:?}, expected octal e.g. 660", s))
+    }
+}
+
+impl<'de> Deserialize<'de> for Mode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(NumberOrText("an octal mode such as 660", |bits| {
+            match bits <= 0o7777 {
+                true => Ok(Mode(bits as u32)),
+                false => Err(format!("invalid mode {:o}, expected e.g. 0o660", bits)),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn size(s: &str) -> Result<u64, String> {
+        s.parse::<Size>().map(|size| size.0)
+    }
+
+    fn flags(args: &[&str]) -> Settings {
+        Settings::try_parse_from([&["crabstore-store"], args].concat()).unwrap()
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn sizes_with_units() {
+        assert_eq!(size("512"), Ok(512));
+        assert_eq!(size("512b"), Ok(512));
+        assert_eq!(size("2k"), Ok(2_000));
+        assert_eq!(size("512MB"), Ok(512_000_000));
+        assert_eq!(size(" 3 gb "), Ok(3_000_000_000));
+        assert_eq!(size("1Ki"), Ok(1 << 10));
+        assert_eq!(size("8GiB"), Ok(8 << 30));
+        assert_eq!(size("2TiB"), Ok(2 << 40));
+        assert!(size("8XB").is_err());
+        assert!(size("GiB").is_err());
+        assert!(size("").is_err());
+        assert!(size("-1MB").is_err());
+    }
+
+    #[test]
+    fn fractional_sizes() {
+        assert_eq!(size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(size("0.5k"), Ok(500));
+        assert_eq!(size(".25MiB"), Ok(1 << 18));
+        assert!(size("1.2.3MB").is_err());
+    }
+
+    #[test]
+    fn sizes_that_overflow() {
+        assert_eq!(size("18446744073709551615"), Ok(u64::MAX));
+        assert!(size("18446744073709551616").is_err());
+        assert!(size("20000000000GB").is_err());
+        assert!(size("16777216TiB").is_err());
+        assert!(size("16777215.5TiB").is_ok());
+        assert!(size("99999999999999999999.5TiB").is_err());
+    }
+
+    #[test]
+    fn quotas() {
+        let quota = |max_bytes, max_objects| Quota {
+            max_bytes,
+            max_objects,
+        };
+        assert_eq!(
+            parse_quota("team-a:8GiB:10000"),
+            Ok(("team-a".to_string(), quota(Some(8 << 30), Some(10000))))
+        );
+        assert_eq!(
+            parse_quota("team-b:1GiB"),
+            Ok(("team-b".to_string(), quota(Some(1 << 30), None)))
+        );
+        assert_eq!(
+            parse_quota("*::500"),
+            Ok(("*".to_string(), quota(None, Some(500))))
+        );
+        assert!(parse_quota("team-a").is_err());
+        assert!(parse_quota("team-a:").is_err());
+        assert!(parse_quota("team-a::").is_err());
+        assert!(parse_quota("team-a:lots:1").is_err());
+        assert!(parse_quota("team-a:1GiB:many").is_err());
+        assert!(parse_quota(":1GiB").is_err());
+
+        assert_eq!(
+            parse_client_quota("1GiB:1000"),
+            Ok(quota(Some(1 << 30), Some(1000)))
+        );
+        assert_eq!(parse_client_quota("1GiB"), Ok(quota(Some(1 << 30), None)));
+        assert_eq!(parse_client_quota(":1000"), Ok(quota(None, Some(1000))));
+        assert!(parse_client_quota("").is_err());
+        assert!(parse_client_quota(":").is_err());
+        assert!(parse_client_quota("1GiB:1000:1").is_err());
+    }
+
+    #[test]
+    fn flags_override_environment_override_file() {
+        let dir = TempDir::new();
+        let file = dir.path().join("crabstore.toml");
+        fs::write(
+            &file,
+            r#"
+                socket_path = "/from/file"
+                mem_mapped_dir = "/dev/shm"
+                sys_memory = "1MB"
+                heartbeat_interval_ms = 100
+                prefault = true
+                pull_from = ["file-a:1", "file-b:1"]
+                quota = ["a:1MB"]
+            "#,
+        )
+        .unwrap();
+        let file = file.to_str().unwrap();
+
+        let config = Settings::load_from(flags(&["--config", file]), env(&[])).unwrap();
+        assert_eq!(config.store.socket_path, PathBuf::from("/from/file"));
+        assert_eq!(config.store.sys_memory, 1_000_000);
+        assert_eq!(
+            config.cluster.heartbeat_interval,
+            Duration::from_millis(100)
+        );
+        assert!(config.store.prefault);
+        assert_eq!(config.cluster.pull_from, ["file-a:1", "file-b:1"]);
+        assert_eq!(config.store.quotas.len(), 1);
+
+        let vars = env(&[
+            ("CRABSTORE_CONFIG", file),
+            ("CRABSTORE_SYS_MEMORY", "2MB"),
+            ("CRABSTORE_HEARTBEAT_INTERVAL_MS", "200"),
+            ("CRABSTORE_PULL_FROM", "env-a:1, env-b:1"),
+            ("CRABSTORE_QUOTA", "a:2MB,b::1"),
+        ]);
+        let config = Settings::load_from(flags(&[]), vars.clone()).unwrap();
+        assert_eq!(config.store.socket_path, PathBuf::from("/from/file"));
+        assert_eq!(config.store.sys_memory, 2_000_000);
+        assert_eq!(
+            config.cluster.heartbeat_interval,
+            Duration::from_millis(200)
+        );
+        assert_eq!(config.cluster.pull_from, ["env-a:1", "env-b:1"]);
+        assert_eq!(config.store.quotas.len(), 2);
+
+        let config = Settings::load_from(
+            flags(&["-m", "3MB", "-s", "/from/flag", "--pull-from", "flag:1"]),
+            vars,
+        )
+        .unwrap();
+        assert_eq!(config.store.socket_path, PathBuf::from("/from/flag"));
+        assert_eq!(config.store.sys_memory, 3_000_000);
+        assert_eq!(
+            config.cluster.heartbeat_interval,
+            Duration::from_millis(200)
+        );
+        assert_eq!(config.cluster.pull_from, ["flag:1"]);
+        assert!(config.store.prefault);
+    }
+
+    #[test]
+    fn unknown_environment_variables() {
+        let vars = env(&[
+            ("CRABSTORE_SOCKET_PATH", "/tmp/sock"),
+            ("CRABSTORE_MEM_MAPPED_DIR", "/dev/shm"),
+            ("CRABSTORE_SOMETHING_ELSE", "1"),
+            ("OTHER_SYS_MEMORY", "nonsense"),
+        ]);
+        let config = Settings::load_from(flags(&[]), vars).unwrap();
+        assert_eq!(config.store.socket_path, PathBuf::from("/tmp/sock"));
+        assert_eq!(config.store.sys_memory, DEFAULT_SYS_MEMORY);
+        assert_eq!(config.warnings.len(), 1);
+        assert!(config.warnings[0].contains("CRABSTORE_SOMETHING_ELSE"));
+
+        let vars = env(&[("CRABSTORE_SYS_MEMORY", "nonsense")]);
+        let error = Settings::load_from(flags(&["-s", "/tmp/sock", "-d", "/dev/shm"]), vars)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("CRABSTORE_SYS_MEMORY"));
+    }
+
+    #[test]
+    fn unknown_keys_in_the_file() {
+        let dir = TempDir::new();
+        let file = dir.path().join("crabstore.toml");
+        fs::write(&file, "sys_memroy = \"1MB\"\n").unwrap();
+        let error = Settings::load_from(flags(&["--config", file.to_str().unwrap()]), env(&[]))
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("sys_memroy"));
+    }
+}
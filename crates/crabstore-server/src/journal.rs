@@ -0,0 +1,416 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::names::{read_name, write_name};
+
+// A store whose pool is recoverable records where its sealed objects are in
+// the pool file, so that after a crash the next store can map the same file
+// and take them over where they are. The objects' headers are in the pool
+// already, the journal only says which allocations hold one.
+//
+// It is appended to as objects are sealed and freed, and rewritten with just
+// the live entries when it grows much larger than them. Records are not
+// synced: the pool lives in memory, so the journal only has to survive the
+// store process, not the machine.
+//
+// Layout (all integers little endian):
+//
+//   magic    u32
+//   version  u16
+//   then records, each starting with its kind:
+//     sealed  u8 = 1, offset u64, size u64, device_num i32,
+//             flags u8 (primary, pinned, has an owner node, has a namespace),
+//             owner_node u16 length then UTF-8, if flagged
+//             namespace u16 length then UTF-8, if flagged
+//     freed   u8 = 2, offset u64
+//
+// A record cut short by a crash ends the journal.
+
+/// Name of the journal of a recoverable store, next to its pool file.
+pub const JOURNAL_FILE: &str = "crabstore-journal";
+
+const JOURNAL_MAGIC: u32 = u32::from_le_bytes(*b"CRJL");
+const JOURNAL_VERSION: u16 = 1;
+
+const RECORD_SEALED: u8 = 1;
+const RECORD_FREED: u8 = 2;
+
+const FLAG_PRIMARY: u8 = 1;
+const FLAG_PINNED: u8 = 1 << 1;
+const FLAG_OWNER_NODE: u8 = 1 << 2;
+const FLAG_NAMESPACE: u8 = 1 << 3;
+
+// Rewrite the journal once it has this many more records than live entries.
+const COMPACT_SLACK: u64 = 4096;
+
+/// A sealed object in the pool, as recorded in the journal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub size: u64,
+    pub device_num: i32,
+    pub primary: bool,
+    pub pinned: bool,
+    pub owner_node: Option<String>,
+    /// The namespace charged for the object.
+    pub namespace: Option<String>,
+}
+
+/// The journal of the sealed objects in a recoverable pool.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    // Live entries by the offset of their allocation.
+    live: HashMap<u64, JournalEntry>,
+    records: u64,
+}
+
+impl Journal {
+    /// Opens the journal at `path`, reading the entries a previous store
+    /// left in it, or starts an empty one.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let live = match File::open(path) {
+            Ok(file) => read_journal(BufReader::new(file)).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("could not read the journal {}: {}", path.display(), e),
+                )
+            })?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Journal {
+            path: path.to_path_buf(),
+            file: rewrite(path, &live)?,
+            records: live.len() as u64,
+            live,
+        })
+    }
+
+    /// The live entries, by the offset of their allocation.
+    pub fn entries(&self) -> impl Iterator<Item = (u64, &JournalEntry)> {
+        self.live.iter().map(|(offset, entry)| (*offset, entry))
+    }
+
+    /// Records the object sealed at `offset`, or its new pin or owner.
+    /// Fails, leaving the journal as it was, if a name in `entry` is too
+    /// long to record.
+    pub fn sealed(&mut self, offset: u64, entry: JournalEntry) -> io::Result<()> {
+        let mut record = vec![RECORD_SEALED];
+        encode_entry(&mut record, offset, &entry)?;
+        self.live.insert(offset, entry);
+        self.append(&record);
+        Ok(())
+    }
+
+    /// Records that the allocation at `offset` no longer holds an object.
+    pub fn freed(&mut self, offset: u64) {
+        if self.live.remove(&offset).is_none() {
+            return;
+        }
+        let mut record = vec![RECORD_FREED];
+        record.extend_from_slice(&offset.to_le_bytes());
+        self.append(&record);
+    }
+
+    fn append(&mut self, record: &[u8]) {
+        if let Err(e) = self.file.write_all(record) {
+            warn!(
+                "Writing to the journal {} failed: {}",
+                self.path.display(),
+                e
+            );
+        }
+        self.records += 1;
+        if self.records > 2 * self.live.len() as u64 + COMPACT_SLACK {
+            match rewrite(&self.path, &self.live) {
+                Ok(file) => {
+                    debug!(
+                        "Compacted the journal from {} to {} records",
+                        self.records,
+                        self.live.len()
+                    );
+                    self.file = file;
+                    self.records = self.live.len() as u64;
+                }
+                Err(e) => warn!(
+                    "Compacting the journal {} failed: {}",
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+fn encode_entry(buf: &mut Vec<u8>, offset: u64, entry: &JournalEntry) -> io::Result<()> {
+    buf.extend_from_slice(&offset.to_le_bytes());
+    buf.extend_from_slice(&entry.size.to_le_bytes());
+    buf.extend_from_slice(&entry.device_num.to_le_bytes());
+    let mut flags = 0;
+    if entry.primary {
+        flags |= FLAG_PRIMARY;
+    }
+    if entry.pinned {
+        flags |= FLAG_PINNED;
+    }
+    if entry.owner_node.is_some() {
+        flags |= FLAG_OWNER_NODE;
+    }
+    if entry.namespace.is_some() {
+        flags |= FLAG_NAMESPACE;
+    }
+    buf.push(flags);
+    if let Some(owner_node) = &entry.owner_node {
+        write_name(buf, owner_node, "owner node name")?;
+    }
+    if let Some(namespace) = &entry.namespace {
+        write_name(buf, namespace, "namespace")?;
+    }
+    Ok(())
+}
+
+// Writes a journal holding just `live`, and returns it opened for appending.
+fn rewrite(path: &Path, live: &HashMap<u64, JournalEntry>) -> io::Result<File> {
+    let temp = path.with_extension("tmp");
+    let mut file = BufWriter::new(File::create(&temp)?);
+    file.write_all(&JOURNAL_MAGIC.to_le_bytes())?;
+    file.write_all(&JOURNAL_VERSION.to_le_bytes())?;
+    let mut record = Vec::new();
+    for (offset, entry) in live {
+        record.clear();
+        record.push(RECORD_SEALED);
+        encode_entry(&mut record, *offset, entry)?;
+        file.write_all(&record)?;
+    }
+    file.flush()?;
+    drop(file);
+    fs::rename(&temp, path)?;
+    OpenOptions::new().append(true).open(path)
+}
+
+fn read_journal(mut file: impl Read) -> io::Result<HashMap<u64, JournalEntry>> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut buf = [0u8; 8];
+    file.read_exact(&mut buf[..4])?;
+    if u32::from_le_bytes(buf[..4].try_into().unwrap()) != JOURNAL_MAGIC {
+        return Err(invalid("not a journal".to_string()));
+    }
+    file.read_exact(&mut buf[..2])?;
+    let version = u16::from_le_bytes(buf[..2].try_into().unwrap());
+    if version != JOURNAL_VERSION {
+        return Err(invalid(format!("unsupported version {}", version)));
+    }
+
+    let mut live = HashMap::new();
+    loop {
+        match read_record(&mut file, &mut live) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                warn!("The journal ends in a partial record, ignoring it");
+                break;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(live)
+}
+
+// Applies the next record to `live`. Returns false at the end of the journal.
+fn read_record(file: &mut impl Read, live: &mut HashMap<u64, JournalEntry>) -> io::Result<bool> {
+    let mut kind = [0u8; 1];
+    if file.read(&mut kind)? == 0 {
+        return Ok(false);
+    }
+    let read_u64 = |file: &mut dyn Read| -> io::Result<u64> {
+        let mut buf = [0u8; 8];
+        file.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    };
+    let offset = read_u64(file)?;
+    match kind[0] {
+        RECORD_SEALED => {
+            let size = read_u64(file)?;
+            let mut buf = [0u8; 5];
+            file.read_exact(&mut buf)?;
+            let device_num = i32::from_le_bytes(buf[..4].try_into().unwrap());
+            let flags = buf[4];
+            let owner_node = (flags & FLAG_OWNER_NODE != 0)
+                .then(|| read_name(file, "owner node name"))
+                .transpose()?;
+            let namespace = (flags & FLAG_NAMESPACE != 0)
+                .then(|| read_name(file, "namespace"))
+                .transpose()?;
+            live.insert(
+                offset,
+                JournalEntry {
+                    size,
+                    device_num,
+                    primary: flags & FLAG_PRIMARY != 0,
+                    pinned: flags & FLAG_PINNED != 0,
+                    owner_node,
+                    namespace,
+                },
+            );
+        }
+        RECORD_FREED => {
+            live.remove(&offset);
+        }
+        kind => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unknown record kind {}", kind),
+            ))
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn entry(size: u64) -> JournalEntry {
+        JournalEntry {
+            size,
+            device_num: 0,
+            primary: true,
+            pinned: false,
+            owner_node: None,
+            namespace: None,
+        }
+    }
+
+    fn entries(journal: &Journal) -> Vec<(u64, JournalEntry)> {
+        let mut entries: Vec<_> = journal
+            .entries()
+            .map(|(offset, entry)| (offset, entry.clone()))
+            .collect();
+        entries.sort_by_key(|(offset, _)| *offset);
+        entries
+    }
+
+    #[test]
+    fn reopen() {
+        let dir = TempDir::new();
+        let path = dir.path().join(JOURNAL_FILE);
+        let named = JournalEntry {
+            size: 256,
+            device_num: -1,
+            primary: false,
+            pinned: true,
+            owner_node: Some("node-2".to_string()),
+            namespace: Some("tenant".to_string()),
+        };
+        let mut journal = Journal::open(&path).unwrap();
+        journal.sealed(0, entry(128)).unwrap();
+        journal.sealed(128, entry(64)).unwrap();
+        journal.sealed(192, named.clone()).unwrap();
+        journal.freed(128);
+        journal.freed(4096);
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(entries(&journal), vec![(0, entry(128)), (192, named)]);
+    }
+
+    #[test]
+    fn name_too_long() {
+        let dir = TempDir::new();
+        let path = dir.path().join(JOURNAL_FILE);
+        let mut journal = Journal::open(&path).unwrap();
+        let named = JournalEntry {
+            namespace: Some("a".repeat(u16::MAX as usize + 1)),
+            ..entry(64)
+        };
+        let err = journal.sealed(0, named).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(journal.entries().count(), 0);
+        journal.sealed(64, entry(64)).unwrap();
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(entries(&journal), vec![(64, entry(64))]);
+    }
+
+    #[test]
+    fn truncated_record() {
+        let dir = TempDir::new();
+        let path = dir.path().join(JOURNAL_FILE);
+        let named = JournalEntry {
+            namespace: Some("tenant".to_string()),
+            ..entry(64)
+        };
+        let mut journal = Journal::open(&path).unwrap();
+        journal.sealed(0, entry(128)).unwrap();
+        journal.sealed(128, named.clone()).unwrap();
+        drop(journal);
+
+        // Cut into the last record, down to just its kind.
+        let len = fs::metadata(&path).unwrap().len();
+        let last = 1 + 8 + 8 + 4 + 1 + 2 + "tenant".len() as u64;
+        for cut in [1, 4, 10, last - 1] {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.set_len(len - cut).unwrap();
+            drop(file);
+            let journal = Journal::open(&path).unwrap();
+            assert_eq!(entries(&journal), vec![(0, entry(128))]);
+            drop(journal);
+
+            // Reopening rewrote the journal without the partial record.
+            let mut journal = Journal::open(&path).unwrap();
+            journal.sealed(128, named.clone()).unwrap();
+            drop(journal);
+        }
+    }
+
+    #[test]
+    fn not_a_journal() {
+        let dir = TempDir::new();
+        let path = dir.path().join(JOURNAL_FILE);
+        for contents in [
+            &b"CRJ"[..],
+            b"CRJX\x01\x00",
+            b"CRJL\x00\x00",
+            b"CRJL\x02\x00",
+        ] {
+            fs::write(&path, contents).unwrap();
+            assert!(Journal::open(&path).is_err());
+        }
+
+        let mut contents = b"CRJL\x01\x00".to_vec();
+        contents.push(3);
+        contents.extend_from_slice(&0u64.to_le_bytes());
+        fs::write(&path, contents).unwrap();
+        let err = Journal::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compaction() {
+        let dir = TempDir::new();
+        let path = dir.path().join(JOURNAL_FILE);
+        let mut journal = Journal::open(&path).unwrap();
+        journal.sealed(0, entry(64)).unwrap();
+        // The last of these records is the one too many for a single live
+        // entry, and leaves only that entry in the journal.
+        for i in 1..=COMPACT_SLACK / 2 + 1 {
+            journal.sealed(i * 64, entry(64)).unwrap();
+            journal.freed(i * 64);
+        }
+        assert_eq!(journal.records, 1);
+        journal.sealed(64, entry(128)).unwrap();
+        let record = 1 + 8 + 8 + 4 + 1;
+        assert_eq!(fs::metadata(&path).unwrap().len(), 4 + 2 + 2 * record);
+        drop(journal);
+
+        let journal = Journal::open(&path).unwrap();
+        assert_eq!(entries(&journal), vec![(0, entry(64)), (64, entry(128))]);
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
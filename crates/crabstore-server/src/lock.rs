@@ -0,0 +1,35 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Locking for the state connections share. A connection task that panics
+/// while holding a lock poisons it; the other connections keep using the
+/// state rather than panicking in turn, so one bad request cannot take the
+/// whole store down.
+pub trait LockExt<T> {
+    fn locked(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn locked(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn locks_a_poisoned_mutex() {
+        let mutex = Arc::new(Mutex::new(1));
+        let poisoner = mutex.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poisoning the lock");
+        })
+        .join();
+        assert!(mutex.is_poisoned());
+        *mutex.locked() += 1;
+        assert_eq!(*mutex.locked(), 2);
+    }
+}
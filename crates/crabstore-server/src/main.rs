@@ -1,29 +1,54 @@
 mod allocator;
+mod chunked;
+mod cluster;
+mod config;
+mod journal;
+mod lock;
+mod membership;
+mod metrics;
+mod names;
+mod object_store;
+mod pull_manager;
+mod push;
+mod quota;
+mod refcount;
+mod replication;
 mod runner;
+mod spill;
 mod store;
+#[cfg(test)]
+mod test_util;
 
-use std::path::PathBuf;
-
-use clap::Parser;
-
-#[derive(Parser)]
-#[command(author, version, about, long_about = None)]
-struct CliArgs {
-    #[arg(short = 's', long)]
-    socket_path: PathBuf,
-
-    #[arg(short = 'm', long, default_value_t = 1000000)]
-    sys_memory: i64,
-
-    #[arg(short = 'd', long)]
-    mem_mapped_dir: PathBuf,
-}
+use log::{error, warn};
 
 fn main() {
-    env_logger::init();
-    let args = CliArgs::parse();
-
-    let runner = runner::Runner::new(args.socket_path, args.sys_memory, args.mem_mapped_dir);
-
-    runner.start();
+    let config = match config::Settings::load() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    let mut logger = match &config.log {
+        Some(filter) => {
+            let mut logger = env_logger::Builder::new();
+            logger.parse_filters(filter);
+            logger
+        }
+        None => env_logger::Builder::from_default_env(),
+    };
+    logger.init();
+    for warning in &config.warnings {
+        warn!("{}", warning);
+    }
+
+    let runner = match runner::Runner::new(config.store, config.cluster) {
+        Ok(runner) => runner,
+        Err(e) => {
+            error!("Failed to initialize the store: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    std::process::exit(runner.start());
 }
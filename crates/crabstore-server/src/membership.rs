@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::SinkExt;
+use log::{debug, info, warn};
+use tokio::net::TcpStream;
+use tokio::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+
+use crate::cluster::Directory;
+use crate::lock::LockExt;
+use crate::object_store::ObjectStore;
+use crate::refcount::References;
+
+struct Member {
+    node_id: String,
+    memory_capacity: u64,
+    memory_used: u64,
+    // `None` until the first heartbeat.
+    last_seen: Option<Instant>,
+    alive: bool,
+}
+
+impl Member {
+    fn unknown() -> Self {
+        Member {
+            node_id: String::new(),
+            memory_capacity: 0,
+            memory_used: 0,
+            last_seen: None,
+            alive: false,
+        }
+    }
+}
+
+/// The stores of the cluster this one knows of, keyed by TCP address, and
+/// whether they are alive. A store is alive from its first heartbeat until
+/// none arrives within the dead node timeout.
+pub struct Membership {
+    node_id: String,
+    address: String,
+    heartbeat_interval: Duration,
+    dead_node_timeout: Duration,
+    members: Mutex<HashMap<String, Member>>,
+}
+
+impl Membership {
+    pub fn new(
+        node_id: String,
+        address: String,
+        peers: &[String],
+        heartbeat_interval: Duration,
+        dead_node_timeout: Duration,
+    ) -> Self {
+        let members = peers
+            .iter()
+            .filter(|peer| **peer != address)
+            .map(|peer| (peer.clone(), Member::unknown()))
+            .collect();
+        Membership {
+            node_id,
+            address,
+            heartbeat_interval,
+            dead_node_timeout,
+            members: Mutex::new(members),
+        }
+    }
+
+    /// The heartbeat this store sends, or answers one with.
+    pub fn heartbeat(&self, object_store: &Mutex<ObjectStore>) -> messages::HeartbeatRequest {
+        let object_store = object_store.locked();
+        messages::HeartbeatRequest {
+            node_id: self.node_id.clone(),
+            address: self.address.clone(),
+            memory_capacity: object_store.memory_capacity(),
+            memory_used: object_store.memory_used(),
+        }
+    }
+
+    /// Records a heartbeat from a peer, which need not be one of `--peers`.
+    pub fn record(&self, heartbeat: messages::HeartbeatRequest) {
+        if heartbeat.address == self.address {
+            return;
+        }
+        let mut members = self.members.locked();
+        let member = members
+            .entry(heartbeat.address.clone())
+            .or_insert_with(Member::unknown);
+        if !member.alive {
+            info!(
+                "Node {} at {} is alive",
+                heartbeat.node_id, heartbeat.address
+            );
+        }
+        member.node_id = heartbeat.node_id;
+        member.memory_capacity = heartbeat.memory_capacity;
+        member.memory_used = heartbeat.memory_used;
+        member.last_seen = Some(Instant::now());
+        member.alive = true;
+    }
+
+    /// Every known store, this one first.
+    pub fn members(&self, object_store: &Mutex<ObjectStore>) -> Vec<messages::Member> {
+        let heartbeat = self.heartbeat(object_store);
+        let mut members = vec![messages::Member {
+            node_id: heartbeat.node_id,
+            address: heartbeat.address,
+            alive: true,
+            memory_capacity: heartbeat.memory_capacity,
+            memory_used: heartbeat.memory_used,
+            last_heartbeat_ms: 0,
+        }];
+        let now = Instant::now();
+        let mut peers: Vec<_> = self
+            .members
+            .locked()
+            .iter()
+            .map(|(address, member)| messages::Member {
+                node_id: member.node_id.clone(),
+                address: address.clone(),
+                alive: member.alive,
+                memory_capacity: member.memory_capacity,
+                memory_used: member.memory_used,
+                last_heartbeat_ms: member
+                    .last_seen
+                    .map_or(0, |seen| (now - seen).as_millis() as u64),
+            })
+            .collect();
+        peers.sort_by(|a, b| a.address.cmp(&b.address));
+        members.extend(peers);
+        members
+    }
+
+    /// The addresses of the peers currently alive, those with the most free
+    /// memory first.
+    pub fn alive_peers(&self) -> Vec<String> {
+        let members = self.members.locked();
+        let mut alive: Vec<_> = members.iter().filter(|(_, member)| member.alive).collect();
+        alive.sort_by_key(|(address, member)| {
+            let free = member.memory_capacity.saturating_sub(member.memory_used);
+            (std::cmp::Reverse(free), *address)
+        });
+        alive
+            .into_iter()
+            .map(|(address, _)| address.clone())
+            .collect()
+    }
+
+    // Marks members not heard from within the timeout dead, returning the
+    // addresses of those that just died.
+    fn expire(&self) -> Vec<String> {
+        let now = Instant::now();
+        let mut dead = Vec::new();
+        for (address, member) in self.members.locked().iter_mut() {
+            let expired = member
+                .last_seen
+                .is_some_and(|seen| now - seen > self.dead_node_timeout);
+            if member.alive && expired {
+                warn!("Node {} at {} is dead", member.node_id, address);
+                member.alive = false;
+                dead.push(address.clone());
+            }
+        }
+        dead
+    }
+}
+
+/// Sends heartbeats to `peer` for as long as the store runs, reconnecting
+/// whenever the connection fails.
+pub async fn heartbeat_peer(
+    membership: Arc<Membership>,
+    object_store: Arc<Mutex<ObjectStore>>,
+    peer: String,
+) {
+    let mut interval = tokio::time::interval(membership.heartbeat_interval);
+    let mut connection = None;
+    loop {
+        interval.tick().await;
+        if connection.is_none() {
+            match TcpStream::connect(&peer).await {
+                Ok(stream) => {
+                    let _ = stream.set_nodelay(true);
+                    connection = Some(Framed::new(stream, MessageCodec {}));
+                }
+                Err(e) => {
+                    debug!("Cannot reach peer {}: {}", peer, e);
+                    continue;
+                }
+            }
+        }
+        let framed = connection.as_mut().unwrap();
+        let request = Messages::HeartbeatRequest(membership.heartbeat(&object_store));
+        let exchange = async {
+            framed.send(request).await?;
+            match framed.next().await {
+                Some(Ok(Messages::HeartbeatResponse(hr))) => Ok(hr),
+                Some(Ok(Messages::ErrorResponse(er))) => {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, er.reason))
+                }
+                Some(Ok(_)) => Err(io::ErrorKind::InvalidData.into()),
+                Some(Err(e)) => Err(e),
+                None => Err(io::ErrorKind::UnexpectedEof.into()),
+            }
+        };
+        match tokio::time::timeout(membership.heartbeat_interval, exchange).await {
+            Ok(Ok(hr)) => membership.record(messages::HeartbeatRequest {
+                node_id: hr.node_id,
+                address: hr.address,
+                memory_capacity: hr.memory_capacity,
+                memory_used: hr.memory_used,
+            }),
+            Ok(Err(e)) => {
+                debug!("Heartbeat to {} failed: {}", peer, e);
+                connection = None;
+            }
+            Err(_) => {
+                debug!("Heartbeat to {} timed out", peer);
+                connection = None;
+            }
+        }
+    }
+}
+
+/// Periodically marks peers that stopped sending heartbeats dead, forgets
+/// the references they held and drops their objects from the directory.
+pub async fn detect_failures(
+    membership: Arc<Membership>,
+    directory: Option<Arc<Directory>>,
+    references: Arc<References>,
+) {
+    let mut interval = tokio::time::interval(membership.heartbeat_interval);
+    loop {
+        interval.tick().await;
+        for address in membership.expire() {
+            references.node_died(&address);
+            let Some(directory) = &directory else {
+                continue;
+            };
+            match directory.purge(&address).await {
+                Ok(()) => info!("Dropped the objects of {} from the directory", address),
+                Err(e) => warn!("Dropping the objects of {} failed: {}", address, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn heartbeat(address: &str, memory_used: u64) -> messages::HeartbeatRequest {
+        messages::HeartbeatRequest {
+            node_id: format!("node at {}", address),
+            address: address.to_string(),
+            memory_capacity: 100,
+            memory_used,
+        }
+    }
+
+    #[test]
+    fn peers_die_without_heartbeats() {
+        let peers = ["a:1".to_string(), "b:1".to_string(), "self:1".to_string()];
+        let membership = Membership::new(
+            "self".to_string(),
+            "self:1".to_string(),
+            &peers,
+            Duration::from_millis(10),
+            Duration::from_millis(50),
+        );
+        // Peers are not alive before their first heartbeat.
+        assert!(membership.alive_peers().is_empty());
+        assert!(membership.expire().is_empty());
+
+        membership.record(heartbeat("a:1", 90));
+        membership.record(heartbeat("b:1", 10));
+        membership.record(heartbeat("self:1", 0));
+        assert_eq!(membership.alive_peers(), ["b:1", "a:1"]);
+
+        std::thread::sleep(Duration::from_millis(100));
+        membership.record(heartbeat("b:1", 10));
+        assert_eq!(membership.expire(), ["a:1"]);
+        assert!(membership.expire().is_empty());
+        assert_eq!(membership.alive_peers(), ["b:1"]);
+
+        // Until it is heard from again.
+        membership.record(heartbeat("a:1", 0));
+        assert_eq!(membership.alive_peers(), ["a:1", "b:1"]);
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Duration;
+
+use crabstore_common::messages::message_name;
+
+use crate::lock::LockExt;
+use crate::object_store::ObjectStore;
+
+// The store counts what it does as it goes, and a small HTTP listener hands
+// the counts out in the Prometheus text format, along with the state of the
+// object store at the time of the scrape.
+
+// Upper bounds of the latency histogram buckets, in microseconds.
+const BUCKETS_US: [u64; 12] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 50_000, 250_000, 1_000_000, 10_000_000,
+];
+
+// Scrapers send a short request head. Anything longer, or slower, is dropped.
+const MAX_REQUEST_HEAD: usize = 8192;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The requests whose rates and latencies are tracked.
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    Create,
+    Get,
+    Seal,
+    Delete,
+}
+
+impl Operation {
+    const ALL: [Operation; 4] = [
+        Operation::Create,
+        Operation::Get,
+        Operation::Seal,
+        Operation::Delete,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Operation::Create => "create",
+            Operation::Get => "get",
+            Operation::Seal => "seal",
+            Operation::Delete => "delete",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    // Not cumulative, one past the last bucket for slower observations.
+    buckets: [AtomicU64; BUCKETS_US.len() + 1],
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let us = elapsed.as_micros() as u64;
+        let bucket = BUCKETS_US.partition_point(|bound| *bound < us);
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+}
+
+/// Counters of what the store has done since it started.
+#[derive(Default)]
+pub struct Metrics {
+    requests: [Histogram; Operation::ALL.len()],
+    connected_clients: AtomicU64,
+    queued_creates: AtomicU64,
+    // By the name of the message type, or "unknown".
+    decode_errors: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Metrics::default())
+    }
+
+    /// Records that a request took `elapsed` to serve.
+    pub fn observe(&self, operation: Operation, elapsed: Duration) {
+        self.requests[operation as usize].observe(elapsed);
+    }
+
+    /// Counts a client as connected until the returned guard is dropped.
+    pub fn client_connected(self: &Arc<Self>) -> Gauged {
+        Gauged::new(self.clone(), |metrics| &metrics.connected_clients)
+    }
+
+    /// Counts a create as waiting for a concurrent create of the same object
+    /// until the returned guard is dropped.
+    pub fn create_queued(self: &Arc<Self>) -> Gauged {
+        Gauged::new(self.clone(), |metrics| &metrics.queued_creates)
+    }
+
+    /// Counts a frame of `message_type` that failed to decode.
+    pub fn decode_error(&self, message_type: u16) {
+        let name = message_name(message_type).unwrap_or("unknown");
+        *self.decode_errors.locked().entry(name).or_default() += 1;
+    }
+
+    /// The metrics in the Prometheus text format.
+    pub fn render(&self, object_store: &Mutex<ObjectStore>) -> String {
+        let mut out = String::new();
+        {
+            let object_store = object_store.locked();
+            gauge(
+                &mut out,
+                "crabstore_memory_used_bytes",
+                "Bytes of the memory pool allocated to objects.",
+                object_store.memory_used(),
+            );
+            gauge(
+                &mut out,
+                "crabstore_memory_capacity_bytes",
+                "Size of the memory pool.",
+                object_store.memory_capacity(),
+            );
+            gauge(
+                &mut out,
+                "crabstore_fallback_bytes",
+                "Bytes of objects held on disk, in the spill directory, for lack of memory.",
+                object_store.spilled_bytes(),
+            );
+            gauge(
+                &mut out,
+                "crabstore_objects",
+                "Objects in memory.",
+                object_store.num_objects() as u64,
+            );
+            gauge(
+                &mut out,
+                "crabstore_spilled_objects",
+                "Objects in the spill directory.",
+                object_store.num_spilled() as u64,
+            );
+            counter(
+                &mut out,
+                "crabstore_evictions_total",
+                "Objects dropped from memory to make room.",
+                object_store.num_evictions(),
+            );
+            counter(
+                &mut out,
+                "crabstore_spills_total",
+                "Objects moved to the spill directory.",
+                object_store.num_spills(),
+            );
+        }
+        gauge(
+            &mut out,
+            "crabstore_queued_creates",
+            "Creates waiting for a concurrent create of the same object.",
+            self.queued_creates.load(Ordering::Relaxed),
+        );
+        gauge(
+            &mut out,
+            "crabstore_connected_clients",
+            "Clients connected to the store.",
+            self.connected_clients.load(Ordering::Relaxed),
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP crabstore_request_duration_seconds Time taken to serve requests.\n\
+             # TYPE crabstore_request_duration_seconds histogram"
+        );
+        for operation in Operation::ALL {
+            let histogram = &self.requests[operation as usize];
+            let mut count = 0;
+            for (i, bucket) in histogram.buckets.iter().enumerate() {
+                count += bucket.load(Ordering::Relaxed);
+                let le = match BUCKETS_US.get(i) {
+                    Some(us) => (*us as f64 / 1e6).to_string(),
+                    None => "+Inf".to_string(),
+                };
+                let _ = writeln!(
+                    out,
+                    "crabstore_request_duration_seconds_bucket{{op=\"{}\",le=\"{}\"}} {}",
+                    operation.name(),
+                    le,
+                    count
+                );
+            }
+            let sum = histogram.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(
+                out,
+                "crabstore_request_duration_seconds_sum{{op=\"{}\"}} {}\n\
+                 crabstore_request_duration_seconds_count{{op=\"{}\"}} {}",
+                operation.name(),
+                sum,
+                operation.name(),
+                count
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP crabstore_decode_errors_total Frames from clients that failed to decode.\n\
+             # TYPE crabstore_decode_errors_total counter"
+        );
+        for (name, count) in self.decode_errors.locked().iter() {
+            let _ = writeln!(
+                out,
+                "crabstore_decode_errors_total{{type=\"{}\"}} {}",
+                name, count
+            );
+        }
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: u64) {
+    metric(out, name, "gauge", help, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    metric(out, name, "counter", help, value);
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, value: u64) {
+    let _ = writeln!(
+        out,
+        "# HELP {} {}\n# TYPE {} {}\n{} {}",
+        name, help, name, kind, name, value
+    );
+}
+
+/// Holds one up on a gauge of `Metrics`, e.g. for a connected client.
+pub struct Gauged {
+    metrics: Arc<Metrics>,
+    gauge: fn(&Metrics) -> &AtomicU64,
+}
+
+impl Gauged {
+    fn new(metrics: Arc<Metrics>, gauge: fn(&Metrics) -> &AtomicU64) -> Self {
+        gauge(&metrics).fetch_add(1, Ordering::Relaxed);
+        Gauged { metrics, gauge }
+    }
+}
+
+impl Drop for Gauged {
+    fn drop(&mut self) {
+        (self.gauge)(&self.metrics).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Answers Prometheus scrapes of /metrics on `listener`.
+pub async fn serve(
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+    object_store: Arc<Mutex<ObjectStore>>,
+) {
+    if let Ok(address) = listener.local_addr() {
+        info!("Serving metrics on http://{}/metrics", address);
+    }
+    loop {
+        let Ok((stream, address)) = listener.accept().await else {
+            continue;
+        };
+        let metrics = metrics.clone();
+        let object_store = object_store.clone();
+        tokio::spawn(async move {
+            let scrape = scrape(stream, &metrics, &object_store);
+            match tokio::time::timeout(REQUEST_TIMEOUT, scrape).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Metrics request from {} failed: {}", address, e),
+                Err(_) => debug!("Metrics request from {} timed out", address),
+            }
+        });
+    }
+}
+
+// Serves one HTTP request, then closes the connection.
+async fn scrape(
+    mut stream: TcpStream,
+    metrics: &Metrics,
+    object_store: &Mutex<ObjectStore>,
+) -> io::Result<()> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request head too long",
+            ));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let request_line = head.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|b| *b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", metrics.render(object_store)),
+        (Some(b"GET"), _) => ("404 Not Found", "Not found, try /metrics\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: text/plain; version=0.0.4; charset=utf-8\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::RamAllocator;
+    use crate::test_util::TempDir;
+
+    fn test_store(dir: &TempDir) -> Arc<Mutex<ObjectStore>> {
+        let allocator = RamAllocator::new(dir.path(), 1 << 20, false).unwrap();
+        Arc::new(Mutex::new(ObjectStore::new(allocator)))
+    }
+
+    // The value of the sample `name`, with its labels if any.
+    fn sample(out: &str, name: &str) -> String {
+        out.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("no sample {} in\n{}", name, out))
+            .to_string()
+    }
+
+    #[test]
+    fn render() {
+        let dir = TempDir::new();
+        let object_store = test_store(&dir);
+        let metrics = Metrics::new();
+        metrics.observe(Operation::Get, Duration::from_micros(30));
+        metrics.observe(Operation::Get, Duration::from_millis(2));
+        metrics.observe(Operation::Get, Duration::from_secs(20));
+        metrics.decode_error(u16::MAX);
+        metrics.decode_error(u16::MAX);
+        let client = metrics.client_connected();
+
+        let out = metrics.render(&object_store);
+        assert_eq!(sample(&out, "crabstore_memory_capacity_bytes"), "1048576");
+        assert_eq!(sample(&out, "crabstore_objects"), "0");
+        assert_eq!(sample(&out, "crabstore_connected_clients"), "1");
+        assert!(out.contains("# TYPE crabstore_evictions_total counter\n"));
+        let bucket = |le: &str| {
+            let name = format!(
+                "crabstore_request_duration_seconds_bucket{{op=\"get\",le=\"{}\"}}",
+                le
+            );
+            sample(&out, &name)
+        };
+        assert_eq!(bucket("0.00005"), "1");
+        assert_eq!(bucket("0.001"), "1");
+        assert_eq!(bucket("0.0025"), "2");
+        assert_eq!(bucket("10"), "2");
+        assert_eq!(bucket("+Inf"), "3");
+        assert_eq!(
+            sample(&out, "crabstore_request_duration_seconds_sum{op=\"get\"}"),
+            "20.00203"
+        );
+        assert_eq!(
+            sample(&out, "crabstore_request_duration_seconds_count{op=\"get\"}"),
+            "3"
+        );
+        assert_eq!(
+            sample(
+                &out,
+                "crabstore_request_duration_seconds_count{op=\"seal\"}"
+            ),
+            "0"
+        );
+        assert_eq!(
+            sample(&out, "crabstore_decode_errors_total{type=\"unknown\"}"),
+            "2"
+        );
+
+        drop(client);
+        let out = metrics.render(&object_store);
+        assert_eq!(sample(&out, "crabstore_connected_clients"), "0");
+    }
+
+    async fn request(address: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn scrapes() {
+        let dir = TempDir::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(serve(listener, Metrics::new(), test_store(&dir)));
+
+        let response = request(&address, "GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        assert_eq!(sample(body, "crabstore_memory_used_bytes"), "0");
+
+        let response = request(&address, "GET / HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        let response = request(&address, "POST /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+}
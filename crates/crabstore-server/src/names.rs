@@ -0,0 +1,66 @@
+use std::io::{self, Read, Write};
+
+/// The longest owner node or namespace name the spill index and the journal
+/// can hold next to an object.
+pub const MAX_NAME_LEN: usize = u16::MAX as usize;
+
+/// Writes `name` as a u16 length then its UTF-8 bytes. Fails with
+/// InvalidInput if it is longer than `MAX_NAME_LEN`; `what` names it in the
+/// error.
+pub fn write_name(out: &mut impl Write, name: &str, what: &str) -> io::Result<()> {
+    let len = u16::try_from(name.len()).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is longer than {} bytes", what, MAX_NAME_LEN),
+        )
+    })?;
+    out.write_all(&len.to_le_bytes())?;
+    out.write_all(name.as_bytes())
+}
+
+/// Reads a name written by `write_name`. Fails with InvalidData if it is not
+/// UTF-8.
+pub fn read_name(input: &mut impl Read, what: &str) -> io::Result<String> {
+    let mut len = [0u8; 2];
+    input.read_exact(&mut len)?;
+    let mut name = vec![0u8; u16::from_le_bytes(len) as usize];
+    input.read_exact(&mut name)?;
+    String::from_utf8(name)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("{} is not UTF-8", what)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        write_name(&mut buf, "tenant", "namespace").unwrap();
+        write_name(&mut buf, "", "namespace").unwrap();
+        assert_eq!(buf.len(), 2 + 6 + 2);
+        let mut input = &buf[..];
+        assert_eq!(read_name(&mut input, "namespace").unwrap(), "tenant");
+        assert_eq!(read_name(&mut input, "namespace").unwrap(), "");
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn too_long() {
+        let mut buf = Vec::new();
+        write_name(&mut buf, &"a".repeat(MAX_NAME_LEN), "namespace").unwrap();
+        let err = write_name(&mut buf, &"a".repeat(MAX_NAME_LEN + 1), "namespace").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "namespace is longer than 65535 bytes");
+    }
+
+    #[test]
+    fn not_utf8() {
+        let mut input = &[2, 0, 0xff, 0xfe][..];
+        let err = read_name(&mut input, "namespace").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut input = &[4, 0, b'a'][..];
+        let err = read_name(&mut input, "namespace").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
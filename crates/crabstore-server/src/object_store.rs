@@ -0,0 +1,1391 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::ops::Bound;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{debug, error, warn};
+use tokio::sync::{mpsc, oneshot};
+
+use crabstore_common::header::{ObjectHeader, VersionCounter, CHECKSUM_SIZE, HEADER_SIZE};
+use crabstore_common::messages::messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::allocator::{Allocation, Allocator, RamAllocator};
+use crate::journal::{Journal, JournalEntry};
+use crate::quota::Quotas;
+use crate::spill::{SpillDir, SpilledObject};
+
+/// Identifies a client connection for the lifetime of the store.
+pub type ClientId = u64;
+
+/// Stands in for the store itself as the creator of objects, e.g. those
+/// pulled from peers.
+pub const STORE_CLIENT_ID: ClientId = ClientId::MAX;
+
+/// Creates the objects uploaded in chunks, which outlive the connection of
+/// the client uploading them.
+pub const UPLOAD_CLIENT_ID: ClientId = ClientId::MAX - 1;
+
+// The client whose quota an object created by `owner` counts against: none
+// for the store's own objects and for chunked uploads, which outlive their
+// connection.
+fn creator(owner: ClientId) -> Option<ClientId> {
+    (owner != STORE_CLIENT_ID && owner != UPLOAD_CLIENT_ID).then_some(owner)
+}
+
+/// Microseconds since the Unix epoch, as in object headers.
+pub fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as u64
+}
+
+/// What listeners are told about objects coming and going.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectEvent {
+    Sealed(ObjectId),
+    /// The store no longer holds the object, e.g. because it was evicted.
+    Removed(ObjectId),
+}
+
+/// Which objects go first when memory runs out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EvictionPolicy {
+    /// The least recently created or gotten.
+    #[default]
+    Lru,
+    /// The oldest.
+    Fifo,
+    /// None, creates fail with OutOfMemory instead.
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectState {
+    /// Allocated and handed to the creating client, which is still writing it.
+    Created,
+    /// Immutable and visible to every client.
+    Sealed,
+}
+
+pub struct LocalObject {
+    pub allocation: Allocation,
+    pub data_size: u64,
+    pub metadata_size: u64,
+    // Room for data and metadata. Only differs from their sum for mutable
+    // objects that were resized.
+    pub allocated_size: u64,
+    pub device_num: i32,
+    pub is_mutable: bool,
+    pub state: ObjectState,
+    pub header: ObjectHeader,
+    // The client that created the object.
+    pub owner: ClientId,
+    // The store owning a reference counted copy, if not this one.
+    pub owner_node: Option<String>,
+    // The namespace the object's memory is charged to, if any.
+    pub namespace: Option<String>,
+    // Outstanding Gets that have not been released yet, per client.
+    pub holders: HashMap<ClientId, usize>,
+    // Created here by a client, rather than copied from a peer.
+    pub primary: bool,
+    // Never dropped to make room, only spilled. Primary copies start pinned.
+    pub pinned: bool,
+    // When the object was last created or gotten, on the store's clock.
+    pub last_used: u64,
+    // The same in microseconds since the Unix epoch, for administrators.
+    pub last_access_us: u64,
+    // Deleted while clients were using it; dropped once they release it.
+    pub deleting: bool,
+}
+
+impl LocalObject {
+    pub fn spec(&self) -> messages::ObjectSpec {
+        messages::ObjectSpec {
+            segment_index: self.allocation.segment_index,
+            unique_fd_id: 0,
+            header_offset: self.allocation.offset,
+            data_offset: self.allocation.offset + HEADER_SIZE,
+            data_size: self.data_size,
+            metadata_offset: self.allocation.offset + HEADER_SIZE + self.data_size,
+            metadata_size: self.metadata_size,
+            allocated_size: self.allocated_size,
+            fallback_allocated: false,
+            device_num: self.device_num,
+            is_experimental_mutable_object: self.is_mutable,
+        }
+    }
+
+    pub fn info(&self, object_id: &ObjectId) -> messages::ObjectInfo {
+        let state = match self.state {
+            ObjectState::Created => messages::ObjectState::Created,
+            ObjectState::Sealed => messages::ObjectState::Sealed,
+        };
+        messages::ObjectInfo {
+            object_id: object_id.binary(),
+            data_size: self.data_size,
+            metadata_size: self.metadata_size,
+            state: state.into(),
+            pinned: self.pinned,
+            primary: self.primary,
+            create_time_us: self.header.create_time_us,
+            last_access_us: self.last_access_us,
+            ref_count: self.holders.values().sum::<usize>() as u64,
+            references: 0,
+            owner: self.owner,
+            fallback_allocated: false,
+        }
+    }
+}
+
+// The old allocation of a reallocated mutable object. Clients that had the
+// object mapped may still be reading it, so it is only freed once each of
+// them has come back for the new copy or disconnected.
+struct RetiredAllocation {
+    object_id: ObjectId,
+    allocation: Allocation,
+    readers: HashSet<ClientId>,
+}
+
+/// Bookkeeping for every object held by this store. All methods are called
+/// with the store lock held, so they never block.
+pub struct ObjectStore {
+    allocator: RamAllocator,
+    // Ordered by ID, so that listings can page through them.
+    objects: BTreeMap<ObjectId, LocalObject>,
+    // Clients waiting for an object that is still being created to be
+    // sealed or aborted.
+    seal_waiters: HashMap<ObjectId, Vec<oneshot::Sender<()>>>,
+    retired: Vec<RetiredAllocation>,
+    // Told about every sealed and removed object, e.g. to keep the
+    // cluster's directory current.
+    listeners: Vec<mpsc::UnboundedSender<ObjectEvent>>,
+    // Where pinned objects go when memory runs out, if anywhere.
+    spill: Option<SpillDir>,
+    // Where the sealed objects are in the pool, if it is recoverable.
+    journal: Option<Journal>,
+    eviction_policy: EvictionPolicy,
+    quotas: Quotas,
+    // Ticks on every create and get, to find the least recently used object.
+    clock: u64,
+    // Objects dropped and spilled to make room or on shutdown, ever.
+    evictions: u64,
+    spills: u64,
+}
+
+impl ObjectStore {
+    pub fn new(allocator: RamAllocator) -> Self {
+        ObjectStore {
+            allocator,
+            objects: BTreeMap::new(),
+            seal_waiters: HashMap::new(),
+            retired: Vec::new(),
+            listeners: Vec::new(),
+            spill: None,
+            journal: None,
+            eviction_policy: EvictionPolicy::default(),
+            quotas: Quotas::default(),
+            clock: 0,
+            evictions: 0,
+            spills: 0,
+        }
+    }
+
+    /// Tells `listener` about the objects sealed and removed from now on,
+    /// and about those held already, e.g. persisted or left in the pool by
+    /// the last store.
+    pub fn add_listener(&mut self, listener: mpsc::UnboundedSender<ObjectEvent>) {
+        for (object_id, object) in &self.objects {
+            if object.state == ObjectState::Sealed {
+                let _ = listener.send(ObjectEvent::Sealed(*object_id));
+            }
+        }
+        if let Some(spill) = &self.spill {
+            for object_id in spill.object_ids() {
+                let _ = listener.send(ObjectEvent::Sealed(*object_id));
+            }
+        }
+        self.listeners.push(listener);
+    }
+
+    pub fn set_spill_dir(&mut self, spill: SpillDir) {
+        self.spill = Some(spill);
+    }
+
+    /// Takes over the sealed objects that `journal` lists in the pool, left
+    /// there by the last store, and records the objects sealed and freed
+    /// from now on in it. Returns how many objects were recovered.
+    pub fn set_journal(&mut self, journal: Journal) -> usize {
+        let entries: Vec<(u64, JournalEntry)> = journal
+            .entries()
+            .map(|(offset, entry)| (offset, entry.clone()))
+            .collect();
+        self.journal = Some(journal);
+        let mut recovered = 0;
+        for (offset, entry) in entries {
+            match self.recover(offset, entry) {
+                Ok(object_id) => {
+                    debug!("Recovered {} at offset {} of the pool", object_id, offset);
+                    recovered += 1;
+                }
+                Err(reason) => {
+                    warn!(
+                        "Dropping the object at offset {} of the pool: {}",
+                        offset, reason
+                    );
+                    self.journal.as_mut().unwrap().freed(offset);
+                }
+            }
+        }
+        recovered
+    }
+
+    // Rebuilds the object whose allocation is at `offset` from its header.
+    fn recover(&mut self, offset: u64, entry: JournalEntry) -> Result<ObjectId, String> {
+        let allocation = self
+            .allocator
+            .claim(offset, entry.size)
+            .ok_or("it overlaps another object or the end of the pool")?;
+        let header = match ObjectHeader::read_from(self.allocator.buffer_mut(&allocation)) {
+            Ok(header) if !header.sealed || header.mutable || header.moved => {
+                Err("it is not a sealed, immutable object".to_string())
+            }
+            Ok(header)
+                if header
+                    .data_size
+                    .checked_add(header.metadata_size)
+                    .and_then(|size| size.checked_add(HEADER_SIZE))
+                    .map_or(true, |size| size > entry.size) =>
+            {
+                Err("its header does not fit its allocation".to_string())
+            }
+            Ok(header) if self.objects.contains_key(&header.object_id) => {
+                Err(format!("{} is there twice", header.object_id))
+            }
+            Ok(header) => Ok(header),
+            Err(e) => Err(e.to_string()),
+        };
+        let header = match header {
+            Ok(header) => header,
+            Err(reason) => {
+                self.allocator.free(allocation);
+                return Err(reason);
+            }
+        };
+
+        let object_id = header.object_id;
+        let last_used = self.tick();
+        let last_access_us = header.create_time_us;
+        let size = header.data_size + header.metadata_size;
+        // Charged again without a check, as in `restore`.
+        if let Some(namespace) = &entry.namespace {
+            self.quotas.charge(namespace, None, size, 1);
+        }
+        self.objects.insert(
+            object_id,
+            LocalObject {
+                allocation,
+                data_size: header.data_size,
+                metadata_size: header.metadata_size,
+                allocated_size: size,
+                device_num: entry.device_num,
+                is_mutable: false,
+                state: ObjectState::Sealed,
+                header,
+                owner: STORE_CLIENT_ID,
+                owner_node: entry.owner_node,
+                namespace: entry.namespace,
+                holders: HashMap::new(),
+                primary: entry.primary,
+                pinned: entry.pinned,
+                last_used,
+                last_access_us,
+                deleting: false,
+            },
+        );
+        Ok(object_id)
+    }
+
+    // Records in the journal, if any, where the sealed object is in the pool
+    // and how it is held.
+    fn journal_sealed(&mut self, object_id: &ObjectId) {
+        let (Some(journal), Some(object)) = (&mut self.journal, self.objects.get(object_id)) else {
+            return;
+        };
+        if object.state != ObjectState::Sealed || object.is_mutable {
+            return;
+        }
+        let entry = JournalEntry {
+            size: object.allocation.size,
+            device_num: object.device_num,
+            primary: object.primary,
+            pinned: object.pinned,
+            owner_node: object.owner_node.clone(),
+            namespace: object.namespace.clone(),
+        };
+        if let Err(e) = journal.sealed(object.allocation.offset, entry) {
+            warn!("{} will not be recovered after a crash: {}", object_id, e);
+        }
+    }
+
+    // Gives back the allocation of an object that is gone from memory.
+    fn free(&mut self, allocation: Allocation) {
+        if let Some(journal) = &mut self.journal {
+            journal.freed(allocation.offset);
+        }
+        self.allocator.free(allocation);
+    }
+
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.eviction_policy = policy;
+    }
+
+    pub fn set_quotas(&mut self, quotas: Quotas) {
+        self.quotas = quotas;
+    }
+
+    pub fn quotas(&self) -> &Quotas {
+        &self.quotas
+    }
+
+    fn notify(&self, event: ObjectEvent) {
+        for listener in &self.listeners {
+            let _ = listener.send(event);
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    pub fn memory_capacity(&self) -> u64 {
+        self.allocator.capacity()
+    }
+
+    pub fn memory_used(&self) -> u64 {
+        self.allocator.total_allocated()
+    }
+
+    pub fn num_objects(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn num_spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, SpillDir::len)
+    }
+
+    pub fn spilled_bytes(&self) -> u64 {
+        self.spill.as_ref().map_or(0, SpillDir::used)
+    }
+
+    pub fn num_evictions(&self) -> u64 {
+        self.evictions
+    }
+
+    pub fn num_spills(&self) -> u64 {
+        self.spills
+    }
+
+    /// Describes the first `limit` objects held, in memory or spilled, from
+    /// `start` on in the order of their IDs that pass `filter`.
+    pub fn object_infos(
+        &self,
+        start: Bound<ObjectId>,
+        limit: usize,
+        filter: impl Fn(&messages::ObjectInfo) -> bool,
+    ) -> Vec<messages::ObjectInfo> {
+        let mut infos: Vec<_> = self
+            .objects
+            .range((start, Bound::Unbounded))
+            .map(|(object_id, object)| object.info(object_id))
+            .filter(&filter)
+            .take(limit)
+            .collect();
+        if let Some(spill) = &self.spill {
+            infos.extend(
+                spill
+                    .objects(start)
+                    .map(|(object_id, spilled)| spilled.info(object_id))
+                    .filter(&filter)
+                    .take(limit),
+            );
+            infos.sort_unstable_by(|a, b| a.object_id.cmp(&b.object_id));
+            infos.truncate(limit);
+        }
+        infos
+    }
+
+    pub fn object(&self, object_id: &ObjectId) -> Option<&LocalObject> {
+        self.objects.get(object_id)
+    }
+
+    pub fn spilled_object(&self, object_id: &ObjectId) -> Option<&SpilledObject> {
+        self.spill.as_ref().and_then(|spill| spill.get(object_id))
+    }
+
+    pub fn store_path(&self) -> &Path {
+        self.allocator.path()
+    }
+
+    /// Allocates an object created by `owner`, charging its memory to
+    /// `namespace` if set. Fails with QuotaExceeded if the namespace cannot
+    /// take it, whether or not the pool has room.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_object(
+        &mut self,
+        object_id: ObjectId,
+        data_size: u64,
+        metadata_size: u64,
+        device_num: i32,
+        is_mutable: bool,
+        owner: ClientId,
+        namespace: Option<&str>,
+    ) -> Result<&LocalObject, messages::Error> {
+        if self.state(&object_id).is_some() {
+            return Err(messages::Error::ObjectExists);
+        }
+        // The sizes come from clients, so their sum may not fit.
+        let size = data_size
+            .checked_add(metadata_size)
+            .filter(|size| *size <= u64::MAX - HEADER_SIZE)
+            .ok_or(messages::Error::InvalidRequest)?;
+        if let Some(namespace) = namespace {
+            self.quotas.check(namespace, creator(owner), size, 1)?;
+        }
+        let allocation = self
+            .allocate(HEADER_SIZE + size)
+            .ok_or(messages::Error::OutOfMemory)?;
+        debug!(
+            "Allocated {} bytes for {}, {}/{} bytes in use",
+            allocation.size,
+            object_id,
+            self.allocator.total_allocated(),
+            self.allocator.capacity()
+        );
+
+        let mut header = ObjectHeader::new(object_id, data_size, metadata_size);
+        header.mutable = is_mutable;
+        header.init(self.allocator.buffer_mut(&allocation));
+
+        let primary = owner != STORE_CLIENT_ID;
+        let last_used = self.tick();
+        if let Some(namespace) = namespace {
+            self.quotas.charge(namespace, creator(owner), size, 1);
+        }
+        let object = self.objects.entry(object_id).or_insert(LocalObject {
+            allocation,
+            data_size,
+            metadata_size,
+            allocated_size: size,
+            device_num,
+            is_mutable,
+            state: ObjectState::Created,
+            header,
+            owner,
+            owner_node: None,
+            namespace: namespace.map(str::to_string),
+            holders: HashMap::new(),
+            primary,
+            pinned: primary,
+            last_used,
+            last_access_us: now_us(),
+            deleting: false,
+        });
+        Ok(object)
+    }
+
+    // Allocates `size` bytes, evicting objects to make room if needed.
+    fn allocate(&mut self, size: u64) -> Option<Allocation> {
+        loop {
+            if let Some(allocation) = self.allocator.allocate(size) {
+                return Some(allocation);
+            }
+            if size > self.allocator.capacity() || !self.evict_one() {
+                return None;
+            }
+        }
+    }
+
+    // Frees the object that may go first under the eviction policy: copies
+    // pulled from peers first, then primary copies that were unpinned, then
+    // pinned ones, which are spilled to disk rather than dropped if there is
+    // room for them. Objects in use, and mutable ones, are never evicted.
+    // Returns false if nothing could go.
+    fn evict_one(&mut self) -> bool {
+        let policy = self.eviction_policy;
+        if policy == EvictionPolicy::None {
+            return false;
+        }
+        let spill = self.spill.as_ref();
+        let victim = self
+            .objects
+            .iter()
+            .filter(|(_, o)| {
+                o.state == ObjectState::Sealed
+                    && o.holders.is_empty()
+                    && !o.is_mutable
+                    && (!o.pinned
+                        || spill.is_some_and(|s| s.has_room(o.data_size + o.metadata_size)))
+            })
+            .min_by_key(|(_, o)| {
+                let age = match policy {
+                    EvictionPolicy::Fifo => o.header.create_time_us,
+                    _ => o.last_used,
+                };
+                (o.pinned, o.primary, age)
+            })
+            .map(|(object_id, _)| *object_id);
+        let Some(object_id) = victim else {
+            return false;
+        };
+
+        if self.objects[&object_id].pinned {
+            if let Err(e) = self.write_to_spill(object_id) {
+                warn!("Spilling {} failed: {}", object_id, e);
+                return false;
+            }
+            debug!("Spilled {} to disk", object_id);
+            self.spills += 1;
+        } else {
+            debug!("Evicted {}", object_id);
+            self.notify(ObjectEvent::Removed(object_id));
+            self.evictions += 1;
+        }
+        let object = self.take(&object_id);
+        self.free(object.allocation);
+        true
+    }
+
+    /// Drops or spills objects, as if memory had run out, until at least
+    /// `bytes` of the pool were freed or nothing else can go. Returns how
+    /// many bytes were freed, and how many objects were dropped and spilled.
+    pub fn evict(&mut self, bytes: u64) -> (u64, u64, u64) {
+        let (used, evictions, spills) = (self.memory_used(), self.evictions, self.spills);
+        while used - self.memory_used() < bytes && self.evict_one() {}
+        (
+            used - self.memory_used(),
+            self.evictions - evictions,
+            self.spills - spills,
+        )
+    }
+
+    // Writes a copy of a sealed object to the spill directory, leaving it in
+    // memory.
+    fn write_to_spill(&mut self, object_id: ObjectId) -> io::Result<()> {
+        let object = &self.objects[&object_id];
+        let spilled = SpilledObject {
+            header: object.header.clone(),
+            device_num: object.device_num,
+            owner_node: object.owner_node.clone(),
+            primary: object.primary,
+            pinned: object.pinned,
+            last_access_us: object.last_access_us,
+            namespace: object.namespace.clone(),
+        };
+        let start = HEADER_SIZE as usize;
+        let size = (object.data_size + object.metadata_size) as usize;
+        let bytes = &self.allocator.buffer_mut(&object.allocation)[start..start + size];
+        self.spill
+            .as_mut()
+            .unwrap()
+            .spill(object_id, spilled, bytes)
+    }
+
+    // Takes an object out of memory, releasing what it was charged to its
+    // namespace and creator. Freeing its allocation is up to the caller.
+    fn take(&mut self, object_id: &ObjectId) -> LocalObject {
+        let object = self.objects.remove(object_id).unwrap();
+        if let Some(namespace) = &object.namespace {
+            let creator = creator(object.owner);
+            self.quotas
+                .release(namespace, creator, object.allocated_size, 1);
+        }
+        object
+    }
+
+    /// Writes the index of the spill directory, so that the objects in it
+    /// are found again after a restart. Returns how many it lists.
+    pub fn save_spill_index(&self) -> io::Result<usize> {
+        self.spill.as_ref().map_or(Ok(0), SpillDir::save_index)
+    }
+
+    /// Moves every sealed, immutable object that fits to the spill
+    /// directory, e.g. before the store exits. Returns how many were moved
+    /// and how many could not be.
+    pub fn spill_all(&mut self) -> (usize, usize) {
+        if self.spill.is_none() {
+            return (0, 0);
+        }
+        let mut candidates: Vec<(ObjectId, u64)> = self
+            .objects
+            .iter()
+            .filter(|(_, o)| o.state == ObjectState::Sealed && !o.is_mutable)
+            .map(|(object_id, o)| (*object_id, o.data_size + o.metadata_size))
+            .collect();
+        // Keep as many objects as the limit allows.
+        candidates.sort_by_key(|(_, size)| *size);
+        let mut failed = candidates.len();
+        let mut spilled = 0;
+        for (object_id, size) in candidates {
+            if !self.spill.as_ref().unwrap().has_room(size) {
+                continue;
+            }
+            if let Err(e) = self.write_to_spill(object_id) {
+                warn!("Spilling {} failed: {}", object_id, e);
+                continue;
+            }
+            let object = self.take(&object_id);
+            self.free(object.allocation);
+            self.spills += 1;
+            spilled += 1;
+            failed -= 1;
+        }
+        (spilled, failed)
+    }
+
+    // Brings a spilled object back into memory.
+    fn restore(&mut self, object_id: &ObjectId) -> Result<(), messages::Error> {
+        let Some(spilled) = self.spill.as_ref().and_then(|s| s.get(object_id)).cloned() else {
+            return Err(messages::Error::ObjectNonexistent);
+        };
+        let header = spilled.header;
+        let size = header.data_size + header.metadata_size;
+        let allocation = self
+            .allocate(HEADER_SIZE + size)
+            .ok_or(messages::Error::OutOfMemory)?;
+        let buffer = self.allocator.buffer_mut(&allocation);
+        header.init(buffer);
+        let start = HEADER_SIZE as usize;
+        let contents = &mut buffer[start..start + size as usize];
+        if let Err(e) = self.spill.as_ref().unwrap().read(object_id, contents) {
+            error!("Restoring {} from disk failed: {}", object_id, e);
+            self.allocator.free(allocation);
+            return Err(messages::Error::UnexpectedError);
+        }
+        self.spill.as_mut().unwrap().remove(object_id);
+        debug!("Restored {} from disk", object_id);
+        // Charged again without a check, which may take the namespace past
+        // its quota, see the quota module.
+        if let Some(namespace) = &spilled.namespace {
+            self.quotas.charge(namespace, None, size, 1);
+        }
+
+        let last_used = self.tick();
+        self.objects.insert(
+            *object_id,
+            LocalObject {
+                allocation,
+                data_size: header.data_size,
+                metadata_size: header.metadata_size,
+                allocated_size: size,
+                device_num: spilled.device_num,
+                is_mutable: false,
+                state: ObjectState::Sealed,
+                header,
+                owner: STORE_CLIENT_ID,
+                owner_node: spilled.owner_node,
+                namespace: spilled.namespace,
+                holders: HashMap::new(),
+                primary: spilled.primary,
+                pinned: spilled.pinned,
+                last_used,
+                last_access_us: spilled.last_access_us,
+                deleting: false,
+            },
+        );
+        self.journal_sealed(object_id);
+        Ok(())
+    }
+
+    /// Sets whether the object may be dropped to make room. Pinned objects
+    /// are spilled to disk instead, if the store has somewhere to spill to.
+    pub fn pin_object(
+        &mut self,
+        object_id: &ObjectId,
+        pinned: bool,
+    ) -> Result<(), messages::Error> {
+        if let Some(object) = self.objects.get_mut(object_id) {
+            object.pinned = pinned;
+            self.journal_sealed(object_id);
+            return Ok(());
+        }
+        let spilled = self
+            .spill
+            .as_mut()
+            .and_then(|spill| spill.get_mut(object_id))
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        spilled.pinned = pinned;
+        Ok(())
+    }
+
+    /// Marks the object sealed, recording `digest` (if given) in its header.
+    /// Mutable objects become readable here and are then updated in place by
+    /// their writer through the version counter in the header.
+    pub fn seal_object(
+        &mut self,
+        object_id: &ObjectId,
+        digest: Option<[u8; CHECKSUM_SIZE]>,
+    ) -> Result<(), messages::Error> {
+        let object = self
+            .objects
+            .get_mut(object_id)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        if object.state == ObjectState::Sealed {
+            return Err(messages::Error::ObjectSealed);
+        }
+        object.state = ObjectState::Sealed;
+        object.header.sealed = true;
+        // The contents of mutable objects keep changing after seal, so a
+        // checksum would go stale with the first update.
+        if !object.is_mutable {
+            object.header.checksum = digest;
+        }
+        object
+            .header
+            .write_to(self.allocator.buffer_mut(&object.allocation));
+        self.journal_sealed(object_id);
+        self.notify_seal_waiters(object_id);
+        self.notify(ObjectEvent::Sealed(*object_id));
+        Ok(())
+    }
+
+    /// Drops an object that was created but never sealed, e.g. because its
+    /// creator disconnected.
+    pub fn abort_object(&mut self, object_id: &ObjectId) -> Result<(), messages::Error> {
+        match self.objects.get(object_id) {
+            None => return Err(messages::Error::ObjectNonexistent),
+            Some(object) if object.state == ObjectState::Sealed => {
+                return Err(messages::Error::ObjectSealed)
+            }
+            Some(_) => {}
+        }
+        let object = self.take(object_id);
+        self.allocator.free(object.allocation);
+        self.notify_seal_waiters(object_id);
+        Ok(())
+    }
+
+    /// Returns a receiver that fires once `object_id` is sealed or aborted, or
+    /// `None` if the object is not currently being created.
+    pub fn wait_for_seal(&mut self, object_id: &ObjectId) -> Option<oneshot::Receiver<()>> {
+        match self.objects.get(object_id) {
+            Some(object) if object.state == ObjectState::Created => {
+                let (tx, rx) = oneshot::channel();
+                self.seal_waiters.entry(*object_id).or_default().push(tx);
+                Some(rx)
+            }
+            _ => None,
+        }
+    }
+
+    fn notify_seal_waiters(&mut self, object_id: &ObjectId) {
+        for waiter in self.seal_waiters.remove(object_id).unwrap_or_default() {
+            let _ = waiter.send(());
+        }
+    }
+
+    /// Hands out a reference to a sealed object. Every successful call must be
+    /// matched by a `release_object` from the same client.
+    pub fn get_object(
+        &mut self,
+        client_id: ClientId,
+        object_id: &ObjectId,
+    ) -> Result<messages::ObjectSpec, messages::Error> {
+        if !self.objects.contains_key(object_id) {
+            self.restore(object_id)?;
+        }
+        let last_used = self.tick();
+        let object = self.objects.get_mut(object_id).unwrap();
+        if object.deleting {
+            return Err(messages::Error::ObjectNonexistent);
+        }
+        if object.state != ObjectState::Sealed {
+            return Err(messages::Error::ObjectNotSealed);
+        }
+        object.last_used = last_used;
+        object.last_access_us = now_us();
+        *object.holders.entry(client_id).or_default() += 1;
+        let spec = object.spec();
+        self.forget_retired_reader(client_id, Some(object_id));
+        Ok(spec)
+    }
+
+    pub fn release_object(
+        &mut self,
+        client_id: ClientId,
+        object_id: &ObjectId,
+    ) -> Result<(), messages::Error> {
+        let object = self
+            .objects
+            .get_mut(object_id)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        let count = object
+            .holders
+            .get_mut(&client_id)
+            .ok_or(messages::Error::UnexpectedError)?;
+        *count -= 1;
+        if *count == 0 {
+            object.holders.remove(&client_id);
+        }
+        if object.deleting && object.holders.is_empty() {
+            self.remove(object_id);
+        }
+        self.forget_retired_reader(client_id, Some(object_id));
+        Ok(())
+    }
+
+    /// Drops a sealed object, or flags it to be dropped once the clients
+    /// using it have released it. It is no longer handed out either way.
+    pub fn delete_object(&mut self, object_id: &ObjectId) -> Result<(), messages::Error> {
+        if let Some(spill) = &mut self.spill {
+            if spill.remove(object_id).is_some() {
+                debug!("Deleted spilled object {}", object_id);
+                self.notify(ObjectEvent::Removed(*object_id));
+                return Ok(());
+            }
+        }
+        let object = self
+            .objects
+            .get_mut(object_id)
+            .filter(|o| !o.deleting)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        if object.state != ObjectState::Sealed {
+            return Err(messages::Error::ObjectNotSealed);
+        }
+        if object.holders.is_empty() {
+            self.remove(object_id);
+        } else {
+            debug!("Deleting {} once it is released", object_id);
+            object.deleting = true;
+        }
+        Ok(())
+    }
+
+    fn remove(&mut self, object_id: &ObjectId) {
+        let object = self.take(object_id);
+        debug!("Deleted {}", object_id);
+        self.free(object.allocation);
+        self.notify(ObjectEvent::Removed(*object_id));
+    }
+
+    /// Records the store owning a reference counted copy pulled from a peer.
+    pub fn set_owner_node(&mut self, object_id: &ObjectId, node: String) {
+        if let Some(object) = self.objects.get_mut(object_id) {
+            object.owner_node = Some(node);
+            self.journal_sealed(object_id);
+        }
+    }
+
+    pub fn owner_node(&self, object_id: &ObjectId) -> Option<String> {
+        match self.objects.get(object_id) {
+            Some(object) => object.owner_node.clone(),
+            None => self
+                .spill
+                .as_ref()
+                .and_then(|spill| spill.get(object_id))
+                .and_then(|spilled| spilled.owner_node.clone()),
+        }
+    }
+
+    /// Copies `bytes` into an object that is still being created, at `offset`
+    /// from the start of its data. Metadata follows right after the data.
+    pub fn write_object(
+        &mut self,
+        object_id: &ObjectId,
+        offset: u64,
+        bytes: &[u8],
+    ) -> Result<(), messages::Error> {
+        let object = self
+            .objects
+            .get(object_id)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        if object.state != ObjectState::Created {
+            return Err(messages::Error::ObjectSealed);
+        }
+        let end = offset
+            .checked_add(bytes.len() as u64)
+            .ok_or(messages::Error::InvalidRequest)?;
+        if end > object.data_size + object.metadata_size {
+            return Err(messages::Error::InvalidRequest);
+        }
+        let start = (HEADER_SIZE + offset) as usize;
+        self.allocator.buffer_mut(&object.allocation)[start..start + bytes.len()]
+            .copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Copies up to `len` bytes of a sealed, immutable object, starting at
+    /// `offset` from the start of its data.
+    pub fn read_object(
+        &mut self,
+        object_id: &ObjectId,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<u8>, messages::Error> {
+        let object = self
+            .objects
+            .get(object_id)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        if object.state != ObjectState::Sealed {
+            return Err(messages::Error::ObjectNotSealed);
+        }
+        let size = object.data_size + object.metadata_size;
+        if offset > size {
+            return Err(messages::Error::InvalidRequest);
+        }
+        let start = HEADER_SIZE + offset;
+        let end = HEADER_SIZE + offset.saturating_add(len).min(size);
+        Ok(self.allocator.buffer_mut(&object.allocation)[start as usize..end as usize].to_vec())
+    }
+
+    /// Copies the data and metadata of a mutable object along with its
+    /// current spec, or returns `None` if its writer is in the middle of an
+    /// update.
+    pub fn try_snapshot_object(
+        &mut self,
+        object_id: &ObjectId,
+    ) -> Result<Option<(messages::ObjectSpec, Vec<u8>)>, messages::Error> {
+        let object = self
+            .objects
+            .get(object_id)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        let spec = object.spec();
+        let size = (object.data_size + object.metadata_size) as usize;
+        let buffer = self.allocator.buffer_mut(&object.allocation);
+        // SAFETY: as in `resize_object`.
+        let counter = unsafe { VersionCounter::from_header(buffer.as_ptr()) };
+        let Some(version) = counter.read_acquire() else {
+            return Ok(None);
+        };
+        let start = HEADER_SIZE as usize;
+        let data = buffer[start..start + size].to_vec();
+        Ok(counter.read_release(version).then_some((spec, data)))
+    }
+
+    /// Changes the data and metadata sizes of a mutable object owned by
+    /// `client_id`. If they do not fit the current allocation and
+    /// `allow_reallocate` is set, the object is copied to a larger one and
+    /// the old copy is flagged as moved for its readers.
+    pub fn resize_object(
+        &mut self,
+        client_id: ClientId,
+        object_id: &ObjectId,
+        data_size: u64,
+        metadata_size: u64,
+        allow_reallocate: bool,
+    ) -> Result<messages::ObjectSpec, messages::Error> {
+        let object = self
+            .objects
+            .get_mut(object_id)
+            .ok_or(messages::Error::ObjectNonexistent)?;
+        if !object.is_mutable || object.owner != client_id {
+            return Err(messages::Error::InvalidRequest);
+        }
+        let new_size = data_size
+            .checked_add(metadata_size)
+            .filter(|size| *size <= u64::MAX - HEADER_SIZE)
+            .ok_or(messages::Error::InvalidRequest)?;
+        if new_size > object.allocated_size && !allow_reallocate {
+            return Err(messages::Error::InvalidRequest);
+        }
+
+        let old_allocation = object.allocation.clone();
+        let old_buffer = self.allocator.buffer_mut(&old_allocation);
+        // SAFETY: the header is at the start of the allocation, which is 64
+        // byte aligned and stays mapped for the lifetime of the allocator.
+        let counter = unsafe { VersionCounter::from_header(old_buffer.as_ptr()) };
+        // The owner is the only writer and it is waiting on this request, so
+        // an odd counter means it left a write unfinished.
+        let version = counter
+            .write_acquire()
+            .map_err(|_| messages::Error::ObjectInUse)?;
+
+        if new_size <= object.allocated_size {
+            object.data_size = data_size;
+            object.metadata_size = metadata_size;
+            object.header.data_size = data_size;
+            object.header.metadata_size = metadata_size;
+            object.header.write_to(old_buffer);
+            let _ = counter.write_release();
+            return Ok(object.spec());
+        }
+
+        let growth = new_size - object.allocated_size;
+        if let Some(namespace) = &object.namespace {
+            if let Err(e) = self.quotas.check(namespace, creator(client_id), growth, 0) {
+                let _ = counter.write_release();
+                return Err(e);
+            }
+        }
+        let Some(allocation) = self.allocate(HEADER_SIZE + new_size) else {
+            let _ = counter.write_release();
+            return Err(messages::Error::OutOfMemory);
+        };
+        // Mutable objects are never evicted to make room.
+        let object = self.objects.get_mut(object_id).unwrap();
+        if let Some(namespace) = &object.namespace {
+            self.quotas.charge(namespace, creator(client_id), growth, 0);
+        }
+        let keep_data = object.data_size.min(data_size);
+        let keep_metadata = object.metadata_size.min(metadata_size);
+        self.allocator.copy(
+            &old_allocation,
+            HEADER_SIZE,
+            &allocation,
+            HEADER_SIZE,
+            keep_data,
+        );
+        self.allocator.copy(
+            &old_allocation,
+            HEADER_SIZE + object.data_size,
+            &allocation,
+            HEADER_SIZE + data_size,
+            keep_metadata,
+        );
+
+        object.data_size = data_size;
+        object.metadata_size = metadata_size;
+        object.allocated_size = new_size;
+        object.header.data_size = data_size;
+        object.header.metadata_size = metadata_size;
+        // Continue from the version readers last saw.
+        object.header.version = version + 1;
+        object.header.init(self.allocator.buffer_mut(&allocation));
+        object.allocation = allocation;
+
+        let mut moved = object.header.clone();
+        moved.moved = true;
+        let spec = object.spec();
+        let readers: HashSet<ClientId> = object.holders.keys().copied().collect();
+        moved.write_to(self.allocator.buffer_mut(&old_allocation));
+        let _ = counter.write_release();
+
+        debug!(
+            "Moved mutable object {} to a {} byte allocation",
+            object_id, new_size
+        );
+        if readers.is_empty() {
+            self.allocator.free(old_allocation);
+        } else {
+            self.retired.push(RetiredAllocation {
+                object_id: *object_id,
+                allocation: old_allocation,
+                readers,
+            });
+        }
+        Ok(spec)
+    }
+
+    /// Drops everything `client_id` was keeping alive.
+    pub fn client_disconnected(&mut self, client_id: ClientId) {
+        self.forget_retired_reader(client_id, None);
+        self.quotas.forget_client(client_id);
+    }
+
+    // `client_id` has come back for `object_id` (or for everything, if
+    // `None`), so it no longer reads the retired copies of it.
+    fn forget_retired_reader(&mut self, client_id: ClientId, object_id: Option<&ObjectId>) {
+        let allocator = &mut self.allocator;
+        self.retired.retain_mut(|retired| {
+            if object_id.map_or(true, |id| *id == retired.object_id) {
+                retired.readers.remove(&client_id);
+            }
+            if retired.readers.is_empty() {
+                debug!("Freeing the old allocation of {}", retired.object_id);
+                allocator.free(retired.allocation.clone());
+                return false;
+            }
+            true
+        });
+    }
+
+    /// The state of the object, counting spilled objects as sealed.
+    pub fn state(&self, object_id: &ObjectId) -> Option<ObjectState> {
+        match self.objects.get(object_id) {
+            Some(object) => Some(object.state),
+            None => self
+                .spill
+                .as_ref()
+                .and_then(|spill| spill.get(object_id))
+                .map(|_| ObjectState::Sealed),
+        }
+    }
+
+    pub fn contains(&self, object_id: &ObjectId) -> bool {
+        let deleting = self.objects.get(object_id).is_some_and(|o| o.deleting);
+        !deleting && self.state(object_id) == Some(ObjectState::Sealed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::journal::JOURNAL_FILE;
+    use crate::quota::Quota;
+    use crate::test_util::TempDir;
+
+    fn test_store(dir: &TempDir) -> ObjectStore {
+        ObjectStore::new(RamAllocator::new(dir.path(), 1 << 20, false).unwrap())
+    }
+
+    #[test]
+    fn write_past_the_end() {
+        let dir = TempDir::new();
+        let mut store = test_store(&dir);
+        let object_id = ObjectId::random();
+        store
+            .create_object(object_id, 8, 0, 0, false, 0, None)
+            .unwrap();
+
+        assert_eq!(
+            store.write_object(&object_id, u64::MAX, b"ab"),
+            Err(messages::Error::InvalidRequest)
+        );
+        assert_eq!(
+            store.write_object(&object_id, 7, b"ab"),
+            Err(messages::Error::InvalidRequest)
+        );
+        assert_eq!(store.write_object(&object_id, 6, b"ab"), Ok(()));
+    }
+
+    #[test]
+    fn resize_with_overflowing_sizes() {
+        let dir = TempDir::new();
+        let mut store = test_store(&dir);
+        let object_id = ObjectId::random();
+        store
+            .create_object(object_id, 8, 0, 0, true, 0, None)
+            .unwrap();
+
+        assert_eq!(
+            store.resize_object(0, &object_id, u64::MAX, 1, true),
+            Err(messages::Error::InvalidRequest)
+        );
+        assert_eq!(
+            store.resize_object(0, &object_id, u64::MAX - 8, 0, true),
+            Err(messages::Error::InvalidRequest)
+        );
+        assert_eq!(
+            store.resize_object(0, &object_id, u64::MAX - 1024, 0, true),
+            Err(messages::Error::OutOfMemory)
+        );
+        let spec = store.resize_object(0, &object_id, 4, 2, false).unwrap();
+        assert_eq!((spec.data_size, spec.metadata_size), (4, 2));
+    }
+
+    #[test]
+    fn resize_evicts_to_make_room() {
+        let dir = TempDir::new();
+        let mut store = test_store(&dir);
+        let object_id = ObjectId::random();
+        store
+            .create_object(object_id, 64, 0, 0, true, 0, None)
+            .unwrap();
+        // Fill the rest of the pool but for 64 bytes.
+        let filler = ObjectId::random();
+        let size = (1 << 20) - (HEADER_SIZE + 64) - HEADER_SIZE - 64;
+        store
+            .create_object(filler, size, 0, 0, false, 0, None)
+            .unwrap();
+        store.seal_object(&filler, None).unwrap();
+
+        assert_eq!(
+            store.resize_object(0, &object_id, 4096, 0, true),
+            Err(messages::Error::OutOfMemory)
+        );
+        store.pin_object(&filler, false).unwrap();
+        let spec = store.resize_object(0, &object_id, 4096, 0, true).unwrap();
+        assert_eq!(spec.data_size, 4096);
+        assert_eq!(store.state(&filler), None);
+        assert_eq!(store.object(&object_id).unwrap().data_size, 4096);
+    }
+
+    // Creates and seals an object holding `data`.
+    fn sealed(store: &mut ObjectStore, owner: ClientId, data: &[u8]) -> ObjectId {
+        let object_id = ObjectId::random();
+        store
+            .create_object(object_id, data.len() as u64, 0, 0, false, owner, None)
+            .unwrap();
+        store.write_object(&object_id, 0, data).unwrap();
+        store.seal_object(&object_id, None).unwrap();
+        object_id
+    }
+
+    #[test]
+    fn copies_go_first_and_pinned_objects_are_spilled() {
+        let dir = TempDir::new();
+        let mut store = test_store(&dir);
+        store.set_spill_dir(SpillDir::new(dir.path().join("spill"), None).unwrap());
+        let pinned = sealed(&mut store, 0, b"pinned");
+        let unpinned = sealed(&mut store, 0, b"unpinned");
+        store.pin_object(&unpinned, false).unwrap();
+        let copy = sealed(&mut store, STORE_CLIENT_ID, b"copy");
+        let held = sealed(&mut store, 0, b"held");
+        store.pin_object(&held, false).unwrap();
+        store.get_object(0, &held).unwrap();
+        let copy_info = store.object(&copy).unwrap();
+        assert!(!copy_info.primary && !copy_info.pinned);
+
+        assert_eq!(store.evict(1).1, 1);
+        assert_eq!(store.state(&copy), None);
+        assert_eq!(store.evict(1).1, 1);
+        assert_eq!(store.state(&unpinned), None);
+        assert_eq!(store.evict(1).2, 1);
+        assert!(store.object(&pinned).is_none());
+        assert!(store.contains(&pinned));
+        assert_eq!(store.evict(1), (0, 0, 0));
+        assert!(store.object(&held).is_some());
+
+        // Brought back, still pinned, on the next get.
+        store.get_object(0, &pinned).unwrap();
+        assert_eq!(store.num_spilled(), 0);
+        assert_eq!(store.read_object(&pinned, 0, 6).unwrap(), b"pinned");
+        let restored = store.object(&pinned).unwrap();
+        assert!(restored.primary && restored.pinned);
+    }
+
+    #[test]
+    fn pinned_objects_stay_without_a_spill_dir() {
+        let dir = TempDir::new();
+        let mut store = test_store(&dir);
+        let pinned = sealed(&mut store, 0, b"pinned");
+        assert_eq!(store.evict(1), (0, 0, 0));
+        // Takes the whole pool.
+        let filler = (1 << 20) - HEADER_SIZE;
+        assert_eq!(
+            store
+                .create_object(ObjectId::random(), filler, 0, 0, false, 0, None)
+                .err(),
+            Some(messages::Error::OutOfMemory)
+        );
+        assert!(store.contains(&pinned));
+
+        store.pin_object(&pinned, false).unwrap();
+        store
+            .create_object(ObjectId::random(), filler, 0, 0, false, 0, None)
+            .unwrap();
+        assert!(!store.contains(&pinned));
+    }
+
+    #[test]
+    fn object_infos_in_order() {
+        let dir = TempDir::new();
+        let mut store = test_store(&dir);
+        store.set_spill_dir(SpillDir::new(dir.path().join("spill"), None).unwrap());
+        let mut object_ids: Vec<ObjectId> = (0..8).map(|_| ObjectId::random()).collect();
+        for object_id in &object_ids {
+            store
+                .create_object(*object_id, 8, 0, 0, false, 0, None)
+                .unwrap();
+            store.seal_object(object_id, None).unwrap();
+        }
+        // Spill some of them.
+        assert_eq!(store.evict(4 * (HEADER_SIZE + 64)).2, 4);
+        object_ids.sort();
+
+        let ids = |infos: Vec<messages::ObjectInfo>| -> Vec<ObjectId> {
+            infos
+                .iter()
+                .map(|info| ObjectId::try_from_binary(&info.object_id).unwrap())
+                .collect()
+        };
+        let first = store.object_infos(Bound::Unbounded, 5, |_| true);
+        let spilled = first
+            .iter()
+            .filter(|info| info.state() == messages::ObjectState::Spilled)
+            .count();
+        assert!(spilled > 0 && spilled < 5);
+        assert_eq!(ids(first), object_ids[..5]);
+        let rest = store.object_infos(Bound::Excluded(object_ids[4]), 5, |_| true);
+        assert_eq!(ids(rest), object_ids[5..]);
+        let from = store.object_infos(Bound::Included(object_ids[6]), 5, |_| true);
+        assert_eq!(ids(from), object_ids[6..]);
+
+        let odd = store.object_infos(Bound::Unbounded, 2, |info| info.object_id[0] % 2 == 1);
+        let expected: Vec<ObjectId> = object_ids
+            .iter()
+            .filter(|id| id.data()[0] % 2 == 1)
+            .take(2)
+            .copied()
+            .collect();
+        assert_eq!(ids(odd), expected);
+    }
+
+    // A store over the pool of a recoverable store in `dir`, which lets
+    // namespace "a" hold one object.
+    fn recoverable_store(dir: &TempDir) -> (ObjectStore, usize) {
+        let allocator = RamAllocator::reattach(dir.path(), 1 << 20, false).unwrap();
+        let mut store = ObjectStore::new(allocator);
+        let quota = Quota {
+            max_bytes: None,
+            max_objects: Some(1),
+        };
+        store.set_quotas(Quotas::new(
+            HashMap::from([("a".to_string(), quota)]),
+            Quota::default(),
+        ));
+        let journal = Journal::open(&dir.path().join(JOURNAL_FILE)).unwrap();
+        let recovered = store.set_journal(journal);
+        (store, recovered)
+    }
+
+    #[test]
+    fn recovered_objects_count_against_their_namespace() {
+        let dir = TempDir::new();
+        let (mut store, _) = recoverable_store(&dir);
+        let object_id = ObjectId::random();
+        store
+            .create_object(object_id, 8, 0, 0, false, 0, Some("a"))
+            .unwrap();
+        store.seal_object(&object_id, None).unwrap();
+        drop(store);
+
+        let (mut store, recovered) = recoverable_store(&dir);
+        assert_eq!(recovered, 1);
+        let stats = store.quotas().stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].used_bytes, stats[0].num_objects), (8, 1));
+        assert_eq!(
+            store
+                .create_object(ObjectId::random(), 8, 0, 0, false, 0, Some("a"))
+                .err(),
+            Some(messages::Error::QuotaExceeded)
+        );
+    }
+
+    #[test]
+    fn recover_with_bad_journal_entries() {
+        let dir = TempDir::new();
+        let (mut store, _) = recoverable_store(&dir);
+        let object_id = ObjectId::random();
+        store
+            .create_object(object_id, 64, 0, 0, false, 0, None)
+            .unwrap();
+        store.seal_object(&object_id, None).unwrap();
+        drop(store);
+
+        // Entries overlapping the object, or outside the pool.
+        let mut journal = Journal::open(&dir.path().join(JOURNAL_FILE)).unwrap();
+        let entry = |size| JournalEntry {
+            size,
+            device_num: 0,
+            primary: true,
+            pinned: false,
+            owner_node: None,
+            namespace: None,
+        };
+        journal.sealed(64, entry(128)).unwrap();
+        journal.sealed(128, entry(u64::MAX - 127)).unwrap();
+        journal.sealed(1 << 20, entry(192)).unwrap();
+        drop(journal);
+
+        let (store, recovered) = recoverable_store(&dir);
+        assert_eq!(recovered, 1);
+        assert!(store.objects.contains_key(&object_id));
+        assert_eq!(store.journal.as_ref().unwrap().entries().count(), 1);
+    }
+}
@@ -0,0 +1,322 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::future::{self, BoxFuture};
+use futures::SinkExt;
+use log::{debug, info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::{oneshot, Semaphore};
+use tokio::time::Duration;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::lock::LockExt;
+use crate::object_store::{ObjectStore, STORE_CLIENT_ID};
+
+// Object bytes asked for per FetchResponse when pulling from a peer.
+const PULL_CHUNK_SIZE: u64 = 4 << 20;
+// Rounds over all known locations before a pull is given up.
+const PULL_ATTEMPTS: u32 = 3;
+// Wait before the next round, multiplied by the number of rounds so far.
+const PULL_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Tells the pull manager which peer stores may hold an object.
+pub trait ObjectLocator: Send + Sync {
+    /// TCP addresses of stores that may hold `object_id`, most promising
+    /// first.
+    fn locate<'a>(&'a self, object_id: &'a ObjectId) -> BoxFuture<'a, Vec<String>>;
+}
+
+/// Asks a fixed list of peers, in order, for every object.
+pub struct StaticPeers(pub Vec<String>);
+
+impl ObjectLocator for StaticPeers {
+    fn locate<'a>(&'a self, _object_id: &'a ObjectId) -> BoxFuture<'a, Vec<String>> {
+        Box::pin(future::ready(self.0.clone()))
+    }
+}
+
+#[derive(Debug)]
+pub enum PullError {
+    // The peer does not hold the object.
+    NotFound,
+    Store(messages::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for PullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PullError::NotFound => write!(f, "object not found"),
+            PullError::Store(e) => write!(f, "store error {}", e.as_str_name()),
+            PullError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for PullError {
+    fn from(e: io::Error) -> Self {
+        PullError::Io(e)
+    }
+}
+
+/// Copies objects that a local Get asked for but the store does not hold
+/// from peer stores, over the same TCP protocol remote clients use.
+pub struct PullManager {
+    object_store: Arc<Mutex<ObjectStore>>,
+    locator: Box<dyn ObjectLocator>,
+    // Limits how many objects are transferred at the same time.
+    permits: Semaphore,
+    // Pulls in progress, with everyone waiting for each of them.
+    in_flight: Mutex<HashMap<ObjectId, Vec<oneshot::Sender<bool>>>>,
+}
+
+impl PullManager {
+    pub fn new(
+        object_store: Arc<Mutex<ObjectStore>>,
+        locator: Box<dyn ObjectLocator>,
+        max_concurrent_pulls: usize,
+    ) -> Arc<Self> {
+        Arc::new(PullManager {
+            object_store,
+            locator,
+            permits: Semaphore::new(max_concurrent_pulls.max(1)),
+            in_flight: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Starts pulling `object_id` into the local store, unless a pull of it
+    /// is already running. The receiver fires with whether the object was
+    /// pulled. It is only sealed once the pull is done, but may also have
+    /// been created locally by someone else in the meantime.
+    pub fn start_pull(self: &Arc<Self>, object_id: ObjectId) -> oneshot::Receiver<bool> {
+        let (tx, rx) = oneshot::channel();
+        match self.in_flight.locked().entry(object_id) {
+            Entry::Occupied(mut e) => {
+                e.get_mut().push(tx);
+                return rx;
+            }
+            Entry::Vacant(e) => {
+                e.insert(vec![tx]);
+            }
+        }
+
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let pulled = manager.pull(&object_id).await;
+            let waiters = manager.in_flight.locked().remove(&object_id);
+            for waiter in waiters.unwrap_or_default() {
+                let _ = waiter.send(pulled);
+            }
+        });
+        rx
+    }
+
+    async fn pull(&self, object_id: &ObjectId) -> bool {
+        let locations = self.locator.locate(object_id).await;
+        if locations.is_empty() {
+            return false;
+        }
+        let Ok(_permit) = self.permits.acquire().await else {
+            return false;
+        };
+
+        for attempt in 1..=PULL_ATTEMPTS {
+            let mut retry = false;
+            for address in &locations {
+                match pull_object(&self.object_store, address, object_id).await {
+                    Ok(()) => {
+                        info!("Pulled {} from {}", object_id, address);
+                        return true;
+                    }
+                    Err(PullError::NotFound) => {
+                        debug!("{} does not hold {}", address, object_id);
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Pulling {} from {} failed (attempt {}/{}): {}",
+                            object_id, address, attempt, PULL_ATTEMPTS, e
+                        );
+                        retry = true;
+                    }
+                }
+            }
+            // Nobody has it, asking again will not help.
+            if !retry {
+                break;
+            }
+            if attempt < PULL_ATTEMPTS {
+                tokio::time::sleep(PULL_RETRY_DELAY * attempt).await;
+            }
+        }
+        false
+    }
+}
+
+impl PullError {
+    /// The error to report for the object, e.g. in a PushResult.
+    pub fn store_error(&self) -> messages::Error {
+        match self {
+            PullError::NotFound => messages::Error::ObjectNonexistent,
+            PullError::Store(e) => *e,
+            PullError::Io(_) => messages::Error::NodeUnreachable,
+        }
+    }
+}
+
+/// Streams an object from the store at `address` into a new local object and
+/// seals it. Anything written is dropped again if the transfer fails. Also
+/// succeeds if the object was created locally in the meantime, which then
+/// may not be sealed yet.
+pub async fn pull_object(
+    object_store: &Mutex<ObjectStore>,
+    address: &str,
+    object_id: &ObjectId,
+) -> Result<(), PullError> {
+    let stream = TcpStream::connect(address).await?;
+    stream.set_nodelay(true)?;
+    let mut framed = Framed::new(stream, MessageCodec {});
+    let request = Messages::FetchRequest(messages::FetchRequest {
+        object_id: object_id.binary(),
+        timeout_ms: 0,
+        max_chunk_size: PULL_CHUNK_SIZE,
+        no_pull: true,
+    });
+    framed.send(request).await?;
+
+    let mut created = false;
+    let mut offset = 0;
+    let result = loop {
+        let fr = match framed.next().await {
+            Some(Ok(Messages::FetchResponse(fr))) => fr,
+            Some(Ok(response)) => {
+                break Err(PullError::Io(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unexpected response {:?}", response),
+                )))
+            }
+            Some(Err(e)) => break Err(e.into()),
+            None => break Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+        match fr.error() {
+            messages::Error::Ok => {}
+            messages::Error::ObjectNonexistent | messages::Error::ObjectNotSealed => {
+                break Err(PullError::NotFound)
+            }
+            e => break Err(PullError::Store(e)),
+        }
+
+        let mut object_store = object_store.locked();
+        if !created {
+            match object_store.create_object(
+                *object_id,
+                fr.data_size,
+                fr.metadata_size,
+                0,
+                false,
+                STORE_CLIENT_ID,
+                None,
+            ) {
+                Ok(_) => {
+                    created = true;
+                    if !fr.owner.is_empty() {
+                        object_store.set_owner_node(object_id, fr.owner.clone());
+                    }
+                }
+                // Created locally since the Get missed it; the Get
+                // waits for that copy instead.
+                Err(messages::Error::ObjectExists) => break Ok(()),
+                Err(e) => break Err(PullError::Store(e)),
+            }
+        }
+        if let Err(e) = object_store.write_object(object_id, offset, &fr.data) {
+            break Err(PullError::Store(e));
+        }
+        offset += fr.data.len() as u64;
+        if fr.last && offset != fr.data_size + fr.metadata_size {
+            break Err(PullError::Io(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("object ended after {} bytes", offset),
+            )));
+        }
+        if fr.last {
+            break object_store
+                .seal_object(object_id, None)
+                .map_err(PullError::Store);
+        }
+    };
+
+    if result.is_err() && created {
+        let _ = object_store.locked().abort_object(object_id);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::RamAllocator;
+    use crate::test_util::TempDir;
+    use tokio::net::TcpListener;
+
+    // Accepts the next pull, returning its connection and the object pulled.
+    async fn accept(listener: &TcpListener) -> (Framed<TcpStream, MessageCodec>, ObjectId) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut framed = Framed::new(stream, MessageCodec {});
+        let Some(Ok(Messages::FetchRequest(fr))) = framed.next().await else {
+            panic!("expected a fetch request");
+        };
+        (framed, ObjectId::try_from_binary(&fr.object_id).unwrap())
+    }
+
+    async fn not_found(mut framed: Framed<TcpStream, MessageCodec>, object_id: &ObjectId) {
+        let mut response = messages::FetchResponse {
+            object_id: object_id.binary(),
+            last: true,
+            ..Default::default()
+        };
+        response.set_error(messages::Error::ObjectNonexistent);
+        framed
+            .send(Messages::FetchResponse(response))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn pulls_wait_for_a_permit() {
+        let dir = TempDir::new();
+        let allocator = RamAllocator::new(dir.path(), 1 << 20, false).unwrap();
+        let object_store = Arc::new(Mutex::new(ObjectStore::new(allocator)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peers = StaticPeers(vec![listener.local_addr().unwrap().to_string()]);
+        let manager = PullManager::new(object_store, Box::new(peers), 1);
+
+        let first = ObjectId::random();
+        let second = ObjectId::random();
+        let first_pulled = manager.start_pull(first);
+        let second_pulled = manager.start_pull(second);
+        // Joins the pull already running.
+        let first_again = manager.start_pull(first);
+
+        let (framed, pulled) = accept(&listener).await;
+        let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+        assert!(accepted.is_err(), "two pulls ran at once");
+        not_found(framed, &pulled).await;
+        let (framed, waiting) = accept(&listener).await;
+        assert_eq!(waiting, if pulled == first { second } else { first });
+        not_found(framed, &waiting).await;
+
+        assert!(!first_pulled.await.unwrap());
+        assert!(!first_again.await.unwrap());
+        assert!(!second_pulled.await.unwrap());
+        assert!(manager.in_flight.locked().is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use std::io;
+use std::sync::Mutex;
+
+use futures::future::{self, BoxFuture};
+use futures::SinkExt;
+use log::{debug, warn};
+use tokio::net::TcpStream;
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::lock::LockExt;
+use crate::object_store::ObjectStore;
+use crate::pull_manager::pull_object;
+
+/// How many stores each store passes a push on to, unless asked otherwise.
+pub const DEFAULT_FANOUT: u32 = 2;
+
+// Pushes are spread as a tree: every store copies the object from its
+// parent, then forwards the push to up to `fanout` children, each of which
+// takes care of an equal share of the remaining nodes. A fanout of 1 turns
+// the tree into a chain. This keeps the source from sending the object more
+// than `fanout` times, however many nodes there are.
+
+/// Handles a PushRequest received by the store at `node`, either from a
+/// client (the store is the source) or from its parent in the tree.
+pub async fn handle_push(
+    object_store: &Mutex<ObjectStore>,
+    node: Option<&str>,
+    pr: messages::PushRequest,
+) -> messages::PushResponse {
+    let fanout = match pr.fanout {
+        0 => DEFAULT_FANOUT,
+        fanout => fanout,
+    };
+    let mut nodes = Vec::new();
+    for peer in pr.nodes {
+        if Some(peer.as_str()) != node && !nodes.contains(&peer) {
+            nodes.push(peer);
+        }
+    }
+    let failed = |nodes: Vec<String>, error: messages::Error| -> Vec<messages::PushResult> {
+        nodes
+            .into_iter()
+            .map(|node| push_result(node, error))
+            .collect()
+    };
+    let mut response = messages::PushResponse {
+        object_id: pr.object_id.clone(),
+        results: Vec::new(),
+    };
+
+    let (Ok(object_id), Some(node)) = (ObjectId::try_from_binary(&pr.object_id), node) else {
+        // Without a TCP address, no one can copy the object from here.
+        response.results = failed(nodes, messages::Error::InvalidRequest);
+        return response;
+    };
+    if !pr.source.is_empty() {
+        // Forwarded by our parent, which expects a result for us too.
+        let result = receive(object_store, &pr.source, &object_id).await;
+        response.results.push(push_result(node.to_string(), result));
+        if result != messages::Error::Ok {
+            response.results.extend(failed(nodes, result));
+            return response;
+        }
+    } else if !object_store.locked().contains(&object_id) {
+        response.results = failed(nodes, messages::Error::ObjectNonexistent);
+        return response;
+    }
+
+    let results = push_to(node.to_string(), object_id, nodes, fanout).await;
+    response.results.extend(results);
+    response
+}
+
+// Copies the object from `source` unless it is already here, and waits for
+// it to be sealed.
+async fn receive(
+    object_store: &Mutex<ObjectStore>,
+    source: &str,
+    object_id: &ObjectId,
+) -> messages::Error {
+    if !object_store.locked().contains(object_id) {
+        if let Err(e) = pull_object(object_store, source, object_id).await {
+            warn!(
+                "Receiving pushed {} from {} failed: {}",
+                object_id, source, e
+            );
+            return e.store_error();
+        }
+    }
+    // Someone else may be creating it here.
+    let sealed = object_store.locked().wait_for_seal(object_id);
+    if let Some(sealed) = sealed {
+        let _ = sealed.await;
+    }
+    if object_store.locked().contains(object_id) {
+        messages::Error::Ok
+    } else {
+        messages::Error::ObjectNonexistent
+    }
+}
+
+/// Pushes the object from `node`, this store, to `nodes`: forwards the push
+/// to `fanout` children, each responsible for a share of them, and collects
+/// their results.
+pub fn push_to(
+    node: String,
+    object_id: ObjectId,
+    nodes: Vec<String>,
+    fanout: u32,
+) -> BoxFuture<'static, Vec<messages::PushResult>> {
+    Box::pin(async move {
+        if nodes.is_empty() {
+            return Vec::new();
+        }
+        let share = nodes.len().div_ceil(fanout as usize);
+        let children = nodes.chunks(share).map(|subtree| {
+            let (child, rest) = subtree.split_first().unwrap();
+            forward(
+                node.clone(),
+                object_id,
+                child.clone(),
+                rest.to_vec(),
+                fanout,
+            )
+        });
+        future::join_all(children).await.concat()
+    })
+}
+
+async fn forward(
+    node: String,
+    object_id: ObjectId,
+    child: String,
+    subtree: Vec<String>,
+    fanout: u32,
+) -> Vec<messages::PushResult> {
+    let request = messages::PushRequest {
+        object_id: object_id.binary(),
+        nodes: subtree.clone(),
+        fanout,
+        source: node.clone(),
+    };
+    match send_push(&child, request).await {
+        Ok(results) => {
+            debug!("Push of {} through {} done", object_id, child);
+            results
+        }
+        Err(e) => {
+            // Serve the child's share ourselves instead.
+            warn!("Pushing {} to {} failed: {}", object_id, child, e);
+            let mut results = vec![push_result(child, messages::Error::NodeUnreachable)];
+            results.extend(push_to(node, object_id, subtree, fanout).await);
+            results
+        }
+    }
+}
+
+async fn send_push(
+    address: &str,
+    request: messages::PushRequest,
+) -> io::Result<Vec<messages::PushResult>> {
+    let stream = TcpStream::connect(address).await?;
+    stream.set_nodelay(true)?;
+    let mut framed = Framed::new(stream, MessageCodec {});
+    framed.send(Messages::PushRequest(request)).await?;
+    match framed.next().await {
+        Some(Ok(Messages::PushResponse(pr))) => Ok(pr.results),
+        Some(Ok(response)) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected response {:?}", response),
+        )),
+        Some(Err(e)) => Err(e),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+fn push_result(node: String, error: messages::Error) -> messages::PushResult {
+    let mut result = messages::PushResult {
+        node,
+        ..Default::default()
+    };
+    result.set_error(error);
+    result
+}
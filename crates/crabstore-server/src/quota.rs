@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use crabstore_common::messages::messages;
+
+use crate::object_store::ClientId;
+
+// Clients name a namespace when they connect, and the objects they create
+// are charged to it for as long as they are in memory: their bytes of data
+// and metadata, and their count. A create that would take its namespace past
+// the namespace's quota fails with QuotaExceeded, even if the pool has room,
+// so that one runaway job cannot fill the pool for everybody else. Copies
+// pulled from other stores are not charged to anyone.
+//
+// Namespaces without a quota of their own, that of clients naming none
+// included, are charged together to DEFAULT_NAMESPACE and share its quota,
+// so that connecting under a new name does not bring a fresh quota.
+//
+// Each connection may also be limited to a client quota, over the objects it
+// created that are still in memory. What a connection created stops
+// counting against it when it disconnects, but still counts against its
+// namespace. Objects uploaded in chunks outlive the connection uploading
+// them, and only count against their namespace.
+//
+// Objects spilled to disk stop counting until they are read back, and
+// objects recovered or persisted by the last store count again for the
+// namespace that created them. Neither is checked against the quota: the
+// objects exist already, and refusing them would keep a namespace at its
+// quota from reading its own objects. Such a namespace can end up over its
+// quota, and then cannot create objects until it is back under it.
+
+/// Stands for every namespace without a quota of its own, clients that
+/// named none included, which are charged to it together.
+pub const DEFAULT_NAMESPACE: &str = "*";
+
+/// The most a namespace may hold in memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub max_bytes: Option<u64>,
+    pub max_objects: Option<u64>,
+}
+
+impl Quota {
+    fn allows(&self, usage: Option<&Usage>, bytes: u64, objects: u64) -> bool {
+        let (used_bytes, used_objects) = usage.map_or((0, 0), |usage| (usage.bytes, usage.objects));
+        !(self
+            .max_bytes
+            .is_some_and(|max| used_bytes.saturating_add(bytes) > max)
+            || self
+                .max_objects
+                .is_some_and(|max| used_objects.saturating_add(objects) > max))
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    bytes: u64,
+    objects: u64,
+}
+
+impl Usage {
+    fn add(&mut self, bytes: u64, objects: u64) {
+        self.bytes += bytes;
+        self.objects += objects;
+    }
+
+    // Returns whether nothing is left.
+    fn remove(&mut self, bytes: u64, objects: u64) -> bool {
+        self.bytes = self.bytes.saturating_sub(bytes);
+        self.objects = self.objects.saturating_sub(objects);
+        self.bytes == 0 && self.objects == 0
+    }
+}
+
+/// The quotas of the namespaces and of each client, and what each of them
+/// holds.
+#[derive(Default)]
+pub struct Quotas {
+    limits: HashMap<String, Quota>,
+    client_limit: Quota,
+    usage: HashMap<String, Usage>,
+    client_usage: HashMap<ClientId, Usage>,
+}
+
+impl Quotas {
+    /// Quotas by namespace, `DEFAULT_NAMESPACE` for every other one, and
+    /// the quota of every client.
+    pub fn new(limits: HashMap<String, Quota>, client_limit: Quota) -> Self {
+        Quotas {
+            limits,
+            client_limit,
+            usage: HashMap::new(),
+            client_usage: HashMap::new(),
+        }
+    }
+
+    // The namespace `namespace` is charged to: itself if it has a quota,
+    // else the default one.
+    fn charged<'a>(&self, namespace: &'a str) -> &'a str {
+        match self.limits.contains_key(namespace) {
+            true => namespace,
+            false => DEFAULT_NAMESPACE,
+        }
+    }
+
+    fn quota(&self, namespace: &str) -> Quota {
+        self.limits.get(namespace).copied().unwrap_or_default()
+    }
+
+    /// Fails with QuotaExceeded if `namespace`, or `client` if set, cannot
+    /// take `bytes` and `objects` more.
+    pub fn check(
+        &self,
+        namespace: &str,
+        client: Option<ClientId>,
+        bytes: u64,
+        objects: u64,
+    ) -> Result<(), messages::Error> {
+        let namespace = self.charged(namespace);
+        let client_allows = client.map_or(true, |client| {
+            self.client_limit
+                .allows(self.client_usage.get(&client), bytes, objects)
+        });
+        if !client_allows
+            || !self
+                .quota(namespace)
+                .allows(self.usage.get(namespace), bytes, objects)
+        {
+            return Err(messages::Error::QuotaExceeded);
+        }
+        Ok(())
+    }
+
+    pub fn charge(&mut self, namespace: &str, client: Option<ClientId>, bytes: u64, objects: u64) {
+        let namespace = self.charged(namespace);
+        self.usage
+            .entry(namespace.to_string())
+            .or_default()
+            .add(bytes, objects);
+        if let Some(client) = client {
+            self.client_usage
+                .entry(client)
+                .or_default()
+                .add(bytes, objects);
+        }
+    }
+
+    pub fn release(&mut self, namespace: &str, client: Option<ClientId>, bytes: u64, objects: u64) {
+        let namespace = self.charged(namespace);
+        if let Some(usage) = self.usage.get_mut(namespace) {
+            if usage.remove(bytes, objects) {
+                self.usage.remove(namespace);
+            }
+        }
+        if let Some(client) = client {
+            if let Some(usage) = self.client_usage.get_mut(&client) {
+                if usage.remove(bytes, objects) {
+                    self.client_usage.remove(&client);
+                }
+            }
+        }
+    }
+
+    /// Stops counting what `client` created against it, once it is gone.
+    pub fn forget_client(&mut self, client: ClientId) {
+        self.client_usage.remove(&client);
+    }
+
+    /// What the namespaces holding objects or with a quota of their own
+    /// hold, and may hold, by name. Those without a quota of their own are
+    /// counted together as `DEFAULT_NAMESPACE`.
+    pub fn stats(&self) -> Vec<messages::NamespaceStats> {
+        let mut namespaces: Vec<&String> = self.usage.keys().chain(self.limits.keys()).collect();
+        namespaces.sort();
+        namespaces.dedup();
+        namespaces
+            .into_iter()
+            .map(|namespace| {
+                let quota = self.quota(namespace);
+                let usage = self.usage.get(namespace);
+                messages::NamespaceStats {
+                    namespace: namespace.clone(),
+                    used_bytes: usage.map_or(0, |usage| usage.bytes),
+                    num_objects: usage.map_or(0, |usage| usage.objects),
+                    max_bytes: quota.max_bytes.unwrap_or_default(),
+                    max_objects: quota.max_objects.unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clients_are_forgotten() {
+        let one = Quota {
+            max_bytes: None,
+            max_objects: Some(1),
+        };
+        let mut quotas = Quotas::new(HashMap::new(), one);
+        quotas.charge("a", Some(0), 8, 1);
+        assert_eq!(
+            quotas.check("a", Some(0), 8, 1),
+            Err(messages::Error::QuotaExceeded)
+        );
+        assert_eq!(quotas.check("a", Some(1), 8, 1), Ok(()));
+        assert_eq!(quotas.check("a", None, 8, 1), Ok(()));
+
+        quotas.forget_client(0);
+        assert_eq!(quotas.check("a", Some(0), 8, 1), Ok(()));
+        // Releasing what a forgotten client created only frees its namespace.
+        quotas.release("a", Some(0), 8, 1);
+        assert!(quotas.stats().is_empty());
+        assert!(quotas.client_usage.is_empty());
+    }
+
+    #[test]
+    fn stats_of_the_default_namespace() {
+        let quota = Quota {
+            max_bytes: Some(100),
+            max_objects: None,
+        };
+        let mut quotas = Quotas::new(
+            HashMap::from([(DEFAULT_NAMESPACE.to_string(), quota)]),
+            Quota::default(),
+        );
+        let stats = quotas.stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!((stats[0].used_bytes, stats[0].max_bytes), (0, 100));
+
+        quotas.charge("x", None, 40, 1);
+        quotas.charge("y", None, 40, 1);
+        assert_eq!(
+            quotas.check("z", None, 40, 1),
+            Err(messages::Error::QuotaExceeded)
+        );
+        quotas.release("x", None, 40, 1);
+        assert_eq!(quotas.check("z", None, 40, 1), Ok(()));
+        let stats = quotas.stats();
+        assert_eq!(stats[0].namespace, DEFAULT_NAMESPACE);
+        assert_eq!((stats[0].used_bytes, stats[0].num_objects), (40, 1));
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use futures::SinkExt;
+use log::{debug, info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio_stream::StreamExt;
+use tokio_util::codec::Framed;
+
+use crabstore_common::messages::messages;
+use crabstore_common::messages::MessageCodec;
+use crabstore_common::messages::Messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::lock::LockExt;
+use crate::object_store::{ClientId, ObjectEvent, ObjectState, ObjectStore};
+
+// Reference counted objects are owned by the store they were created in,
+// which keeps the count: references held by its own clients, plus what
+// every other store holding a copy or references last reported. Those
+// stores report their count whenever it changes, and register with a count
+// of 0 when they get a copy, so the owner knows where copies are. Once the
+// count drops to zero, the owner deletes the object locally and on every
+// store it heard from.
+
+/// What happens to the copies of an object whose owner died, since nobody
+/// can tell anymore when it is no longer needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LostObjectPolicy {
+    /// Keep the copies, no longer reference counted, until evicted or
+    /// deleted by hand.
+    Keep,
+    /// Delete the copies, as their owner would have eventually.
+    Delete,
+}
+
+#[derive(Default)]
+struct Counted {
+    // The store keeping the count, `None` if it is this one.
+    owner: Option<String>,
+    // References held by clients of this store.
+    local: HashMap<ClientId, u64>,
+    // At the owner: references held by clients of other stores, per store.
+    remote: HashMap<String, u64>,
+}
+
+impl Counted {
+    fn local_count(&self) -> u64 {
+        self.local.values().sum()
+    }
+
+    fn total(&self) -> u64 {
+        self.local_count() + self.remote.values().sum::<u64>()
+    }
+}
+
+/// The count of references held here to an object owned elsewhere.
+pub struct Report {
+    owner: String,
+    object_id: ObjectId,
+    count: u64,
+    // Fired once the owner has the count.
+    done: Option<oneshot::Sender<()>>,
+}
+
+/// Reference counts of the reference counted objects this store owns or
+/// holds copies of or references to.
+pub struct References {
+    object_store: Arc<Mutex<ObjectStore>>,
+    // The address this store is known by, if it listens on TCP.
+    node: Option<String>,
+    policy: LostObjectPolicy,
+    objects: Mutex<HashMap<ObjectId, Counted>>,
+    // Counts to report to owners, sent by `report_references`.
+    reports: mpsc::UnboundedSender<Report>,
+}
+
+impl References {
+    pub fn new(
+        object_store: Arc<Mutex<ObjectStore>>,
+        node: Option<String>,
+        policy: LostObjectPolicy,
+    ) -> (Arc<Self>, mpsc::UnboundedReceiver<Report>) {
+        let (reports, rx) = mpsc::unbounded_channel();
+        let references = Arc::new(References {
+            object_store,
+            node,
+            policy,
+            objects: Mutex::new(HashMap::new()),
+            reports,
+        });
+        (references, rx)
+    }
+
+    /// Records a reference counted object created by `client_id`, which
+    /// holds the first reference.
+    pub fn created(&self, client_id: ClientId, object_id: ObjectId) {
+        let mut counted = Counted::default();
+        counted.local.insert(client_id, 1);
+        self.objects.locked().insert(object_id, counted);
+    }
+
+    /// The store counting the references to the object, if it is reference
+    /// counted.
+    pub fn owner(&self, object_id: &ObjectId) -> Option<String> {
+        match self.objects.locked().get(object_id) {
+            Some(Counted { owner: None, .. }) => self.node.clone(),
+            Some(Counted { owner, .. }) => owner.clone(),
+            None => self.object_store.locked().owner_node(object_id),
+        }
+    }
+
+    /// The references to the object this store knows of, if it is reference
+    /// counted: all of them at the owner, those held here elsewhere.
+    pub fn count(&self, object_id: &ObjectId) -> Option<u64> {
+        self.objects.locked().get(object_id).map(Counted::total)
+    }
+
+    /// Fills in how references to the object are counted, if they are.
+    pub fn describe(&self, object_id: &ObjectId, response: &mut messages::InspectResponse) {
+        let objects = self.objects.locked();
+        let Some(counted) = objects.get(object_id) else {
+            return;
+        };
+        response.ref_counted = true;
+        response.owner_node = counted.owner.clone().unwrap_or_default();
+        response.local_references = counted.local_count();
+        response.remote_references = counted
+            .remote
+            .iter()
+            .map(|(node, count)| messages::RemoteReferences {
+                node: node.clone(),
+                count: *count,
+            })
+            .collect();
+    }
+
+    /// Adds a reference held by `client_id`. For objects owned elsewhere,
+    /// returns a receiver that fires once the owner knows of it, so that it
+    /// cannot miss the reference and delete the object in the meantime.
+    pub fn add(
+        &self,
+        client_id: ClientId,
+        object_id: &ObjectId,
+    ) -> Result<Option<oneshot::Receiver<()>>, messages::Error> {
+        let mut objects = self.objects.locked();
+        if !objects.contains_key(object_id) {
+            // A copy whose seal has not been processed yet.
+            let object_store = self.object_store.locked();
+            if !object_store.contains(object_id) {
+                return Err(messages::Error::ObjectNonexistent);
+            }
+            let owner = object_store
+                .owner_node(object_id)
+                .ok_or(messages::Error::InvalidRequest)?;
+            objects.insert(
+                *object_id,
+                Counted {
+                    owner: Some(owner),
+                    ..Default::default()
+                },
+            );
+        }
+        let counted = objects.get_mut(object_id).unwrap();
+        *counted.local.entry(client_id).or_default() += 1;
+        if counted.owner.is_none() {
+            return Ok(None);
+        }
+        let (done, reported) = oneshot::channel();
+        self.changed(&mut objects, object_id, Some(done));
+        Ok(Some(reported))
+    }
+
+    pub fn remove(&self, client_id: ClientId, object_id: &ObjectId) -> Result<(), messages::Error> {
+        let mut objects = self.objects.locked();
+        let count = objects
+            .get_mut(object_id)
+            .and_then(|counted| counted.local.get_mut(&client_id))
+            // Removing a reference this client never added.
+            .ok_or(messages::Error::UnexpectedError)?;
+        *count -= 1;
+        if *count == 0 {
+            objects.get_mut(object_id).unwrap().local.remove(&client_id);
+        }
+        self.changed(&mut objects, object_id, None);
+        Ok(())
+    }
+
+    /// Drops every reference held by a client that went away.
+    pub fn client_disconnected(&self, client_id: ClientId) {
+        let mut objects = self.objects.locked();
+        let held: Vec<ObjectId> = objects
+            .iter_mut()
+            .filter_map(|(object_id, counted)| counted.local.remove(&client_id).map(|_| *object_id))
+            .collect();
+        for object_id in held {
+            self.changed(&mut objects, &object_id, None);
+        }
+    }
+
+    /// Applies a count reported by another store for an object owned here.
+    pub fn update(
+        &self,
+        node: String,
+        object_id: &ObjectId,
+        count: u64,
+    ) -> Result<(), messages::Error> {
+        let mut objects = self.objects.locked();
+        match objects.get_mut(object_id) {
+            Some(counted) if counted.owner.is_none() => {
+                counted.remote.insert(node, count);
+            }
+            _ => return Err(messages::Error::ObjectNonexistent),
+        }
+        self.changed(&mut objects, object_id, None);
+        Ok(())
+    }
+
+    /// Forgets the references held on `node`, which died, and applies the
+    /// lost object policy to the objects it owned.
+    pub fn node_died(&self, node: &str) {
+        let mut objects = self.objects.locked();
+        let mut owned_here = Vec::new();
+        let mut lost = Vec::new();
+        for (object_id, counted) in objects.iter_mut() {
+            if counted.owner.as_deref() == Some(node) {
+                lost.push(*object_id);
+            } else if counted.remote.remove(node).is_some() {
+                owned_here.push(*object_id);
+            }
+        }
+        for object_id in owned_here {
+            self.changed(&mut objects, &object_id, None);
+        }
+        for object_id in lost {
+            objects.remove(&object_id);
+            match self.policy {
+                LostObjectPolicy::Keep => {
+                    info!("Keeping {}, whose owner {} died", object_id, node);
+                }
+                LostObjectPolicy::Delete => {
+                    info!("Deleting {}, whose owner {} died", object_id, node);
+                    let _ = self.object_store.locked().delete_object(&object_id);
+                }
+            }
+        }
+    }
+
+    // Acts on a change to the count of `object_id`: reports it to the owner,
+    // or, at the owner, deletes the object everywhere once it is unused.
+    fn changed(
+        &self,
+        objects: &mut HashMap<ObjectId, Counted>,
+        object_id: &ObjectId,
+        done: Option<oneshot::Sender<()>>,
+    ) {
+        let Some(counted) = objects.get(object_id) else {
+            return;
+        };
+        if let Some(owner) = counted.owner.clone() {
+            let count = counted.local_count();
+            let _ = self.reports.send(Report {
+                owner,
+                object_id: *object_id,
+                count,
+                done,
+            });
+            if count == 0 && !self.object_store.locked().contains(object_id) {
+                // Neither a copy nor references left here.
+                objects.remove(object_id);
+            }
+            return;
+        }
+        if counted.total() > 0 {
+            return;
+        }
+        let mut object_store = self.object_store.locked();
+        if object_store.state(object_id) == Some(ObjectState::Created) {
+            // Deleted once sealed, or aborted.
+            return;
+        }
+        let counted = objects.remove(object_id).unwrap();
+        info!("{} is no longer referenced, deleting it", object_id);
+        let _ = object_store.delete_object(object_id);
+        for node in counted.remote.into_keys() {
+            tokio::spawn(delete_copy(node, *object_id));
+        }
+    }
+
+    // Keeps track of copies as they come and go.
+    fn object_event(&self, event: ObjectEvent) {
+        let mut objects = self.objects.locked();
+        match event {
+            ObjectEvent::Sealed(object_id) if objects.contains_key(&object_id) => {
+                // Owned here, it may have lost its references before seal.
+                self.changed(&mut objects, &object_id, None);
+            }
+            ObjectEvent::Sealed(object_id) => {
+                let owner = self.object_store.locked().owner_node(&object_id);
+                if let Some(owner) = owner {
+                    debug!("Registering the copy of {} with {}", object_id, owner);
+                    let _ = self.reports.send(Report {
+                        owner: owner.clone(),
+                        object_id,
+                        count: 0,
+                        done: None,
+                    });
+                    objects.insert(
+                        object_id,
+                        Counted {
+                            owner: Some(owner),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+            ObjectEvent::Removed(object_id) => {
+                let unused = objects
+                    .get(&object_id)
+                    .is_some_and(|counted| counted.owner.is_some() && counted.local.is_empty());
+                if unused {
+                    objects.remove(&object_id);
+                }
+            }
+        }
+    }
+}
+
+/// Follows the objects sealed in and removed from the store.
+pub async fn track_copies(
+    references: Arc<References>,
+    mut events: mpsc::UnboundedReceiver<ObjectEvent>,
+) {
+    while let Some(event) = events.recv().await {
+        references.object_event(event);
+    }
+}
+
+/// Sends the counts of this store's references to the objects' owners.
+pub async fn report_references(
+    references: Arc<References>,
+    mut reports: mpsc::UnboundedReceiver<Report>,
+) {
+    let Some(node) = references.node.clone() else {
+        // Nobody can have pulled a copy from us, nothing to report.
+        return;
+    };
+    let mut connections: HashMap<String, Framed<TcpStream, MessageCodec>> = HashMap::new();
+    while let Some(report) = reports.recv().await {
+        let Report {
+            owner,
+            object_id,
+            count,
+            done,
+        } = report;
+        let request = messages::ReferenceUpdateRequest {
+            object_id: object_id.binary(),
+            node: node.clone(),
+            count,
+        };
+        match send_update(&mut connections, &owner, request).await {
+            Ok(messages::Error::Ok) => {}
+            Ok(messages::Error::ObjectNonexistent) => {
+                // The owner deleted the object already.
+                debug!("{} no longer exists at {}, deleting it", object_id, owner);
+                let _ = references.object_store.locked().delete_object(&object_id);
+            }
+            Ok(e) => warn!("Reporting references to {} failed: {:?}", object_id, e),
+            Err(e) => {
+                // The owner is probably dead, which membership will notice.
+                warn!(
+                    "Reporting references to {} to {} failed: {}",
+                    object_id, owner, e
+                );
+                connections.remove(&owner);
+            }
+        }
+        if let Some(done) = done {
+            let _ = done.send(());
+        }
+    }
+}
+
+async fn send_update(
+    connections: &mut HashMap<String, Framed<TcpStream, MessageCodec>>,
+    owner: &str,
+    request: messages::ReferenceUpdateRequest,
+) -> io::Result<messages::Error> {
+    if !connections.contains_key(owner) {
+        let stream = TcpStream::connect(owner).await?;
+        stream.set_nodelay(true)?;
+        connections.insert(owner.to_string(), Framed::new(stream, MessageCodec {}));
+    }
+    let framed = connections.get_mut(owner).unwrap();
+    framed
+        .send(Messages::ReferenceUpdateRequest(request))
+        .await?;
+    match framed.next().await {
+        Some(Ok(Messages::ReferenceUpdateResponse(ur))) => Ok(ur.error()),
+        Some(Ok(_)) => Err(io::ErrorKind::InvalidData.into()),
+        Some(Err(e)) => Err(e),
+        None => Err(io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+// Deletes the copy of an object on another store.
+async fn delete_copy(node: String, object_id: ObjectId) {
+    let exchange = async {
+        let stream = TcpStream::connect(&node).await?;
+        let mut framed = Framed::new(stream, MessageCodec {});
+        let request = messages::DeleteRequest {
+            object_id: object_id.binary(),
+        };
+        framed.send(Messages::DeleteRequest(request)).await?;
+        match framed.next().await {
+            Some(Ok(Messages::DeleteResponse(_))) => Ok(()),
+            Some(Ok(_)) => Err(io::ErrorKind::InvalidData.into()),
+            Some(Err(e)) => Err(e),
+            None => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+        }
+    };
+    match exchange.await {
+        Ok(()) => debug!("Deleted the copy of {} on {}", object_id, node),
+        Err(e) => warn!(
+            "Deleting the copy of {} on {} failed: {}",
+            object_id, node, e
+        ),
+    }
+}
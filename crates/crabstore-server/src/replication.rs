@@ -0,0 +1,203 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, info, warn};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crabstore_common::messages::messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::lock::LockExt;
+use crate::membership::Membership;
+use crate::object_store::{ObjectEvent, ObjectState, ObjectStore};
+use crate::push::{self, DEFAULT_FANOUT};
+
+// Objects created here can ask to be held by several stores. Once such an
+// object is sealed, copies are pushed to the live peers with the most free
+// memory. The peers that took a copy are remembered, and when one of them
+// dies the copy is made again elsewhere. Stores holding a copy do not
+// replicate it themselves, so an object whose creating store died stays
+// with the copies it had.
+
+struct Replicated {
+    factor: u32,
+    // Peers known to hold a copy.
+    replicas: HashSet<String>,
+    // Whether copies are being pushed right now.
+    pushing: bool,
+}
+
+/// Keeps the objects created here on as many stores as they asked for.
+pub struct Replicator {
+    object_store: Arc<Mutex<ObjectStore>>,
+    // Without peers, or a TCP address for them to copy from, objects cannot
+    // be replicated and stay under-replicated.
+    membership: Option<Arc<Membership>>,
+    node: Option<String>,
+    default_factor: u32,
+    objects: Mutex<HashMap<ObjectId, Replicated>>,
+}
+
+impl Replicator {
+    pub fn new(
+        object_store: Arc<Mutex<ObjectStore>>,
+        membership: Option<Arc<Membership>>,
+        node: Option<String>,
+        default_factor: u32,
+    ) -> Arc<Self> {
+        Arc::new(Replicator {
+            object_store,
+            membership,
+            node,
+            default_factor,
+            objects: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Records the replication factor an object was created with, 0 for the
+    /// store's default. Mutable objects keep changing after seal and are
+    /// never replicated.
+    pub fn track(&self, object_id: ObjectId, factor: u32, is_mutable: bool) {
+        let factor = match factor {
+            0 => self.default_factor,
+            factor => factor,
+        };
+        if factor <= 1 || is_mutable {
+            return;
+        }
+        self.objects.locked().insert(
+            object_id,
+            Replicated {
+                factor,
+                replicas: HashSet::new(),
+                pushing: false,
+            },
+        );
+    }
+
+    /// Sealed objects that fewer stores hold than they should.
+    pub fn under_replicated(&self) -> Vec<messages::UnderReplicated> {
+        let object_store = self.object_store.locked();
+        let objects = self.objects.locked();
+        let mut under_replicated: Vec<_> = objects
+            .iter()
+            .filter(|(object_id, object)| {
+                object_store.contains(object_id)
+                    && object.replicas.len() + 1 < object.factor as usize
+            })
+            .map(|(object_id, object)| messages::UnderReplicated {
+                object_id: object_id.binary(),
+                replicas: object.replicas.len() as u32 + 1,
+                replication_factor: object.factor,
+            })
+            .collect();
+        under_replicated.sort_by(|a, b| a.object_id.cmp(&b.object_id));
+        under_replicated
+    }
+
+    // Starts copying `object_id` to more peers if it is sealed and short of
+    // copies.
+    fn replicate(self: &Arc<Self>, object_id: &ObjectId) {
+        let Some(membership) = &self.membership else {
+            return;
+        };
+        let alive = membership.alive_peers();
+        let object_store = self.object_store.locked();
+        let mut objects = self.objects.locked();
+        if let Some(object) = objects.get_mut(object_id) {
+            if object_store.contains(object_id) {
+                self.start_push(object_id, object, &alive);
+            }
+        }
+    }
+
+    // Forgets objects that are gone and copies on peers that died, then
+    // replaces the missing copies.
+    fn check(self: &Arc<Self>) {
+        let Some(membership) = &self.membership else {
+            return;
+        };
+        let alive = membership.alive_peers();
+        let object_store = self.object_store.locked();
+        let mut objects = self.objects.locked();
+        objects.retain(|object_id, _| object_store.state(object_id).is_some());
+        for (object_id, object) in objects.iter_mut() {
+            object.replicas.retain(|replica| {
+                let alive = alive.contains(replica);
+                if !alive {
+                    info!("Lost the copy of {} on {}", object_id, replica);
+                }
+                alive
+            });
+            if object_store.state(object_id) == Some(ObjectState::Sealed) {
+                self.start_push(object_id, object, &alive);
+            }
+        }
+    }
+
+    fn start_push(
+        self: &Arc<Self>,
+        object_id: &ObjectId,
+        object: &mut Replicated,
+        alive: &[String],
+    ) {
+        let Some(node) = &self.node else {
+            return;
+        };
+        let missing = (object.factor as usize - 1).saturating_sub(object.replicas.len());
+        if object.pushing || missing == 0 {
+            return;
+        }
+        let targets: Vec<String> = alive
+            .iter()
+            .filter(|peer| !object.replicas.contains(*peer))
+            .take(missing)
+            .cloned()
+            .collect();
+        if targets.is_empty() {
+            return;
+        }
+        debug!("Copying {} to {:?}", object_id, targets);
+        object.pushing = true;
+        let push = push::push_to(node.clone(), *object_id, targets, DEFAULT_FANOUT);
+        let replicator = self.clone();
+        let object_id = *object_id;
+        tokio::spawn(async move {
+            let results = push.await;
+            let mut objects = replicator.objects.locked();
+            let Some(object) = objects.get_mut(&object_id) else {
+                return;
+            };
+            object.pushing = false;
+            for result in results {
+                match result.error() {
+                    messages::Error::Ok => {
+                        object.replicas.insert(result.node);
+                    }
+                    e => warn!("Copying {} to {} failed: {:?}", object_id, result.node, e),
+                }
+            }
+        });
+    }
+}
+
+/// Replicates objects as they are sealed, and checks on their copies every
+/// `interval`.
+pub async fn replicate_objects(
+    replicator: Arc<Replicator>,
+    mut events: mpsc::UnboundedReceiver<ObjectEvent>,
+    interval: Duration,
+) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            Some(event) = events.recv() => {
+                if let ObjectEvent::Sealed(object_id) = event {
+                    replicator.replicate(&object_id);
+                }
+            }
+            _ = interval.tick() => replicator.check(),
+        }
+    }
+}
@@ -1,38 +1,252 @@
-use log::info;
-use std::path::PathBuf;
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use crate::allocator;
+use crate::cluster::ClusterConfig;
+use crate::journal::{Journal, JOURNAL_FILE};
+use crate::object_store::EvictionPolicy;
+use crate::quota::Quota;
+use crate::spill::SpillDir;
 use crate::store;
 
+/// How the store itself is set up, apart from its cluster.
+pub struct StoreConfig {
+    pub socket_path: PathBuf,
+    /// Permissions to give the socket, if not those of the umask.
+    pub socket_mode: Option<u32>,
+    pub sys_memory: u64,
+    pub mem_mapped_dir: PathBuf,
+    /// Reserve all of `sys_memory` at startup.
+    pub prefault: bool,
+    pub spill_dir: Option<PathBuf>,
+    /// The most bytes to spill, if limited.
+    pub spill_limit: Option<u64>,
+    pub eviction_policy: EvictionPolicy,
+    /// How long connected clients are still served once shutdown starts.
+    pub shutdown_timeout: Duration,
+    /// Move the objects to the spill directory on shutdown.
+    pub spill_on_shutdown: bool,
+    /// Serve Prometheus metrics over HTTP on this address, if set.
+    pub metrics_address: Option<String>,
+    /// Keep the pool and a journal of its objects in `mem_mapped_dir`, for
+    /// the next store to reattach to.
+    pub recoverable: bool,
+    /// Keep the objects in the spill directory across restarts.
+    pub persist: bool,
+    /// Quotas by namespace, `quota::DEFAULT_NAMESPACE` for every other one.
+    pub quotas: HashMap<String, Quota>,
+    /// Quota of each client connection.
+    pub client_quota: Quota,
+}
+
 pub struct Runner {
     socket_name: PathBuf,
-    sys_memory: i64,
+    sys_memory: u64,
     mem_mapped_dir: PathBuf,
 
     store: store::CrabStore,
 }
 
 impl Runner {
-    pub fn new(socket_name: PathBuf, sys_memory: i64, mem_mapped_dir: PathBuf) -> Runner {
-        // TODO: Check if directory has enough space;
-        let allocator = allocator::RamAllocator::new();
-        let store = store::CrabStore::new(socket_name.clone(), allocator);
-
-        Runner {
-            socket_name,
-            sys_memory,
-            mem_mapped_dir,
+    pub fn new(config: StoreConfig, cluster: ClusterConfig) -> io::Result<Runner> {
+        let in_use = if config.recoverable {
+            fs::metadata(config.mem_mapped_dir.join(allocator::POOL_FILE))
+                .map_or(0, |metadata| metadata.blocks() * 512)
+        } else {
+            0
+        };
+        check_mem_mapped_dir(&config.mem_mapped_dir, config.sys_memory, in_use)?;
+        let allocator = if config.recoverable {
+            allocator::RamAllocator::reattach(
+                &config.mem_mapped_dir,
+                config.sys_memory,
+                config.prefault,
+            )
+            .map_err(|e| invalid(format!("could not reattach to the pool: {}", e)))?
+        } else {
+            allocator::RamAllocator::new(&config.mem_mapped_dir, config.sys_memory, config.prefault)
+                .map_err(|e| {
+                    invalid(format!(
+                        "could not reserve {} bytes in mem_mapped_dir {}: {}",
+                        config.sys_memory,
+                        config.mem_mapped_dir.display(),
+                        e
+                    ))
+                })?
+        };
+        let spill = config
+            .spill_dir
+            .clone()
+            .map(|dir| {
+                let mut spill = SpillDir::new(dir.clone(), config.spill_limit)?;
+                if config.persist {
+                    spill.load_index()?;
+                }
+                check_spill_dir(&dir, config.spill_limit)?;
+                Ok::<_, io::Error>(spill)
+            })
+            .transpose()?;
+        let journal = config
+            .recoverable
+            .then(|| Journal::open(&config.mem_mapped_dir.join(JOURNAL_FILE)))
+            .transpose()?;
+        let store = store::CrabStore::new(&config, cluster, allocator, spill, journal)?;
+
+        Ok(Runner {
+            socket_name: config.socket_path,
+            sys_memory: config.sys_memory,
+            mem_mapped_dir: config.mem_mapped_dir,
             store,
-        }
+        })
     }
-    pub fn start(self) {
+    /// Serves clients until the store is shut down, and returns the status
+    /// to exit with.
+    pub fn start(self) -> i32 {
         info!(
-            "Starting Crabstore: Listening on {:?}. System Memory = {}",
-            self.socket_name, self.sys_memory
+            "Starting Crabstore: Listening on {:?}. System Memory = {}, Memory mapped dir = {:?}",
+            self.socket_name, self.sys_memory, self.mem_mapped_dir
         );
 
         let runtime = tokio::runtime::Runtime::new().unwrap();
 
-        runtime.block_on(async { self.store.start().await });
+        let result = runtime.block_on(async { self.store.start().await });
+        // Stops the background tasks, which frees the store's memory.
+        drop(runtime);
+        match result {
+            Ok(()) => {
+                info!("Store shut down");
+                0
+            }
+            Err(e) => {
+                error!("Store exited with error: {}", e);
+                1
+            }
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+// Makes sure the store's memory can be created in `dir`: it must be a
+// writable directory with room for `sys_memory` bytes, counting the `in_use`
+// bytes of a pool being reattached to. The file there is sparse, so without
+// this check a full file system would only show once objects are written,
+// as a crash.
+fn check_mem_mapped_dir(dir: &Path, sys_memory: u64, in_use: u64) -> io::Result<()> {
+    check_writable_dir(dir, "mem_mapped_dir")?;
+    let available = available_space(dir)? + in_use;
+    if available < sys_memory {
+        return Err(invalid(format!(
+            "mem_mapped_dir {} has {} bytes available, less than the {} bytes of sys_memory",
+            dir.display(),
+            available,
+            sys_memory
+        )));
+    }
+    if !in_memory(dir)? {
+        warn!(
+            "mem_mapped_dir {} is not on a tmpfs such as /dev/shm, objects will be written \
+             back to disk",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+// Makes sure the spill directory can take `limit` bytes of objects, or
+// warns about how little it can take if unlimited.
+fn check_spill_dir(dir: &Path, limit: Option<u64>) -> io::Result<()> {
+    check_writable_dir(dir, "spill_dir")?;
+    let available = available_space(dir)?;
+    match limit {
+        Some(limit) if available < limit => Err(invalid(format!(
+            "spill_dir {} has {} bytes available, less than the {} bytes of spill_limit",
+            dir.display(),
+            available,
+            limit
+        ))),
+        Some(_) => Ok(()),
+        None => {
+            info!(
+                "Spilling to {}, which has {} bytes available",
+                dir.display(),
+                available
+            );
+            Ok(())
+        }
+    }
+}
+
+fn check_writable_dir(dir: &Path, key: &str) -> io::Result<()> {
+    match fs::metadata(dir) {
+        Ok(metadata) if metadata.is_dir() => {}
+        Ok(_) => {
+            return Err(invalid(format!(
+                "{} {} is not a directory",
+                key,
+                dir.display()
+            )))
+        }
+        Err(e) => return Err(invalid(format!("{} {}: {}", key, dir.display(), e))),
+    }
+    let probe = dir.join(format!(".crabstore-probe-{}", std::process::id()));
+    match File::create(&probe) {
+        Ok(_) => {
+            let _ = fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(invalid(format!(
+            "{} {} is not writable: {}",
+            key,
+            dir.display(),
+            e
+        ))),
     }
 }
+
+// Bytes that unprivileged users may still write to the file system of
+// `path`. The statvfs field types differ between platforms, hence the casts.
+#[allow(clippy::unnecessary_cast)]
+fn available_space(path: &Path) -> io::Result<u64> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stat` is written on success.
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs succeeded.
+    let stat = unsafe { stat.assume_init() };
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+// Whether files under `path` live in memory rather than on disk.
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_cast)]
+fn in_memory(path: &Path) -> io::Result<bool> {
+    const TMPFS_MAGIC: i64 = 0x0102_1994;
+    const HUGETLBFS_MAGIC: i64 = 0x9584_58f6;
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: `path` is NUL terminated and `stat` is written on success.
+    if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statfs succeeded.
+    let stat = unsafe { stat.assume_init() };
+    Ok(matches!(stat.f_type as i64, TMPFS_MAGIC | HUGETLBFS_MAGIC))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn in_memory(_path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
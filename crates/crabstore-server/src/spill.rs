@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Bound;
+use std::path::PathBuf;
+
+use log::{info, warn};
+
+use crabstore_common::header::{ObjectHeader, HEADER_SIZE};
+use crabstore_common::messages::messages;
+use crabstore_common::objectid::ObjectId;
+
+use crate::names::{read_name, write_name};
+use crate::object_store::STORE_CLIENT_ID;
+
+// A store that persists its objects keeps them in its spill directory when
+// it shuts down, and writes an index of them there. The next store started
+// on the directory reads the index and treats the objects as spilled, so
+// they come back into memory as they are used.
+//
+// Index layout (all integers little endian):
+//
+//   magic    u32
+//   version  u16
+//   count    u64
+//   then for each object:
+//     header      [u8; HEADER_SIZE]  as in the store's memory
+//     device_num  i32
+//     flags       u8    primary, pinned, has an owner node, has a namespace
+//     owner_node  u16 length then UTF-8, if flagged
+//     namespace   u16 length then UTF-8, if flagged
+
+const INDEX_FILE: &str = "index";
+const INDEX_MAGIC: u32 = u32::from_le_bytes(*b"CRIX");
+const INDEX_VERSION: u16 = 1;
+
+const INDEX_PRIMARY: u8 = 1;
+const INDEX_PINNED: u8 = 1 << 1;
+const INDEX_OWNER_NODE: u8 = 1 << 2;
+const INDEX_NAMESPACE: u8 = 1 << 3;
+
+/// What is kept in memory about an object spilled to disk, to bring it back
+/// as it was.
+#[derive(Debug, Clone)]
+pub struct SpilledObject {
+    pub header: ObjectHeader,
+    pub device_num: i32,
+    pub owner_node: Option<String>,
+    pub primary: bool,
+    pub pinned: bool,
+    // When the object was last created or gotten before it was spilled, in
+    // microseconds since the Unix epoch.
+    pub last_access_us: u64,
+    // The namespace charged for the object once it is back in memory.
+    pub namespace: Option<String>,
+}
+
+impl SpilledObject {
+    pub fn info(&self, object_id: &ObjectId) -> messages::ObjectInfo {
+        messages::ObjectInfo {
+            object_id: object_id.binary(),
+            data_size: self.header.data_size,
+            metadata_size: self.header.metadata_size,
+            state: messages::ObjectState::Spilled.into(),
+            pinned: self.pinned,
+            primary: self.primary,
+            create_time_us: self.header.create_time_us,
+            last_access_us: self.last_access_us,
+            ref_count: 0,
+            references: 0,
+            owner: STORE_CLIENT_ID,
+            fallback_allocated: false,
+        }
+    }
+}
+
+/// Objects moved out of memory to make room, one file each holding their
+/// data followed by their metadata.
+pub struct SpillDir {
+    dir: PathBuf,
+    // The most bytes of objects to keep on disk, if limited.
+    limit: Option<u64>,
+    used: u64,
+    objects: BTreeMap<ObjectId, SpilledObject>,
+}
+
+impl SpillDir {
+    pub fn new(dir: PathBuf, limit: Option<u64>) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(SpillDir {
+            dir,
+            limit,
+            used: 0,
+            objects: BTreeMap::new(),
+        })
+    }
+
+    fn path(&self, object_id: &ObjectId) -> PathBuf {
+        self.dir.join(object_id.hex())
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Bytes of objects on disk.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// Whether an object of `size` bytes can be spilled without going over
+    /// the limit.
+    pub fn has_room(&self, size: u64) -> bool {
+        self.limit.map_or(true, |limit| self.used + size <= limit)
+    }
+
+    pub fn object_ids(&self) -> impl Iterator<Item = &ObjectId> {
+        self.objects.keys()
+    }
+
+    /// The objects from `start` on, in the order of their IDs.
+    pub fn objects(
+        &self,
+        start: Bound<ObjectId>,
+    ) -> impl Iterator<Item = (&ObjectId, &SpilledObject)> {
+        self.objects.range((start, Bound::Unbounded))
+    }
+
+    pub fn get(&self, object_id: &ObjectId) -> Option<&SpilledObject> {
+        self.objects.get(object_id)
+    }
+
+    pub fn get_mut(&mut self, object_id: &ObjectId) -> Option<&mut SpilledObject> {
+        self.objects.get_mut(object_id)
+    }
+
+    /// Writes `bytes` out as the contents of `object_id`.
+    pub fn spill(
+        &mut self,
+        object_id: ObjectId,
+        object: SpilledObject,
+        bytes: &[u8],
+    ) -> io::Result<()> {
+        let mut file = File::create(self.path(&object_id))?;
+        file.write_all(bytes)?;
+        file.sync_data()?;
+        self.used += bytes.len() as u64;
+        self.objects.insert(object_id, object);
+        Ok(())
+    }
+
+    /// Reads the contents of `object_id` back into `buffer`, which must be
+    /// exactly as large.
+    pub fn read(&self, object_id: &ObjectId, buffer: &mut [u8]) -> io::Result<()> {
+        File::open(self.path(object_id))?.read_exact(buffer)
+    }
+
+    /// Writes the index of the spilled objects, for `load_index` to pick
+    /// up after a restart. Returns how many objects it lists.
+    pub fn save_index(&self) -> io::Result<usize> {
+        let temp = self.dir.join(format!("{}.tmp", INDEX_FILE));
+        let mut file = BufWriter::new(File::create(&temp)?);
+        file.write_all(&INDEX_MAGIC.to_le_bytes())?;
+        file.write_all(&INDEX_VERSION.to_le_bytes())?;
+        file.write_all(&(self.objects.len() as u64).to_le_bytes())?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        for object in self.objects.values() {
+            object.header.init(&mut header);
+            file.write_all(&header)?;
+            file.write_all(&object.device_num.to_le_bytes())?;
+            let mut flags = 0;
+            if object.primary {
+                flags |= INDEX_PRIMARY;
+            }
+            if object.pinned {
+                flags |= INDEX_PINNED;
+            }
+            if object.owner_node.is_some() {
+                flags |= INDEX_OWNER_NODE;
+            }
+            if object.namespace.is_some() {
+                flags |= INDEX_NAMESPACE;
+            }
+            file.write_all(&[flags])?;
+            if let Some(owner_node) = &object.owner_node {
+                write_name(&mut file, owner_node, "owner node name")?;
+            }
+            if let Some(namespace) = &object.namespace {
+                write_name(&mut file, namespace, "namespace")?;
+            }
+        }
+        file.into_inner()?.sync_all()?;
+        fs::rename(temp, self.dir.join(INDEX_FILE))?;
+        Ok(self.objects.len())
+    }
+
+    /// Takes over the objects listed in the index left by a store that
+    /// persisted them, and deletes the object files it does not list.
+    ///
+    /// The index is deleted once read, as it no longer matches the
+    /// directory as soon as objects are restored or spilled. Without one,
+    /// e.g. after a crash, the directory starts out empty.
+    pub fn load_index(&mut self) -> io::Result<()> {
+        let path = self.dir.join(INDEX_FILE);
+        match File::open(&path) {
+            Ok(file) => {
+                let objects = read_index(BufReader::new(file)).map_err(|e| {
+                    io::Error::new(
+                        e.kind(),
+                        format!("could not read the index {}: {}", path.display(), e),
+                    )
+                })?;
+                for (object_id, object) in objects {
+                    let size = object.header.data_size + object.header.metadata_size;
+                    match fs::metadata(self.path(&object_id)) {
+                        Ok(metadata) if metadata.len() == size => {
+                            self.used += size;
+                            self.objects.insert(object_id, object);
+                        }
+                        _ => warn!("Persisted object {} is missing or damaged", object_id),
+                    }
+                }
+                fs::remove_file(&path)?;
+                info!(
+                    "Found {} persisted objects in {}",
+                    self.objects.len(),
+                    self.dir.display()
+                );
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let Some(object_id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| ObjectId::from_hex(name).ok())
+            else {
+                continue;
+            };
+            if !self.objects.contains_key(&object_id) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets `object_id` and deletes its file.
+    pub fn remove(&mut self, object_id: &ObjectId) -> Option<SpilledObject> {
+        let object = self.objects.remove(object_id)?;
+        self.used -= object.header.data_size + object.header.metadata_size;
+        let _ = fs::remove_file(self.path(object_id));
+        Some(object)
+    }
+}
+
+fn read_index(mut file: impl Read) -> io::Result<Vec<(ObjectId, SpilledObject)>> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+    let mut buf = [0u8; 8];
+    file.read_exact(&mut buf[..4])?;
+    if u32::from_le_bytes(buf[..4].try_into().unwrap()) != INDEX_MAGIC {
+        return Err(invalid("not an index"));
+    }
+    file.read_exact(&mut buf[..2])?;
+    let version = u16::from_le_bytes(buf[..2].try_into().unwrap());
+    if version != INDEX_VERSION {
+        return Err(invalid(&format!("unsupported version {}", version)));
+    }
+    file.read_exact(&mut buf)?;
+    let count = u64::from_le_bytes(buf);
+
+    let mut objects = Vec::new();
+    let mut header = [0u8; HEADER_SIZE as usize];
+    for _ in 0..count {
+        file.read_exact(&mut header)?;
+        let header = ObjectHeader::read_from(&header).map_err(|e| invalid(&e.to_string()))?;
+        file.read_exact(&mut buf[..4])?;
+        let device_num = i32::from_le_bytes(buf[..4].try_into().unwrap());
+        file.read_exact(&mut buf[..1])?;
+        let flags = buf[0];
+        let owner_node = (flags & INDEX_OWNER_NODE != 0)
+            .then(|| read_name(&mut file, "owner node name"))
+            .transpose()?;
+        let namespace = (flags & INDEX_NAMESPACE != 0)
+            .then(|| read_name(&mut file, "namespace"))
+            .transpose()?;
+        // Not persisted, the objects count as last used when created.
+        let last_access_us = header.create_time_us;
+        objects.push((
+            header.object_id,
+            SpilledObject {
+                last_access_us,
+                namespace,
+                header,
+                device_num,
+                owner_node,
+                primary: flags & INDEX_PRIMARY != 0,
+                pinned: flags & INDEX_PINNED != 0,
+            },
+        ));
+    }
+    Ok(objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn spilled(
+        object_id: ObjectId,
+        owner_node: Option<&str>,
+        namespace: Option<&str>,
+    ) -> SpilledObject {
+        let mut header = ObjectHeader::new(object_id, 3, 1);
+        header.sealed = true;
+        SpilledObject {
+            header,
+            device_num: 0,
+            owner_node: owner_node.map(str::to_string),
+            primary: true,
+            pinned: false,
+            last_access_us: 0,
+            namespace: namespace.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn index_round_trip() {
+        let dir = TempDir::new();
+        let mut spill = SpillDir::new(dir.path().to_path_buf(), None).unwrap();
+        let (first, second) = (ObjectId::random(), ObjectId::random());
+        spill
+            .spill(first, spilled(first, Some("node"), Some("a")), b"abcd")
+            .unwrap();
+        spill
+            .spill(second, spilled(second, None, None), b"efgh")
+            .unwrap();
+        assert_eq!(spill.save_index().unwrap(), 2);
+
+        let mut spill = SpillDir::new(dir.path().to_path_buf(), None).unwrap();
+        spill.load_index().unwrap();
+        assert_eq!((spill.len(), spill.used()), (2, 8));
+        let object = spill.get(&first).unwrap();
+        assert_eq!(object.owner_node.as_deref(), Some("node"));
+        assert_eq!(object.namespace.as_deref(), Some("a"));
+        assert!(object.primary && !object.pinned);
+        let object = spill.get(&second).unwrap();
+        assert_eq!(
+            (object.owner_node.as_deref(), object.namespace.as_deref()),
+            (None, None)
+        );
+        let mut contents = [0u8; 4];
+        spill.read(&second, &mut contents).unwrap();
+        assert_eq!(&contents, b"efgh");
+    }
+}
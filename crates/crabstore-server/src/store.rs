@@ -1,109 +1,2734 @@
+use crabstore_common::header::CHECKSUM_SIZE;
 use crabstore_common::messages::messages;
 use crabstore_common::messages::MessageCodec;
 use crabstore_common::messages::Messages;
+use crabstore_common::objectid::{ObjectId, UNIQUE_ID_SIZE};
+use crabstore_directory::client::DirectoryClient;
+use crabstore_directory::directory::ObjectDirectory;
+use crabstore_directory::server as directory_server;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use futures::SinkExt;
 use log::debug;
 use log::error;
 use log::info;
+use log::warn;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::io;
+use std::net::SocketAddr;
+use std::ops::Bound;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use tokio::net::{UnixListener, UnixStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener};
 use tokio::signal;
+use tokio::signal::unix::SignalKind;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::{JoinError, JoinSet};
+use tokio::time::{Duration, Instant};
 use tokio_stream::StreamExt;
 use tokio_util::codec::Framed;
 
 use crate::allocator::RamAllocator;
+use crate::chunked::{self, Uploads};
+use crate::cluster::{report_locations, ClusterConfig, Directory, DirectoryLocator};
+use crate::journal::Journal;
+use crate::lock::LockExt;
+use crate::membership::{self, Membership};
+use crate::metrics::{self, Metrics, Operation};
+use crate::names::MAX_NAME_LEN;
+use crate::object_store::{self, ClientId, ObjectStore};
+use crate::pull_manager::{ObjectLocator, PullManager, StaticPeers};
+use crate::push;
+use crate::quota::{Quotas, DEFAULT_NAMESPACE};
+use crate::refcount::{self, References};
+use crate::replication::{self, Replicator};
+use crate::runner::StoreConfig;
+use crate::spill::SpillDir;
+
+// Object bytes sent per FetchResponse unless the client asks for less.
+const DEFAULT_CHUNK_SIZE: u64 = 1 << 20;
+pub(crate) const MAX_CHUNK_SIZE: u64 = 64 << 20;
+// Objects listed per ListResponse unless the client asks for fewer, and the
+// most it may ask for.
+const DEFAULT_LIST_LIMIT: usize = 1000;
+const MAX_LIST_LIMIT: usize = 10_000;
+// How long a create waits for a concurrent create of the same object to be
+// sealed or aborted before giving up with ObjectInUse.
+const CREATE_WAIT_TIMEOUT: Duration = Duration::from_secs(60);
+// How long a fetch of a mutable object in the middle of an update first waits
+// before trying again, and the most it waits between two tries.
+const SNAPSHOT_RETRY_DELAY: Duration = Duration::from_micros(100);
+const MAX_SNAPSHOT_RETRY_DELAY: Duration = Duration::from_millis(10);
+// How many frames that fail to decode a client may send before the store
+// closes its connection.
+const MAX_MALFORMED_FRAMES: usize = 8;
 
 pub struct CrabStore {
     socket_path: PathBuf,
-    allocator: Arc<Mutex<RamAllocator>>,
+    // Permissions to give the socket, if not those of the umask.
+    socket_mode: Option<u32>,
+    // How long connected clients are still served once shutdown starts.
+    shutdown_timeout: Duration,
+    spill_on_shutdown: bool,
+    persist: bool,
+    // Also serve clients over TCP on this address, if set.
+    tcp_address: Option<String>,
+    // Serve Prometheus metrics over HTTP on this address, if set.
+    metrics_address: Option<String>,
+    context: Arc<StoreContext>,
+    // Cluster tasks to run next to the accept loop, e.g. reporting to the
+    // directory and heartbeats.
+    background: Mutex<Vec<BoxFuture<'static, ()>>>,
+}
+
+// State shared by every connection.
+struct StoreContext {
+    object_store: Arc<Mutex<ObjectStore>>,
+    // Fetches objects missing here from peers, if any are configured.
+    pull_manager: Option<Arc<PullManager>>,
+    // Set if this store hosts the cluster's object directory.
+    directory: Option<Arc<Mutex<ObjectDirectory>>>,
+    // Set if this store has peers.
+    membership: Option<Arc<Membership>>,
+    // The address peers reach this store on, if it listens on TCP.
+    node: Option<String>,
+    replicator: Arc<Replicator>,
+    references: Arc<References>,
+    uploads: Arc<Uploads>,
+    // Set once the store starts shutting down, to how long clients are still
+    // served.
+    shutdown: watch::Sender<Option<Duration>>,
+    metrics: Arc<Metrics>,
 }
 
 impl CrabStore {
-    pub fn new(socket_path: PathBuf, allocator: RamAllocator) -> Self {
-        let allocator_mutex = Arc::new(Mutex::new(allocator));
-        CrabStore {
-            socket_path,
-            allocator: allocator_mutex,
+    pub fn new(
+        config: &StoreConfig,
+        cluster: ClusterConfig,
+        allocator: RamAllocator,
+        spill: Option<SpillDir>,
+        journal: Option<Journal>,
+    ) -> io::Result<Self> {
+        let mut object_store = ObjectStore::new(allocator);
+        // Set before recovering objects, which are charged to their
+        // namespaces.
+        object_store.set_quotas(Quotas::new(config.quotas.clone(), config.client_quota));
+        if let Some(spill) = spill {
+            object_store.set_spill_dir(spill);
+        }
+        if let Some(journal) = journal {
+            let recovered = object_store.set_journal(journal);
+            info!(
+                "Recovered {} objects from {}",
+                recovered,
+                object_store.store_path().display()
+            );
+        }
+        object_store.set_eviction_policy(config.eviction_policy);
+        let object_store = Arc::new(Mutex::new(object_store));
+        let embedded = cluster
+            .embed_directory
+            .then(|| Arc::new(Mutex::new(ObjectDirectory::new())));
+        let directory = match (&embedded, &cluster.directory_address) {
+            (Some(directory), _) => Some(Directory::Embedded(directory.clone())),
+            (None, Some(address)) => Some(Directory::Remote(DirectoryClient::new(address.clone()))),
+            (None, None) => None,
+        };
+
+        // Peers reach this store, and know it in the directory, by this.
+        let node = cluster.node_address().map(str::to_string);
+        let cluster_node = node.clone();
+        let needs_node = |what: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} needs a TCP address for this store", what),
+            )
+        };
+
+        let mut background = Vec::new();
+        let directory = directory.map(Arc::new);
+        let mut locator: Option<Box<dyn ObjectLocator>> = None;
+        if let Some(directory) = &directory {
+            let node = node
+                .clone()
+                .ok_or_else(|| needs_node("using an object directory"))?;
+            let (listener, sealed) = mpsc::unbounded_channel();
+            object_store.locked().add_listener(listener);
+            background.push(report_locations(directory.clone(), node.clone(), sealed).boxed());
+            locator = Some(Box::new(DirectoryLocator {
+                directory: directory.clone(),
+                node,
+                fallback: cluster.pull_from,
+            }));
+        } else if !cluster.pull_from.is_empty() {
+            locator = Some(Box::new(StaticPeers(cluster.pull_from)));
         }
+        let pull_manager = locator.map(|locator| {
+            PullManager::new(object_store.clone(), locator, cluster.max_concurrent_pulls)
+        });
+
+        let (references, reports) = References::new(
+            object_store.clone(),
+            node.clone(),
+            cluster.lost_object_policy,
+        );
+        let (listener, events) = mpsc::unbounded_channel();
+        object_store.locked().add_listener(listener);
+        background.push(refcount::track_copies(references.clone(), events).boxed());
+        background.push(refcount::report_references(references.clone(), reports).boxed());
+
+        let mut membership = None;
+        if !cluster.peers.is_empty() {
+            let node = node.ok_or_else(|| needs_node("joining peers"))?;
+            let members = Arc::new(Membership::new(
+                cluster.node_id.unwrap_or_else(|| node.clone()),
+                node,
+                &cluster.peers,
+                cluster.heartbeat_interval,
+                cluster.dead_node_timeout,
+            ));
+            for peer in cluster.peers {
+                background.push(
+                    membership::heartbeat_peer(members.clone(), object_store.clone(), peer).boxed(),
+                );
+            }
+            background.push(
+                membership::detect_failures(members.clone(), directory, references.clone()).boxed(),
+            );
+            membership = Some(members);
+        } else if cluster.replication_factor > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a replication factor above 1 needs peers",
+            ));
+        }
+
+        let replicator = Replicator::new(
+            object_store.clone(),
+            membership.clone(),
+            cluster_node.clone(),
+            cluster.replication_factor,
+        );
+        let (listener, sealed) = mpsc::unbounded_channel();
+        object_store.locked().add_listener(listener);
+        background.push(
+            replication::replicate_objects(replicator.clone(), sealed, cluster.heartbeat_interval)
+                .boxed(),
+        );
+        let uploads = Uploads::new(object_store.clone(), replicator.clone());
+        background
+            .push(chunked::expire_uploads(uploads.clone(), chunked::UPLOAD_IDLE_TIMEOUT).boxed());
+
+        Ok(CrabStore {
+            socket_path: config.socket_path.clone(),
+            socket_mode: config.socket_mode,
+            shutdown_timeout: config.shutdown_timeout,
+            spill_on_shutdown: config.spill_on_shutdown,
+            persist: config.persist,
+            tcp_address: cluster.tcp_address,
+            metrics_address: config.metrics_address.clone(),
+            context: Arc::new(StoreContext {
+                object_store,
+                pull_manager,
+                directory: embedded,
+                membership,
+                node: cluster_node,
+                replicator,
+                references,
+                uploads,
+                shutdown: watch::channel(None).0,
+                metrics: Metrics::new(),
+            }),
+            background: Mutex::new(background),
+        })
     }
 
     pub async fn start(&self) -> io::Result<()> {
         // Remove the socket if it exists
         if Path::new(&self.socket_path).exists() {
-            std::fs::remove_file(&self.socket_path)?;
+            fs::remove_file(&self.socket_path)?;
         }
         let listener = UnixListener::bind(&self.socket_path)?;
+        if let Some(mode) = self.socket_mode {
+            fs::set_permissions(&self.socket_path, fs::Permissions::from_mode(mode))?;
+        }
+        let tcp_listener = match &self.tcp_address {
+            Some(address) => {
+                let tcp_listener = TcpListener::bind(address).await?;
+                info!(
+                    "Listening for TCP clients on {}",
+                    tcp_listener.local_addr()?
+                );
+                Some(tcp_listener)
+            }
+            None => None,
+        };
+        if let Some(address) = &self.metrics_address {
+            let metrics_listener = TcpListener::bind(address).await?;
+            tokio::spawn(metrics::serve(
+                metrics_listener,
+                self.context.metrics.clone(),
+                self.context.object_store.clone(),
+            ));
+        }
+        for task in self.background.locked().drain(..) {
+            tokio::spawn(task);
+        }
+        let mut terminate = signal::unix::signal(SignalKind::terminate())?;
+        let mut clients = JoinSet::new();
+        let mut next_client_id: ClientId = 0;
 
         loop {
             tokio::select! {
                 Ok((stream, _)) = listener.accept() => {
-                    let allocator = self.allocator.clone();
-                    tokio::spawn(async move {
-                        handle_client(stream, allocator).await.expect("Error Happened during handling client");
-                    });
+                    let context = self.context.clone();
+                    let client_id = next_client_id;
+                    next_client_id += 1;
+                    clients.spawn(handle_client(stream, context, client_id));
+                }
+                Ok((stream, address)) = accept_tcp(tcp_listener.as_ref()) => {
+                    debug!("TCP client connected from {}", address);
+                    let _ = stream.set_nodelay(true);
+                    let context = self.context.clone();
+                    let client_id = next_client_id;
+                    next_client_id += 1;
+                    clients.spawn(handle_client(stream, context, client_id));
+                }
+                // Reap the tasks of clients that went away.
+                Some(result) = clients.join_next(), if !clients.is_empty() => {
+                    log_client_exit(result);
                 }
                 _ = signal::ctrl_c() => {
-                    info!("Shutting down the server");
+                    info!("Interrupted, shutting down the server");
                     break;
                 }
+                _ = terminate.recv() => {
+                    info!("Terminated, shutting down the server");
+                    break;
+                }
+            }
+        }
+
+        drop(listener);
+        drop(tcp_listener);
+        let result = self.shutdown(clients).await;
+        if let Err(e) = fs::remove_file(&self.socket_path) {
+            warn!("Could not remove {}: {}", self.socket_path.display(), e);
+        }
+        result
+    }
+
+    // Tells connected clients that the store is going away and serves them
+    // until they disconnect or the shutdown timeout passes, then spills the
+    // objects to disk if asked to, along with their index when persisting
+    // them.
+    async fn shutdown(&self, mut clients: JoinSet<()>) -> io::Result<()> {
+        self.context
+            .shutdown
+            .send_replace(Some(self.shutdown_timeout));
+        if !clients.is_empty() {
+            info!(
+                "Waiting up to {:?} for {} clients to finish",
+                self.shutdown_timeout,
+                clients.len()
+            );
+        }
+        let drained = tokio::time::timeout(self.shutdown_timeout, async {
+            while let Some(result) = clients.join_next().await {
+                log_client_exit(result);
             }
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "Closing the connections of {} clients still connected",
+                clients.len()
+            );
+            // Aborting the tasks still cleans up after their clients, before
+            // anything is spilled or persisted.
+            clients.shutdown().await;
         }
 
+        if self.spill_on_shutdown || self.persist {
+            let mut object_store = self.context.object_store.locked();
+            let (spilled, failed) = object_store.spill_all();
+            info!("Spilled {} objects to disk", spilled);
+            if self.persist {
+                let persisted = object_store.save_spill_index()?;
+                info!("Persisted {} objects", persisted);
+            }
+            if failed > 0 {
+                return Err(io::Error::other(format!(
+                    "{} objects could not be spilled",
+                    failed
+                )));
+            }
+        }
         Ok(())
     }
 }
 
-async fn handle_client(stream: UnixStream, allocator: Arc<Mutex<RamAllocator>>) -> io::Result<()> {
+// Logs a client task that panicked. Its connection is closed, and the others
+// are served on.
+fn log_client_exit(result: Result<(), JoinError>) {
+    if let Err(e) = result {
+        if e.is_panic() {
+            error!("The task serving a client panicked: {}", e);
+        }
+    }
+}
+
+// Waits forever when the store is not listening on TCP.
+async fn accept_tcp(listener: Option<&TcpListener>) -> io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn handle_client<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: S,
+    context: Arc<StoreContext>,
+    client_id: ClientId,
+) {
+    let _connected = context.metrics.client_connected();
     let mut framed = Framed::new(stream, MessageCodec {});
+    let mut connection = Connection {
+        context: context.clone(),
+        client: ClientState {
+            id: client_id,
+            ..Default::default()
+        },
+    };
+
+    let result = serve_client(&mut framed, &context, &mut connection.client).await;
+
+    drop(connection);
+    match result {
+        Ok(()) => debug!("Client {} disconnected", client_id),
+        Err(e) => warn!("Closed the connection of client {}: {}", client_id, e),
+    }
+}
+
+// Cleans up after a client when its connection is over, however that ends:
+// also when its task panics, or is aborted because the client was still
+// connected once the shutdown timeout passed.
+struct Connection {
+    context: Arc<StoreContext>,
+    client: ClientState,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let client_id = self.client.id;
+        let mut object_store = self.context.object_store.locked();
+        for object_id in self.client.unsealed.drain() {
+            debug!(
+                "Aborting unsealed object {} of disconnected client",
+                object_id
+            );
+            let _ = object_store.abort_object(&object_id);
+        }
+        for (object_id, count) in self.client.references.drain() {
+            for _ in 0..count {
+                let _ = object_store.release_object(client_id, &object_id);
+            }
+        }
+        object_store.client_disconnected(client_id);
+        drop(object_store);
+        self.context.references.client_disconnected(client_id);
+    }
+}
+
+/// What the store remembers about a connected client, so it can be cleaned up
+/// when the client goes away.
+#[derive(Default)]
+struct ClientState {
+    id: ClientId,
+    // Objects this client created but has not sealed yet.
+    unsealed: HashSet<ObjectId>,
+    // Objects this client got and has not released yet, with their counts.
+    references: HashMap<ObjectId, usize>,
+    // Objects being uploaded with PutRequest/PutChunk, also in `unsealed`.
+    uploads: HashMap<ObjectId, Upload>,
+    // How many frames from this client failed to decode.
+    malformed_frames: usize,
+    // What the objects it creates are charged to, as named on Connect.
+    namespace: String,
+}
+
+struct Upload {
+    written: u64,
+    size: u64,
+}
 
-    while let Some(request) = framed.next().await {
+async fn serve_client<S: AsyncRead + AsyncWrite + Unpin>(
+    framed: &mut Framed<S, MessageCodec>,
+    context: &StoreContext,
+    client: &mut ClientState,
+) -> io::Result<()> {
+    let object_store = &*context.object_store;
+    let pull_manager = context.pull_manager.as_ref();
+    let replicator = &*context.replicator;
+    let mut shutdown = context.shutdown.subscribe();
+    let mut notified = false;
+    loop {
+        let request = tokio::select! {
+            request = framed.next() => request,
+            deadline = async { shutdown.wait_for(Option::is_some).await.ok().and_then(|d| *d) },
+                if !notified =>
+            {
+                notified = true;
+                let deadline_ms = deadline.unwrap_or_default().as_millis() as u64;
+                let notice = messages::ShutdownNotice { deadline_ms };
+                framed.send(Messages::ShutdownNotice(notice)).await?;
+                continue;
+            }
+        };
+        let Some(request) = request else {
+            break;
+        };
         match request {
-            Ok(Messages::CreateRequest(_cr)) => {
+            Ok(Messages::CreateRequest(cr)) => {
                 debug!("Create request received.");
-                let response = Messages::CreateResponse(messages::CreateResponse {
-                    object_id: _cr.object_id,
-                    retry_with_request_id: 0,
-                    plasma_object: Some(messages::ObjectSpec {
-                        segment_index: 0,
-                        unique_fd_id: 0,
-                        header_offset: 0,
-                        data_offset: 0,
-                        data_size: 0,
-                        metadata_offset: 0,
-                        metadata_size: 0,
-                        allocated_size: 0,
-                        fallback_allocated: false,
-                        device_num: 0,
-                        is_experimental_mutable_object: false,
-                    }),
-                    error: 0,
-                    store_fd: 0,
-                    unique_fd_id: 0,
-                    mmap_size: 0,
-                    ipc_handle: Some(messages::CudaHandle {
-                        handle: vec![vec![0; 4]; 4],
-                    }),
+                let started = Instant::now();
+                let response = create(context, &cr, client).await;
+                context
+                    .metrics
+                    .observe(Operation::Create, started.elapsed());
+                framed.send(Messages::CreateResponse(response)).await?;
+            }
+            Ok(Messages::SealRequest(sr)) => {
+                debug!("Seal request received.");
+                let started = Instant::now();
+                let digest = match sr.digest.len() {
+                    0 => Ok(None),
+                    CHECKSUM_SIZE => Ok(Some(sr.digest[..].try_into().unwrap())),
+                    _ => Err(messages::Error::UnexpectedError),
+                };
+                let error = match (ObjectId::try_from_binary(&sr.object_id), digest) {
+                    (Ok(object_id), Ok(digest)) => {
+                        match object_store.locked().seal_object(&object_id, digest) {
+                            Ok(()) => {
+                                client.unsealed.remove(&object_id);
+                                messages::Error::Ok
+                            }
+                            Err(e) => e,
+                        }
+                    }
+                    (Err(_), _) => messages::Error::ObjectNonexistent,
+                    (_, Err(e)) => e,
+                };
+                context.metrics.observe(Operation::Seal, started.elapsed());
+                let response = Messages::SealResponse(messages::SealResponse {
+                    object_id: sr.object_id,
+                    error: error.into(),
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::GetRequest(gr)) => {
+                debug!("Get request received.");
+                let started = Instant::now();
+                let response = handle_get(object_store, pull_manager, gr, client).await;
+                context.metrics.observe(Operation::Get, started.elapsed());
+                framed.send(Messages::GetResponse(response)).await?;
+            }
+            Ok(Messages::ReleaseRequest(rr)) => {
+                debug!("Release request received.");
+                let error = match ObjectId::try_from_binary(&rr.object_id) {
+                    Ok(object_id) => match client.references.get_mut(&object_id) {
+                        Some(count) => {
+                            *count -= 1;
+                            if *count == 0 {
+                                client.references.remove(&object_id);
+                            }
+                            match object_store.locked().release_object(client.id, &object_id) {
+                                Ok(()) => messages::Error::Ok,
+                                Err(e) => e,
+                            }
+                        }
+                        // Releasing something this client never got.
+                        None => messages::Error::UnexpectedError,
+                    },
+                    Err(_) => messages::Error::ObjectNonexistent,
+                };
+                let response = Messages::ReleaseResponse(messages::ReleaseResponse {
+                    object_id: rr.object_id,
+                    error: error.into(),
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::PutRequest(pr)) => {
+                debug!("Put request received.");
+                let object_id = pr.object_id.clone();
+                if let Some(error) = handle_put(context, pr, client).await {
+                    let response = Messages::PutResponse(messages::PutResponse {
+                        object_id,
+                        error: error.into(),
+                    });
+                    framed.send(response).await?;
+                }
+            }
+            Ok(Messages::PutChunk(pc)) => {
+                let error = match ObjectId::try_from_binary(&pc.object_id) {
+                    Ok(object_id) => continue_upload(object_store, client, object_id, &pc.data),
+                    Err(_) => Some(messages::Error::InvalidRequest),
+                };
+                if let Some(error) = error {
+                    let response = Messages::PutResponse(messages::PutResponse {
+                        object_id: pc.object_id,
+                        error: error.into(),
+                    });
+                    framed.send(response).await?;
+                }
+            }
+            Ok(Messages::FetchRequest(fr)) => {
+                debug!("Fetch request received.");
+                let pull_manager = pull_manager.filter(|_| !fr.no_pull);
+                let references = &*context.references;
+                handle_fetch(framed, object_store, pull_manager, references, fr, client).await?;
+            }
+            Ok(Messages::WriteChunkRequest(wr)) => {
+                debug!("Write chunk request received.");
+                let response = context.uploads.write_chunk(wr, &client.namespace);
+                framed.send(Messages::WriteChunkResponse(response)).await?;
+            }
+            Ok(Messages::ReadChunkRequest(rr)) => {
+                debug!("Read chunk request received.");
+                let response = handle_read_chunk(object_store, pull_manager, rr, client).await;
+                framed.send(Messages::ReadChunkResponse(response)).await?;
+            }
+            Ok(Messages::ResizeRequest(rr)) => {
+                debug!("Resize request received.");
+                let response = handle_resize(object_store, rr, client);
+                framed.send(Messages::ResizeResponse(response)).await?;
+            }
+            Ok(Messages::ContainsRequest(cr)) => {
+                debug!("Contains request received.");
+                let has_object = ObjectId::try_from_binary(&cr.object_id)
+                    .is_ok_and(|object_id| object_store.locked().contains(&object_id));
+                let response = Messages::ContainsResponse(messages::ContainsResponse {
+                    object_id: cr.object_id,
+                    has_object,
                 });
-
                 framed.send(response).await?;
             }
-            Ok(Messages::ConnectRequest(_cr)) => {
+            Ok(Messages::ConnectRequest(cr)) => {
                 debug!("Connect request received.");
+                // Longer names could not be kept with persisted objects.
+                if cr.namespace.len() > MAX_NAME_LEN {
+                    let message_type = Messages::ConnectRequest(cr).message_type();
+                    let reason = format!("namespace is longer than {} bytes", MAX_NAME_LEN);
+                    framed.send(error_response(message_type, reason)).await?;
+                    continue;
+                }
+                client.namespace = match cr.namespace.is_empty() {
+                    true => DEFAULT_NAMESPACE.to_string(),
+                    false => cr.namespace,
+                };
                 let response = Messages::ConnectResponse(messages::ConnectResponse {
-                    memory_capacity: 200,
+                    memory_capacity: object_store.locked().memory_capacity(),
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::HeartbeatRequest(hr)) => {
+                let Some(membership) = &context.membership else {
+                    error!("Heartbeat from {} but this store has no peers", hr.address);
+                    let message_type = Messages::HeartbeatRequest(hr).message_type();
+                    let reason = "no peers configured";
+                    framed.send(error_response(message_type, reason)).await?;
+                    continue;
+                };
+                membership.record(hr);
+                let heartbeat = membership.heartbeat(object_store);
+                let response = Messages::HeartbeatResponse(messages::HeartbeatResponse {
+                    node_id: heartbeat.node_id,
+                    address: heartbeat.address,
+                    memory_capacity: heartbeat.memory_capacity,
+                    memory_used: heartbeat.memory_used,
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::PushRequest(pr)) => {
+                debug!("Push request received.");
+                let response = push::handle_push(object_store, context.node.as_deref(), pr).await;
+                framed.send(Messages::PushResponse(response)).await?;
+            }
+            Ok(Messages::PinRequest(pr)) => {
+                debug!("Pin request received.");
+                let error = pin_object(object_store, &pr.object_id, true);
+                let response = Messages::PinResponse(messages::PinResponse {
+                    object_id: pr.object_id,
+                    error: error.into(),
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::UnpinRequest(ur)) => {
+                debug!("Unpin request received.");
+                let error = pin_object(object_store, &ur.object_id, false);
+                let response = Messages::UnpinResponse(messages::UnpinResponse {
+                    object_id: ur.object_id,
+                    error: error.into(),
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::AddReferenceRequest(ar)) => {
+                debug!("Add reference request received.");
+                let error = match ObjectId::try_from_binary(&ar.object_id) {
+                    Ok(object_id) => match context.references.add(client.id, &object_id) {
+                        Ok(reported) => {
+                            if let Some(reported) = reported {
+                                let _ = reported.await;
+                            }
+                            messages::Error::Ok
+                        }
+                        Err(e) => e,
+                    },
+                    Err(_) => messages::Error::ObjectNonexistent,
+                };
+                let response = Messages::AddReferenceResponse(messages::AddReferenceResponse {
+                    object_id: ar.object_id,
+                    error: error.into(),
+                });
+                framed.send(response).await?;
+            }
+            Ok(Messages::RemoveReferenceRequest(rr)) => {
+                debug!("Remove reference request received.");
+                let error = match ObjectId::try_from_binary(&rr.object_id) {
+                    Ok(object_id) => match context.references.remove(client.id, &object_id) {
+                        Ok(()) => messages::Error::Ok,
+                        Err(e) => e,
+                    },
+                    Err(_) => messages::Error::ObjectNonexistent,
+                };
+                let response =
+                    Messages::RemoveReferenceResponse(messages::RemoveReferenceResponse {
+                        object_id: rr.object_id,
+                        error: error.into(),
+                    });
+                framed.send(response).await?;
+            }
+            Ok(Messages::ReferenceUpdateRequest(ur)) => {
+                debug!("Reference update from {} received.", ur.node);
+                let error = match ObjectId::try_from_binary(&ur.object_id) {
+                    Ok(object_id) => match context.references.update(ur.node, &object_id, ur.count)
+                    {
+                        Ok(()) => messages::Error::Ok,
+                        Err(e) => e,
+                    },
+                    Err(_) => messages::Error::ObjectNonexistent,
+                };
+                let response =
+                    Messages::ReferenceUpdateResponse(messages::ReferenceUpdateResponse {
+                        object_id: ur.object_id,
+                        error: error.into(),
+                    });
+                framed.send(response).await?;
+            }
+            Ok(Messages::DeleteRequest(dr)) => {
+                debug!("Delete request received.");
+                let started = Instant::now();
+                let error = match ObjectId::try_from_binary(&dr.object_id) {
+                    Ok(object_id) => match object_store.locked().delete_object(&object_id) {
+                        Ok(()) => messages::Error::Ok,
+                        Err(e) => e,
+                    },
+                    Err(_) => messages::Error::ObjectNonexistent,
+                };
+                context
+                    .metrics
+                    .observe(Operation::Delete, started.elapsed());
+                let response = Messages::DeleteResponse(messages::DeleteResponse {
+                    object_id: dr.object_id,
+                    error: error.into(),
                 });
                 framed.send(response).await?;
             }
-            Ok(invalid_request) => {
-                error!("Invalid Request = {:?}", invalid_request);
+            Ok(Messages::StatsRequest(_)) => {
+                debug!("Stats request received.");
+                let mut response = {
+                    let object_store = object_store.locked();
+                    messages::StatsResponse {
+                        memory_capacity: object_store.memory_capacity(),
+                        memory_used: object_store.memory_used(),
+                        num_objects: object_store.num_objects() as u64,
+                        num_spilled_objects: object_store.num_spilled() as u64,
+                        namespaces: object_store.quotas().stats(),
+                        ..Default::default()
+                    }
+                };
+                response.under_replicated = replicator.under_replicated();
+                framed.send(Messages::StatsResponse(response)).await?;
+            }
+            Ok(Messages::ListRequest(lr)) => {
+                debug!("List request received.");
+                let response = handle_list(context, &lr);
+                framed.send(Messages::ListResponse(response)).await?;
+            }
+            Ok(Messages::InspectRequest(ir)) => {
+                debug!("Inspect request received.");
+                let response = handle_inspect(context, ir);
+                framed.send(Messages::InspectResponse(response)).await?;
+            }
+            Ok(Messages::EvictRequest(er)) => {
+                debug!("Evict request received.");
+                let (freed, evicted, spilled) = object_store.locked().evict(er.bytes);
+                let mut response = messages::EvictResponse {
+                    freed,
+                    evicted,
+                    spilled,
+                    ..Default::default()
+                };
+                if freed < er.bytes {
+                    response.set_error(messages::Error::OutOfMemory);
+                }
+                framed.send(Messages::EvictResponse(response)).await?;
+            }
+            Ok(Messages::MembersRequest(_)) => {
+                debug!("Members request received.");
+                let members = match &context.membership {
+                    Some(membership) => membership.members(object_store),
+                    None => Vec::new(),
+                };
+                let response = Messages::MembersResponse(messages::MembersResponse { members });
+                framed.send(response).await?;
+            }
+            Ok(Messages::Malformed(frame)) => {
+                warn!("Client {} sent a {}", client.id, frame);
+                context.metrics.decode_error(frame.message_type);
+                client.malformed_frames += 1;
+                let response = error_response(frame.message_type, frame.reason);
+                framed.send(response).await?;
+                if client.malformed_frames >= MAX_MALFORMED_FRAMES {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} malformed frames", client.malformed_frames),
+                    ));
+                }
+            }
+            Ok(request) => {
+                let response = context
+                    .directory
+                    .as_ref()
+                    .and_then(|directory| directory_server::handle_request(directory, &request));
+                match response {
+                    Some(response) => framed.send(response).await?,
+                    None => {
+                        let message_type = request.message_type();
+                        error!("Invalid request of type {}", message_type);
+                        let reason = "the store does not serve this request";
+                        framed.send(error_response(message_type, reason)).await?;
+                    }
+                }
+            }
+            // The stream cannot be framed any more, or reading it failed.
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn error_response(message_type: u16, reason: impl ToString) -> Messages {
+    let mut response = messages::ErrorResponse {
+        message_type: message_type.into(),
+        reason: reason.to_string(),
+        ..Default::default()
+    };
+    response.set_error(messages::Error::InvalidRequest);
+    Messages::ErrorResponse(response)
+}
+
+// Creates the object for `cr`, waiting up to CREATE_WAIT_TIMEOUT for a
+// concurrent create of the same object to finish if the request asks for it.
+async fn create(
+    context: &StoreContext,
+    cr: &messages::CreateRequest,
+    client: &mut ClientState,
+) -> messages::CreateResponse {
+    let deadline = Instant::now() + CREATE_WAIT_TIMEOUT;
+    let response = loop {
+        match handle_create(&context.object_store, cr, client) {
+            CreateOutcome::Done(response) => break response,
+            CreateOutcome::WaitForSeal(sealed) => {
+                debug!("Waiting for the concurrent create of the object to finish");
+                let _queued = context.metrics.create_queued();
+                if tokio::time::timeout_at(deadline, sealed).await.is_err() {
+                    debug!("Gave up waiting for the concurrent create of the object");
+                    let mut response = messages::CreateResponse {
+                        object_id: cr.object_id.clone(),
+                        ..Default::default()
+                    };
+                    response.set_error(messages::Error::ObjectInUse);
+                    break response;
+                }
+            }
+        }
+    };
+    if let (messages::Error::Ok, Ok(object_id)) = (
+        response.error(),
+        ObjectId::try_from_binary(&response.object_id),
+    ) {
+        context
+            .replicator
+            .track(object_id, cr.replication_factor, cr.is_mutable);
+        if cr.ref_counted {
+            context.references.created(client.id, object_id);
+        }
+        client.unsealed.insert(object_id);
+    }
+    response
+}
+
+enum CreateOutcome {
+    Done(messages::CreateResponse),
+    // Another client is creating the object; retry once this fires.
+    WaitForSeal(oneshot::Receiver<()>),
+}
+
+fn handle_create(
+    object_store: &Mutex<ObjectStore>,
+    cr: &messages::CreateRequest,
+    client: &ClientState,
+) -> CreateOutcome {
+    let mut response = messages::CreateResponse {
+        object_id: cr.object_id.clone(),
+        ..Default::default()
+    };
+    let object_id = match ObjectId::try_from_binary(&cr.object_id) {
+        Ok(object_id) => object_id,
+        Err(e) => {
+            error!("Create request with invalid object id: {}", e);
+            response.set_error(messages::Error::UnexpectedError);
+            return CreateOutcome::Done(response);
+        }
+    };
+
+    let mut object_store = object_store.locked();
+    let store_path = object_store.store_path().to_string_lossy().into_owned();
+    let mmap_size = object_store.memory_capacity();
+    match object_store.create_object(
+        object_id,
+        cr.data_size,
+        cr.metadata_size,
+        cr.device_num as i32,
+        cr.is_mutable,
+        client.id,
+        Some(&client.namespace),
+    ) {
+        Ok(object) => {
+            response.plasma_object = Some(object.spec());
+            response.store_path = store_path;
+            response.mmap_size = mmap_size;
+        }
+        Err(messages::Error::ObjectExists)
+            if cr.wait_for_seal && !client.unsealed.contains(&object_id) =>
+        {
+            if let Some(sealed) = object_store.wait_for_seal(&object_id) {
+                return CreateOutcome::WaitForSeal(sealed);
+            }
+            response.set_error(messages::Error::ObjectExists);
+        }
+        Err(e) => {
+            debug!("Create of {} failed: {:?}", object_id, e);
+            response.set_error(e);
+        }
+    }
+    CreateOutcome::Done(response)
+}
+
+async fn handle_get(
+    object_store: &Mutex<ObjectStore>,
+    pull_manager: Option<&Arc<PullManager>>,
+    gr: messages::GetRequest,
+    client: &mut ClientState,
+) -> messages::GetResponse {
+    let deadline = Instant::now() + Duration::from_millis(gr.timeout_ms.max(0) as u64);
+    let mut response = messages::GetResponse::default();
+
+    for raw_id in gr.object_ids {
+        let result = match ObjectId::try_from_binary(&raw_id) {
+            Ok(object_id) => {
+                get_object(object_store, pull_manager, client, &object_id, deadline).await
+            }
+            Err(_) => Err(messages::Error::ObjectNonexistent),
+        };
+
+        response.object_ids.push(raw_id);
+        match result {
+            Ok(spec) => {
+                response.plasma_objects.push(spec);
+                response.errors.push(messages::Error::Ok.into());
+            }
+            Err(e) => {
+                response
+                    .plasma_objects
+                    .push(messages::ObjectSpec::default());
+                response.errors.push(e.into());
+            }
+        }
+    }
+
+    let object_store = object_store.locked();
+    response.store_path = object_store.store_path().to_string_lossy().into_owned();
+    response.mmap_size = object_store.memory_capacity();
+    response
+}
+
+fn handle_resize(
+    object_store: &Mutex<ObjectStore>,
+    rr: messages::ResizeRequest,
+    client: &ClientState,
+) -> messages::ResizeResponse {
+    let mut response = messages::ResizeResponse {
+        object_id: rr.object_id.clone(),
+        ..Default::default()
+    };
+    let Ok(object_id) = ObjectId::try_from_binary(&rr.object_id) else {
+        response.set_error(messages::Error::ObjectNonexistent);
+        return response;
+    };
+
+    let mut object_store = object_store.locked();
+    match object_store.resize_object(
+        client.id,
+        &object_id,
+        rr.data_size,
+        rr.metadata_size,
+        rr.allow_reallocate,
+    ) {
+        Ok(spec) => {
+            response.plasma_object = Some(spec);
+            response.store_path = object_store.store_path().to_string_lossy().into_owned();
+            response.mmap_size = object_store.memory_capacity();
+        }
+        Err(e) => {
+            debug!("Resize of {} failed: {:?}", object_id, e);
+            response.set_error(e);
+        }
+    }
+    response
+}
+
+// What a Get is waiting for.
+enum Pending {
+    Seal(oneshot::Receiver<()>),
+    Pull,
+}
+
+// Gets a reference to `object_id` for `client`, waiting for it to be sealed
+// (or pulled from a peer) until `deadline`. A pull that does not finish in
+// time keeps going, so a later Get may find the object.
+async fn get_object(
+    object_store: &Mutex<ObjectStore>,
+    pull_manager: Option<&Arc<PullManager>>,
+    client: &mut ClientState,
+    object_id: &ObjectId,
+    deadline: Instant,
+) -> Result<messages::ObjectSpec, messages::Error> {
+    let mut pulled = false;
+    loop {
+        let pending = {
+            let mut object_store = object_store.locked();
+            match object_store.get_object(client.id, object_id) {
+                Err(messages::Error::ObjectNotSealed) => {
+                    object_store.wait_for_seal(object_id).map(Pending::Seal)
+                }
+                Err(messages::Error::ObjectNonexistent) if pull_manager.is_some() && !pulled => {
+                    Some(Pending::Pull)
+                }
+                result => {
+                    if result.is_ok() {
+                        *client.references.entry(*object_id).or_default() += 1;
+                    }
+                    return result;
+                }
+            }
+        };
+        match pending {
+            Some(Pending::Seal(sealed)) if Instant::now() < deadline => {
+                let _ = tokio::time::timeout_at(deadline, sealed).await;
+            }
+            Some(Pending::Pull) => {
+                pulled = true;
+                let done = pull_manager.unwrap().start_pull(*object_id);
+                debug!("Pulling {} from a peer", object_id);
+                match tokio::time::timeout_at(deadline, done).await {
+                    Ok(Ok(true)) => {}
+                    _ => return Err(messages::Error::ObjectNonexistent),
+                }
+            }
+            _ => return Err(messages::Error::ObjectNotSealed),
+        }
+    }
+}
+
+fn pin_object(object_store: &Mutex<ObjectStore>, raw_id: &[u8], pinned: bool) -> messages::Error {
+    let Ok(object_id) = ObjectId::try_from_binary(raw_id) else {
+        return messages::Error::ObjectNonexistent;
+    };
+    match object_store.locked().pin_object(&object_id, pinned) {
+        Ok(()) => messages::Error::Ok,
+        Err(e) => e,
+    }
+}
+
+fn release_object(
+    object_store: &Mutex<ObjectStore>,
+    client: &mut ClientState,
+    object_id: &ObjectId,
+) {
+    if let Some(count) = client.references.get_mut(object_id) {
+        *count -= 1;
+        if *count == 0 {
+            client.references.remove(object_id);
+        }
+        let _ = object_store.locked().release_object(client.id, object_id);
+    }
+}
+
+// Describes a page of the objects that pass the filters of `lr`, in the order
+// of their IDs.
+fn handle_list(context: &StoreContext, lr: &messages::ListRequest) -> messages::ListResponse {
+    let now_us = object_store::now_us();
+    let limit = match lr.limit {
+        0 => DEFAULT_LIST_LIMIT,
+        limit => (limit as usize).min(MAX_LIST_LIMIT),
+    };
+    let filter = |info: &messages::ObjectInfo| {
+        let size = info.data_size + info.metadata_size;
+        let age_ms = now_us.saturating_sub(info.create_time_us) / 1000;
+        info.object_id.starts_with(&lr.prefix)
+            && size >= lr.min_size
+            && (lr.max_size == 0 || size <= lr.max_size)
+            && age_ms >= lr.min_age_ms
+            && (lr.max_age_ms == 0 || age_ms <= lr.max_age_ms)
+            && (lr.states.is_empty() || lr.states.contains(&info.state))
+            && (lr.owners.is_empty() || lr.owners.contains(&info.owner))
+            && (info.pinned || !lr.pinned_only)
+    };
+    // One more than a page, to tell whether there is another.
+    let mut objects =
+        context
+            .object_store
+            .locked()
+            .object_infos(list_start(&lr.cursor), limit + 1, filter);
+
+    let mut next_cursor = Vec::new();
+    if objects.len() > limit {
+        objects.truncate(limit);
+        next_cursor = objects[limit - 1].object_id.clone();
+    }
+    for info in &mut objects {
+        if let Ok(object_id) = ObjectId::try_from_binary(&info.object_id) {
+            info.references = context.references.count(&object_id).unwrap_or_default();
+        }
+    }
+    messages::ListResponse {
+        objects,
+        next_cursor,
+    }
+}
+
+// Where a listing that last returned `cursor` continues: with the first ID
+// that sorts after it as bytes.
+fn list_start(cursor: &[u8]) -> Bound<ObjectId> {
+    match cursor.len() {
+        0 => Bound::Unbounded,
+        len if len < UNIQUE_ID_SIZE => {
+            // IDs starting with a shorter cursor sort after it.
+            let mut id = [0u8; UNIQUE_ID_SIZE];
+            id[..len].copy_from_slice(cursor);
+            Bound::Included(ObjectId::try_from_binary(&id).unwrap())
+        }
+        // An ID equal to the start of a longer cursor sorts before it.
+        _ => Bound::Excluded(ObjectId::try_from_binary(&cursor[..UNIQUE_ID_SIZE]).unwrap()),
+    }
+}
+
+// Describes one object, in memory or spilled, with what is known of the
+// clients and stores using it.
+fn handle_inspect(
+    context: &StoreContext,
+    ir: messages::InspectRequest,
+) -> messages::InspectResponse {
+    let mut response = messages::InspectResponse {
+        object_id: ir.object_id,
+        ..Default::default()
+    };
+    let Ok(object_id) = ObjectId::try_from_binary(&response.object_id) else {
+        response.set_error(messages::Error::ObjectNonexistent);
+        return response;
+    };
+    {
+        let object_store = context.object_store.locked();
+        if let Some(object) = object_store.object(&object_id) {
+            response.spec = Some(object.spec());
+            response.info = Some(object.info(&object_id));
+            response.holders = object
+                .holders
+                .iter()
+                .map(|(client_id, count)| messages::Holder {
+                    client_id: *client_id,
+                    count: *count as u64,
+                })
+                .collect();
+        } else if let Some(spilled) = object_store.spilled_object(&object_id) {
+            response.info = Some(spilled.info(&object_id));
+            response.spilled = true;
+        } else {
+            response.set_error(messages::Error::ObjectNonexistent);
+            return response;
+        }
+    }
+    context.references.describe(&object_id, &mut response);
+    response
+}
+
+// Creates the object of a PutRequest and writes the bytes that came with it.
+// Returns the result to send back, if the upload is over.
+async fn handle_put(
+    context: &StoreContext,
+    pr: messages::PutRequest,
+    client: &mut ClientState,
+) -> Option<messages::Error> {
+    let cr = messages::CreateRequest {
+        object_id: pr.object_id,
+        data_size: pr.data_size,
+        metadata_size: pr.metadata_size,
+        wait_for_seal: pr.wait_for_seal,
+        replication_factor: pr.replication_factor,
+        ref_counted: pr.ref_counted,
+        ..Default::default()
+    };
+    let response = create(context, &cr, client).await;
+    let object_id = match (
+        response.error(),
+        ObjectId::try_from_binary(&response.object_id),
+    ) {
+        (messages::Error::Ok, Ok(object_id)) => object_id,
+        (error, _) => return Some(error),
+    };
+    client.uploads.insert(
+        object_id,
+        Upload {
+            written: 0,
+            size: pr.data_size + pr.metadata_size,
+        },
+    );
+    continue_upload(&context.object_store, client, object_id, &pr.data)
+}
+
+// Appends `bytes` to an upload, sealing the object once it is complete.
+// Returns the result to send back, if the upload is over.
+fn continue_upload(
+    object_store: &Mutex<ObjectStore>,
+    client: &mut ClientState,
+    object_id: ObjectId,
+    bytes: &[u8],
+) -> Option<messages::Error> {
+    let Some(upload) = client.uploads.get_mut(&object_id) else {
+        // The upload already failed and its error was sent.
+        debug!(
+            "Dropping chunk for {}, which is not being uploaded",
+            object_id
+        );
+        return None;
+    };
+    let mut object_store = object_store.locked();
+    let result = object_store
+        .write_object(&object_id, upload.written, bytes)
+        .and_then(|()| {
+            upload.written += bytes.len() as u64;
+            if upload.written < upload.size {
+                return Ok(false);
+            }
+            object_store.seal_object(&object_id, None).map(|()| true)
+        });
+    match result {
+        Ok(false) => return None,
+        Ok(true) => {}
+        Err(e) => {
+            debug!("Upload of {} failed: {:?}", object_id, e);
+            let _ = object_store.abort_object(&object_id);
+        }
+    }
+    client.uploads.remove(&object_id);
+    client.unsealed.remove(&object_id);
+    Some(result.err().unwrap_or(messages::Error::Ok))
+}
+
+// Reads one chunk of a sealed object. Mutable objects can change between two
+// chunks, so they are only copied whole with a FetchRequest.
+async fn handle_read_chunk(
+    object_store: &Mutex<ObjectStore>,
+    pull_manager: Option<&Arc<PullManager>>,
+    rr: messages::ReadChunkRequest,
+    client: &mut ClientState,
+) -> messages::ReadChunkResponse {
+    let deadline = Instant::now() + Duration::from_millis(rr.timeout_ms.max(0) as u64);
+    let length = match rr.length {
+        0 => DEFAULT_CHUNK_SIZE,
+        length => length.min(MAX_CHUNK_SIZE),
+    };
+    let mut response = messages::ReadChunkResponse {
+        object_id: rr.object_id.clone(),
+        ..Default::default()
+    };
+    let Ok(object_id) = ObjectId::try_from_binary(&rr.object_id) else {
+        response.set_error(messages::Error::ObjectNonexistent);
+        return response;
+    };
+    let spec = match get_object(object_store, pull_manager, client, &object_id, deadline).await {
+        Ok(spec) => spec,
+        Err(e) => {
+            response.set_error(e);
+            return response;
+        }
+    };
+    let result = match spec.is_experimental_mutable_object {
+        true => Err(messages::Error::InvalidRequest),
+        false => object_store
+            .locked()
+            .read_object(&object_id, rr.offset, length),
+    };
+    release_object(object_store, client, &object_id);
+    match result {
+        Ok(data) => {
+            response.data_size = spec.data_size;
+            response.metadata_size = spec.metadata_size;
+            response.data = data;
+        }
+        Err(e) => response.set_error(e),
+    }
+    response
+}
+
+// Streams a copy of the requested object back in one or more FetchResponses.
+async fn handle_fetch<S: AsyncRead + AsyncWrite + Unpin>(
+    framed: &mut Framed<S, MessageCodec>,
+    object_store: &Mutex<ObjectStore>,
+    pull_manager: Option<&Arc<PullManager>>,
+    references: &References,
+    fr: messages::FetchRequest,
+    client: &mut ClientState,
+) -> io::Result<()> {
+    let deadline = Instant::now() + Duration::from_millis(fr.timeout_ms.max(0) as u64);
+    let chunk_size = match fr.max_chunk_size {
+        0 => DEFAULT_CHUNK_SIZE,
+        size => size.min(MAX_CHUNK_SIZE),
+    };
+    let error_response = |error: messages::Error| {
+        let mut response = messages::FetchResponse {
+            object_id: fr.object_id.clone(),
+            last: true,
+            ..Default::default()
+        };
+        response.set_error(error);
+        Messages::FetchResponse(response)
+    };
+
+    let Ok(object_id) = ObjectId::try_from_binary(&fr.object_id) else {
+        return framed
+            .send(error_response(messages::Error::ObjectNonexistent))
+            .await;
+    };
+    let mut spec = match get_object(object_store, pull_manager, client, &object_id, deadline).await
+    {
+        Ok(spec) => spec,
+        Err(e) => return framed.send(error_response(e)).await,
+    };
+    let owner = references.owner(&object_id).unwrap_or_default();
+
+    // Mutable objects can change under us, so they are copied in one go
+    // between two updates. Sealed ones are copied chunk by chunk.
+    let mut snapshot = None;
+    let mut delay = SNAPSHOT_RETRY_DELAY;
+    while spec.is_experimental_mutable_object && snapshot.is_none() {
+        let result = object_store.locked().try_snapshot_object(&object_id);
+        match result {
+            Ok(Some((current, bytes))) => {
+                spec = current;
+                snapshot = Some(bytes);
+            }
+            Ok(None) if Instant::now() < deadline => {
+                tokio::time::sleep_until(deadline.min(Instant::now() + delay)).await;
+                delay = (delay * 2).min(MAX_SNAPSHOT_RETRY_DELAY);
             }
+            Ok(None) | Err(_) => {
+                release_object(object_store, client, &object_id);
+                let error = result.err().unwrap_or(messages::Error::ObjectInUse);
+                return framed.send(error_response(error)).await;
+            }
+        }
+    }
+
+    let size = spec.data_size + spec.metadata_size;
+    let mut offset = 0;
+    loop {
+        let chunk = match &snapshot {
+            Some(bytes) => {
+                let end = (offset + chunk_size).min(size);
+                Ok(bytes[offset as usize..end as usize].to_vec())
+            }
+            None => object_store
+                .locked()
+                .read_object(&object_id, offset, chunk_size),
+        };
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
             Err(e) => {
-                error!("error on decoding from socket; error = {:?}", e);
+                release_object(object_store, client, &object_id);
+                return framed.send(error_response(e)).await;
             }
+        };
+        offset += chunk.len() as u64;
+        let last = offset >= size;
+        let response = Messages::FetchResponse(messages::FetchResponse {
+            object_id: fr.object_id.clone(),
+            error: messages::Error::Ok.into(),
+            data_size: spec.data_size,
+            metadata_size: spec.metadata_size,
+            data: chunk,
+            last,
+            owner: owner.clone(),
+        });
+        framed.send(response).await?;
+        if last {
+            break;
         }
     }
+    release_object(object_store, client, &object_id);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quota::Quota;
+    use crate::refcount::LostObjectPolicy;
+    use crate::test_util::TempDir;
+    use tokio::io::DuplexStream;
+
+    fn test_store(dir: &TempDir) -> CrabStore {
+        test_store_with(dir, |_, _| {})
+    }
+
+    fn test_store_with(
+        dir: &TempDir,
+        configure: impl FnOnce(&mut StoreConfig, &mut ClusterConfig),
+    ) -> CrabStore {
+        let mut config = StoreConfig {
+            socket_path: dir.path().join("sock"),
+            socket_mode: None,
+            sys_memory: 1 << 20,
+            mem_mapped_dir: dir.path().to_path_buf(),
+            prefault: false,
+            spill_dir: None,
+            spill_limit: None,
+            eviction_policy: Default::default(),
+            shutdown_timeout: Duration::from_secs(1),
+            spill_on_shutdown: false,
+            metrics_address: None,
+            recoverable: false,
+            persist: false,
+            quotas: HashMap::new(),
+            client_quota: Quota::default(),
+        };
+        let mut cluster = ClusterConfig {
+            tcp_address: None,
+            advertise_address: None,
+            pull_from: Vec::new(),
+            max_concurrent_pulls: 1,
+            embed_directory: false,
+            directory_address: None,
+            node_id: None,
+            peers: Vec::new(),
+            heartbeat_interval: Duration::from_secs(1),
+            dead_node_timeout: Duration::from_secs(1),
+            replication_factor: 1,
+            lost_object_policy: LostObjectPolicy::Keep,
+        };
+        configure(&mut config, &mut cluster);
+        let allocator = RamAllocator::new(dir.path(), config.sys_memory, false).unwrap();
+        let spill = config.spill_dir.clone().map(|spill_dir| {
+            let mut spill = SpillDir::new(spill_dir, config.spill_limit).unwrap();
+            if config.persist {
+                spill.load_index().unwrap();
+            }
+            spill
+        });
+        CrabStore::new(&config, cluster, allocator, spill, None).unwrap()
+    }
+
+    // A port for a store to serve peers on, bound before the store is made
+    // so that it can be told its address.
+    async fn tcp_listener() -> (TcpListener, String) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        (listener, address)
+    }
+
+    // Serves clients of `store` over TCP and runs its background tasks, as
+    // `start` does, until the returned tasks are dropped. Peers then find
+    // the store gone.
+    #[must_use]
+    fn serve(store: &CrabStore, listener: TcpListener) -> JoinSet<()> {
+        let mut tasks = JoinSet::new();
+        for task in store.background.locked().drain(..) {
+            tasks.spawn(task);
+        }
+        let context = store.context.clone();
+        tasks.spawn(async move {
+            let mut clients = JoinSet::new();
+            // Clear of the IDs `connect` is given.
+            let mut next_client_id: ClientId = 1000;
+            while let Ok((stream, _)) = listener.accept().await {
+                clients.spawn(handle_client(stream, context.clone(), next_client_id));
+                next_client_id += 1;
+            }
+        });
+        tasks
+    }
+
+    // Serves a client of `store` over an in-memory pipe.
+    fn connect(store: &CrabStore, client_id: ClientId) -> Framed<DuplexStream, MessageCodec> {
+        let (client, server) = tokio::io::duplex(1 << 16);
+        tokio::spawn(handle_client(server, store.context.clone(), client_id));
+        Framed::new(client, MessageCodec {})
+    }
+
+    async fn call(framed: &mut Framed<DuplexStream, MessageCodec>, request: Messages) -> Messages {
+        framed.send(request).await.unwrap();
+        framed.next().await.unwrap().unwrap()
+    }
+
+    async fn put(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        data: &[u8],
+    ) {
+        let request = messages::PutRequest {
+            object_id: object_id.as_ref().to_vec(),
+            data_size: data.len() as u64,
+            data: data.to_vec(),
+            ..Default::default()
+        };
+        match call(framed, Messages::PutRequest(request)).await {
+            Messages::PutResponse(response) => assert_eq!(response.error(), messages::Error::Ok),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn read_chunk(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        offset: u64,
+        length: u64,
+    ) -> messages::ReadChunkResponse {
+        let request = messages::ReadChunkRequest {
+            object_id: object_id.as_ref().to_vec(),
+            offset,
+            length,
+            timeout_ms: 0,
+        };
+        match call(framed, Messages::ReadChunkRequest(request)).await {
+            Messages::ReadChunkResponse(response) => response,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn create(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        data_size: u64,
+        metadata_size: u64,
+    ) -> messages::CreateResponse {
+        let request = messages::CreateRequest {
+            object_id: object_id.as_ref().to_vec(),
+            data_size,
+            metadata_size,
+            ..Default::default()
+        };
+        match call(framed, Messages::CreateRequest(request)).await {
+            Messages::CreateResponse(response) => response,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    // A store serving peers over TCP, and its address.
+    async fn tcp_store_with(
+        dir: &TempDir,
+        configure: impl FnOnce(&mut ClusterConfig),
+    ) -> (CrabStore, String, JoinSet<()>) {
+        let (listener, address) = tcp_listener().await;
+        let store = test_store_with(dir, |_, cluster| {
+            cluster.tcp_address = Some(address.clone());
+            configure(cluster);
+        });
+        let tasks = serve(&store, listener);
+        (store, address, tasks)
+    }
+
+    async fn contains(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+    ) -> bool {
+        let request = messages::ContainsRequest {
+            object_id: object_id.binary(),
+        };
+        match call(framed, Messages::ContainsRequest(request)).await {
+            Messages::ContainsResponse(response) => response.has_object,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn push_to_every_node() {
+        let dirs: Vec<_> = (0..4).map(|_| TempDir::new()).collect();
+        let mut stores = Vec::new();
+        for dir in &dirs {
+            stores.push(tcp_store_with(dir, |_| {}).await);
+        }
+        let object_id = ObjectId::random();
+        let mut source = connect(&stores[0].0, 0);
+        put(&mut source, &object_id, b"pushed").await;
+
+        // Nothing listens there, so its share is pushed by its parent.
+        let unreachable = "127.0.0.1:1".to_string();
+        let nodes = vec![
+            stores[0].1.clone(),
+            stores[1].1.clone(),
+            unreachable.clone(),
+            stores[2].1.clone(),
+            stores[3].1.clone(),
+            stores[3].1.clone(),
+        ];
+        for fanout in [1, 2] {
+            let request = messages::PushRequest {
+                object_id: object_id.binary(),
+                nodes: nodes.clone(),
+                fanout,
+                source: String::new(),
+            };
+            let response = match call(&mut source, Messages::PushRequest(request)).await {
+                Messages::PushResponse(response) => response,
+                other => panic!("unexpected response {:?}", other),
+            };
+            let mut results: Vec<_> = response
+                .results
+                .iter()
+                .map(|result| (result.node.clone(), result.error()))
+                .collect();
+            results.sort();
+            let mut expected = vec![
+                (stores[1].1.clone(), messages::Error::Ok),
+                (stores[2].1.clone(), messages::Error::Ok),
+                (stores[3].1.clone(), messages::Error::Ok),
+                (unreachable.clone(), messages::Error::NodeUnreachable),
+            ];
+            expected.sort();
+            assert_eq!(results, expected, "fanout {}", fanout);
+        }
+        for (store, _, _) in &stores[1..] {
+            let mut framed = connect(store, 0);
+            assert!(contains(&mut framed, &object_id).await);
+            assert_eq!(
+                read_chunk(&mut framed, &object_id, 0, 6).await.data,
+                b"pushed"
+            );
+        }
+
+        let request = messages::PushRequest {
+            object_id: ObjectId::random().binary(),
+            nodes: vec![stores[1].1.clone()],
+            ..Default::default()
+        };
+        match call(&mut source, Messages::PushRequest(request)).await {
+            Messages::PushResponse(response) => {
+                assert_eq!(
+                    response.results[0].error(),
+                    messages::Error::ObjectNonexistent
+                )
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn under_replicated(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+    ) -> Vec<messages::UnderReplicated> {
+        match call(framed, Messages::StatsRequest(messages::StatsRequest {})).await {
+            Messages::StatsResponse(response) => response.under_replicated,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn replicas_on_dead_peers_are_replaced() {
+        let dirs: Vec<_> = (0..3).map(|_| TempDir::new()).collect();
+        let mut listeners = Vec::new();
+        for _ in &dirs {
+            listeners.push(tcp_listener().await);
+        }
+        let addresses: Vec<_> = listeners
+            .iter()
+            .map(|(_, address)| address.clone())
+            .collect();
+        let mut stores = Vec::new();
+        for (i, (dir, (listener, address))) in dirs.iter().zip(listeners).enumerate() {
+            let store = test_store_with(dir, |_, cluster| {
+                cluster.tcp_address = Some(address);
+                // The first store replicates to the others, which only
+                // know of it.
+                cluster.peers = match i {
+                    0 => addresses[1..].to_vec(),
+                    _ => vec![addresses[0].clone()],
+                };
+                cluster.heartbeat_interval = Duration::from_millis(20);
+                cluster.dead_node_timeout = Duration::from_millis(300);
+            });
+            let tasks = serve(&store, listener);
+            stores.push((store, Some(tasks)));
+        }
+        let mut source = connect(&stores[0].0, 0);
+        let mut peers = [connect(&stores[1].0, 0), connect(&stores[2].0, 0)];
+        // Wait for the peers to be alive, so the copy is pushed on seal.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !members(&mut source).await.iter().all(|member| member.alive) {
+            assert!(Instant::now() < deadline, "peers are not alive");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let object_id = ObjectId::random();
+        let request = messages::WriteChunkRequest {
+            object_id: object_id.binary(),
+            data_size: 10,
+            data: b"replicated".to_vec(),
+            replication_factor: 2,
+            ..Default::default()
+        };
+        call(&mut source, Messages::WriteChunkRequest(request)).await;
+
+        // Held by the source and one of the peers.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let holder = loop {
+            let mut holders = Vec::new();
+            for (i, peer) in peers.iter_mut().enumerate() {
+                if contains(peer, &object_id).await {
+                    holders.push(i);
+                }
+            }
+            if !holders.is_empty() && under_replicated(&mut source).await.is_empty() {
+                assert_eq!(holders.len(), 1);
+                break holders[0];
+            }
+            assert!(
+                Instant::now() < deadline,
+                "{} was not replicated",
+                object_id
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+
+        // The copy is made again on the other peer once the holder dies.
+        stores[holder + 1].1 = None;
+        let other = &mut peers[1 - holder];
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !contains(other, &object_id).await {
+            assert!(
+                Instant::now() < deadline,
+                "{} was not copied again",
+                object_id
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            read_chunk(other, &object_id, 0, 10).await.data,
+            b"replicated"
+        );
+
+        // Without peers left, the object is short of a copy.
+        stores[2 - holder].1 = None;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let under_replicated = under_replicated(&mut source).await;
+            if let [object] = &under_replicated[..] {
+                assert_eq!(object.object_id, object_id.binary());
+                assert_eq!((object.replicas, object.replication_factor), (1, 2));
+                break;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "{} is not under-replicated",
+                object_id
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn evict(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        bytes: u64,
+    ) -> messages::EvictResponse {
+        let request = messages::EvictRequest { bytes };
+        match call(framed, Messages::EvictRequest(request)).await {
+            Messages::EvictResponse(response) => response,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn unpinned_objects_are_dropped_and_pinned_ones_spilled() {
+        let dir = TempDir::new();
+        let store = test_store_with(&dir, |config, _| {
+            config.spill_dir = Some(dir.path().join("spill"));
+        });
+        let mut framed = connect(&store, 0);
+        let pinned = ObjectId::random();
+        let unpinned = ObjectId::random();
+        put(&mut framed, &pinned, b"pinned").await;
+        put(&mut framed, &unpinned, b"unpinned").await;
+        let request = messages::UnpinRequest {
+            object_id: unpinned.binary(),
+        };
+        match call(&mut framed, Messages::UnpinRequest(request)).await {
+            Messages::UnpinResponse(response) => assert_eq!(response.error(), messages::Error::Ok),
+            other => panic!("unexpected response {:?}", other),
+        }
+
+        let response = evict(&mut framed, 1 << 20).await;
+        assert_eq!(response.error(), messages::Error::OutOfMemory);
+        assert_eq!((response.evicted, response.spilled), (1, 1));
+        assert!(!contains(&mut framed, &unpinned).await);
+        assert!(contains(&mut framed, &pinned).await);
+        assert_eq!(get(&mut framed, &pinned, 0).await, messages::Error::Ok);
+        assert_eq!(read_chunk(&mut framed, &pinned, 0, 6).await.data, b"pinned");
+
+        let request = messages::PinRequest {
+            object_id: unpinned.binary(),
+        };
+        match call(&mut framed, Messages::PinRequest(request)).await {
+            Messages::PinResponse(response) => {
+                assert_eq!(response.error(), messages::Error::ObjectNonexistent)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn put_ref_counted(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        data: &[u8],
+    ) {
+        let request = messages::PutRequest {
+            object_id: object_id.binary(),
+            data_size: data.len() as u64,
+            data: data.to_vec(),
+            ref_counted: true,
+            ..Default::default()
+        };
+        match call(framed, Messages::PutRequest(request)).await {
+            Messages::PutResponse(response) => assert_eq!(response.error(), messages::Error::Ok),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn inspect(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+    ) -> messages::InspectResponse {
+        let request = messages::InspectRequest {
+            object_id: object_id.binary(),
+        };
+        match call(framed, Messages::InspectRequest(request)).await {
+            Messages::InspectResponse(response) => response,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn add_reference(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+    ) -> messages::Error {
+        let request = messages::AddReferenceRequest {
+            object_id: object_id.binary(),
+        };
+        match call(framed, Messages::AddReferenceRequest(request)).await {
+            Messages::AddReferenceResponse(response) => response.error(),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn remove_reference(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+    ) -> messages::Error {
+        let request = messages::RemoveReferenceRequest {
+            object_id: object_id.binary(),
+        };
+        match call(framed, Messages::RemoveReferenceRequest(request)).await {
+            Messages::RemoveReferenceResponse(response) => response.error(),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn release(framed: &mut Framed<DuplexStream, MessageCodec>, object_id: &ObjectId) {
+        let request = messages::ReleaseRequest {
+            object_id: object_id.binary(),
+        };
+        match call(framed, Messages::ReleaseRequest(request)).await {
+            Messages::ReleaseResponse(response) => {
+                assert_eq!(response.error(), messages::Error::Ok)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    // Waits until the store `framed` is connected to holds `object_id`, or
+    // no longer does.
+    async fn wait_for_contains(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        held: bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while contains(framed, object_id).await != held {
+            assert!(Instant::now() < deadline, "{} held: {}", object_id, !held);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn unreferenced_objects_are_deleted_everywhere() {
+        let owner_dir = TempDir::new();
+        let (owner, owner_address, _owner_tasks) = tcp_store_with(&owner_dir, |_| {}).await;
+        let dir = TempDir::new();
+        let (store, address, _tasks) = tcp_store_with(&dir, |cluster| {
+            cluster.pull_from = vec![owner_address.clone()];
+        })
+        .await;
+        let mut creator = connect(&owner, 0);
+        let object_id = ObjectId::random();
+        put_ref_counted(&mut creator, &object_id, b"counted").await;
+
+        let mut framed = connect(&store, 0);
+        assert_eq!(
+            get(&mut framed, &object_id, 5000).await,
+            messages::Error::Ok
+        );
+        assert_eq!(
+            add_reference(&mut framed, &object_id).await,
+            messages::Error::Ok
+        );
+        let response = inspect(&mut creator, &object_id).await;
+        assert!(response.ref_counted);
+        assert_eq!(response.local_references, 1);
+        let remote = &response.remote_references;
+        assert_eq!((&remote[0].node, remote[0].count), (&address, 1));
+        let response = inspect(&mut framed, &object_id).await;
+        assert_eq!(response.owner_node, owner_address);
+
+        // Still referenced from the other store.
+        assert_eq!(
+            remove_reference(&mut creator, &object_id).await,
+            messages::Error::Ok
+        );
+        assert!(contains(&mut creator, &object_id).await);
+        assert_eq!(
+            remove_reference(&mut creator, &object_id).await,
+            messages::Error::UnexpectedError
+        );
+
+        release(&mut framed, &object_id).await;
+        assert_eq!(
+            remove_reference(&mut framed, &object_id).await,
+            messages::Error::Ok
+        );
+        wait_for_contains(&mut creator, &object_id, false).await;
+        wait_for_contains(&mut framed, &object_id, false).await;
+    }
+
+    #[tokio::test]
+    async fn copies_of_lost_objects_follow_the_policy() {
+        let dirs: Vec<_> = (0..3).map(|_| TempDir::new()).collect();
+        let mut listeners = Vec::new();
+        for _ in &dirs {
+            listeners.push(tcp_listener().await);
+        }
+        let addresses: Vec<_> = listeners
+            .iter()
+            .map(|(_, address)| address.clone())
+            .collect();
+        let policies = [
+            LostObjectPolicy::Keep,
+            LostObjectPolicy::Keep,
+            LostObjectPolicy::Delete,
+        ];
+        let mut stores = Vec::new();
+        for (i, (dir, (listener, address))) in dirs.iter().zip(listeners).enumerate() {
+            let store = test_store_with(dir, |_, cluster| {
+                cluster.tcp_address = Some(address);
+                // The first store owns the object, the others copy it.
+                (cluster.peers, cluster.pull_from) = match i {
+                    0 => (addresses[1..].to_vec(), Vec::new()),
+                    _ => (vec![addresses[0].clone()], vec![addresses[0].clone()]),
+                };
+                cluster.heartbeat_interval = Duration::from_millis(20);
+                cluster.dead_node_timeout = Duration::from_millis(300);
+                cluster.lost_object_policy = policies[i];
+            });
+            let tasks = serve(&store, listener);
+            stores.push((store, Some(tasks)));
+        }
+        let object_id = ObjectId::random();
+        // Disconnecting would drop the only reference.
+        let mut creator = connect(&stores[0].0, 0);
+        put_ref_counted(&mut creator, &object_id, b"lost").await;
+        let mut copies = [connect(&stores[1].0, 0), connect(&stores[2].0, 0)];
+        for framed in &mut copies {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !members(framed).await[1].alive {
+                assert!(Instant::now() < deadline, "the owner is not alive");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert_eq!(get(framed, &object_id, 5000).await, messages::Error::Ok);
+            release(framed, &object_id).await;
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while !inspect(framed, &object_id).await.ref_counted {
+                assert!(Instant::now() < deadline, "the copy is not counted");
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        }
+
+        stores[0].1 = None;
+        let [kept, deleted] = &mut copies;
+        wait_for_contains(deleted, &object_id, false).await;
+        // No longer counted, so it stays until deleted by hand.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while inspect(kept, &object_id).await.ref_counted {
+            assert!(Instant::now() < deadline, "the copy is still counted");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(contains(kept, &object_id).await);
+    }
+
+    #[tokio::test]
+    async fn persisted_objects_survive_a_restart() {
+        let dir = TempDir::new();
+        let spill_dir = TempDir::new();
+        let persisting = |config: &mut StoreConfig, _: &mut ClusterConfig| {
+            config.spill_dir = Some(spill_dir.path().to_path_buf());
+            config.persist = true;
+            config.quotas = HashMap::from([("a".to_string(), Quota::default())]);
+        };
+        let store = test_store_with(&dir, persisting);
+        let mut framed = connect(&store, 0);
+        let object_ids = [ObjectId::random(), ObjectId::random()];
+        for object_id in &object_ids {
+            put(&mut framed, object_id, object_id.as_ref()).await;
+        }
+        let namespaced = ObjectId::random();
+        put(
+            &mut connect_to_namespace(&store, 1, "a").await,
+            &namespaced,
+            b"a",
+        )
+        .await;
+        // Unsealed objects are dropped.
+        let unsealed = ObjectId::random();
+        create(&mut framed, &unsealed, 8, 0).await;
+        drop(framed);
+        store.shutdown(JoinSet::new()).await.unwrap();
+        drop(store);
+
+        let store = test_store_with(&dir, persisting);
+        let mut framed = connect(&store, 0);
+        let stats = match call(
+            &mut framed,
+            Messages::StatsRequest(messages::StatsRequest {}),
+        )
+        .await
+        {
+            Messages::StatsResponse(response) => response,
+            other => panic!("unexpected response {:?}", other),
+        };
+        assert_eq!((stats.num_objects, stats.num_spilled_objects), (0, 3));
+        // Restored on first use.
+        for object_id in &object_ids {
+            assert_eq!(get(&mut framed, object_id, 0).await, messages::Error::Ok);
+            assert_eq!(
+                read_chunk(&mut framed, object_id, 0, 20).await.data,
+                object_id.as_ref()
+            );
+        }
+        assert!(!contains(&mut framed, &unsealed).await);
+        // Charged to their namespace again once restored.
+        let usage = |stats: Vec<messages::NamespaceStats>| {
+            let a = stats.iter().find(|stats| stats.namespace == "a").unwrap();
+            (a.used_bytes, a.num_objects)
+        };
+        assert_eq!(usage(namespace_stats(&mut framed).await), (0, 0));
+        assert_eq!(get(&mut framed, &namespaced, 0).await, messages::Error::Ok);
+        assert_eq!(usage(namespace_stats(&mut framed).await), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn requests_are_counted_in_metrics() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+        let object_id = ObjectId::random();
+        put(&mut framed, &object_id, b"data").await;
+        assert_eq!(get(&mut framed, &object_id, 0).await, messages::Error::Ok);
+        assert_eq!(
+            get(&mut framed, &ObjectId::random(), 0).await,
+            messages::Error::ObjectNonexistent
+        );
+
+        let context = &store.context;
+        let out = context.metrics.render(&context.object_store);
+        for expected in [
+            "crabstore_objects 1",
+            "crabstore_connected_clients 1",
+            "crabstore_request_duration_seconds_count{op=\"get\"} 2",
+            "crabstore_request_duration_seconds_count{op=\"create\"} 0",
+        ] {
+            assert!(
+                out.lines().any(|line| line == expected),
+                "{} not in\n{}",
+                expected,
+                out
+            );
+        }
+    }
+
+    async fn get(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        timeout_ms: i64,
+    ) -> messages::Error {
+        let request = messages::GetRequest {
+            object_ids: vec![object_id.as_ref().to_vec()],
+            timeout_ms,
+        };
+        match call(framed, Messages::GetRequest(request)).await {
+            Messages::GetResponse(response) => response.errors().next().unwrap(),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn get_pulls_from_a_peer() {
+        let peer_dir = TempDir::new();
+        let peer = test_store(&peer_dir);
+        let (listener, address) = tcp_listener().await;
+        let _peer_tasks = serve(&peer, listener);
+        let object_id = ObjectId::random();
+        put(&mut connect(&peer, 0), &object_id, b"pulled").await;
+
+        let dir = TempDir::new();
+        let store = test_store_with(&dir, |_, cluster| cluster.pull_from = vec![address]);
+        let mut framed = connect(&store, 0);
+        assert_eq!(
+            get(&mut framed, &object_id, 5000).await,
+            messages::Error::Ok
+        );
+        assert_eq!(
+            read_chunk(&mut framed, &object_id, 0, 6).await.data,
+            b"pulled"
+        );
+        assert_eq!(
+            get(&mut framed, &ObjectId::random(), 5000).await,
+            messages::Error::ObjectNonexistent
+        );
+    }
+
+    // Asks the directory `framed` is connected to where `object_id` is until
+    // `nodes` hold it, in any order.
+    async fn wait_for_locations(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        object_id: &ObjectId,
+        nodes: &[&str],
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let request = messages::LocateRequest {
+                object_id: object_id.binary(),
+            };
+            let mut located = match call(framed, Messages::LocateRequest(request)).await {
+                Messages::LocateResponse(response) => response.nodes,
+                other => panic!("unexpected response {:?}", other),
+            };
+            located.sort();
+            let mut expected: Vec<_> = nodes.iter().map(|node| node.to_string()).collect();
+            expected.sort();
+            if located == expected {
+                return;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "{} is on {:?}",
+                object_id,
+                located
+            );
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn get_pulls_from_where_the_directory_says() {
+        let directory_dir = TempDir::new();
+        let (listener, directory_address) = tcp_listener().await;
+        let directory_store = test_store_with(&directory_dir, |_, cluster| {
+            cluster.tcp_address = Some(directory_address.clone());
+            cluster.embed_directory = true;
+        });
+        let _directory_tasks = serve(&directory_store, listener);
+        let mut directory = connect(&directory_store, 0);
+        let object_id = ObjectId::random();
+        put(&mut directory, &object_id, b"located").await;
+        wait_for_locations(&mut directory, &object_id, &[&directory_address]).await;
+
+        let dir = TempDir::new();
+        let (listener, address) = tcp_listener().await;
+        let store = test_store_with(&dir, |_, cluster| {
+            cluster.tcp_address = Some(address.clone());
+            cluster.directory_address = Some(directory_address.clone());
+        });
+        let _tasks = serve(&store, listener);
+        let mut framed = connect(&store, 0);
+        assert_eq!(
+            get(&mut framed, &object_id, 5000).await,
+            messages::Error::Ok
+        );
+        // The copy is reported too, and dropped again once deleted.
+        wait_for_locations(&mut directory, &object_id, &[&directory_address, &address]).await;
+        let request = messages::ReleaseRequest {
+            object_id: object_id.binary(),
+        };
+        call(&mut framed, Messages::ReleaseRequest(request)).await;
+        let request = messages::DeleteRequest {
+            object_id: object_id.binary(),
+        };
+        match call(&mut framed, Messages::DeleteRequest(request)).await {
+            Messages::DeleteResponse(response) => assert_eq!(response.error(), messages::Error::Ok),
+            other => panic!("unexpected response {:?}", other),
+        }
+        wait_for_locations(&mut directory, &object_id, &[&directory_address]).await;
+    }
+
+    async fn members(framed: &mut Framed<DuplexStream, MessageCodec>) -> Vec<messages::Member> {
+        match call(
+            framed,
+            Messages::MembersRequest(messages::MembersRequest {}),
+        )
+        .await
+        {
+            Messages::MembersResponse(response) => response.members,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn peers_that_stop_sending_heartbeats_are_purged() {
+        let dir = TempDir::new();
+        let (listener, address) = tcp_listener().await;
+        // Nothing listens there, the test sends its heartbeats instead.
+        let peer = "127.0.0.1:1".to_string();
+        let store = test_store_with(&dir, |_, cluster| {
+            cluster.tcp_address = Some(address.clone());
+            cluster.embed_directory = true;
+            cluster.peers = vec![peer.clone()];
+            cluster.heartbeat_interval = Duration::from_millis(20);
+            cluster.dead_node_timeout = Duration::from_millis(500);
+        });
+        let _tasks = serve(&store, listener);
+        let mut framed = connect(&store, 0);
+        assert!(!members(&mut framed).await[1].alive);
+
+        let request = messages::HeartbeatRequest {
+            node_id: "peer".to_string(),
+            address: peer.clone(),
+            memory_capacity: 1 << 20,
+            memory_used: 0,
+        };
+        match call(&mut framed, Messages::HeartbeatRequest(request)).await {
+            Messages::HeartbeatResponse(response) => assert_eq!(response.address, address),
+            other => panic!("unexpected response {:?}", other),
+        }
+        let object_id = ObjectId::random();
+        let request = messages::DirectoryUpdateRequest {
+            node: peer.clone(),
+            added: vec![object_id.binary()],
+            ..Default::default()
+        };
+        call(&mut framed, Messages::DirectoryUpdateRequest(request)).await;
+        let alive = &members(&mut framed).await[1];
+        assert_eq!((&*alive.node_id, alive.alive), ("peer", true));
+        wait_for_locations(&mut framed, &object_id, &[&peer]).await;
+
+        wait_for_locations(&mut framed, &object_id, &[]).await;
+        assert!(!members(&mut framed).await[1].alive);
+    }
+
+    async fn list(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        request: messages::ListRequest,
+    ) -> messages::ListResponse {
+        match call(framed, Messages::ListRequest(request)).await {
+            Messages::ListResponse(response) => response,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn list_pages() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+        let mut object_ids: Vec<ObjectId> = (0..25).map(|_| ObjectId::random()).collect();
+        for object_id in &object_ids {
+            put(&mut framed, object_id, b"data").await;
+        }
+        object_ids.sort();
+
+        let mut request = messages::ListRequest {
+            limit: 10,
+            ..Default::default()
+        };
+        let mut listed = Vec::new();
+        loop {
+            let response = list(&mut framed, request.clone()).await;
+            assert!(response.objects.len() <= 10);
+            listed.extend(
+                response
+                    .objects
+                    .iter()
+                    .map(|info| ObjectId::try_from_binary(&info.object_id).unwrap()),
+            );
+            if response.next_cursor.is_empty() {
+                break;
+            }
+            request.cursor = response.next_cursor;
+        }
+        assert_eq!(listed, object_ids);
+
+        // Cursors need not be IDs.
+        for cursor in [
+            &object_ids[10].data()[..3],
+            &[object_ids[10].data(), &[0]].concat(),
+        ] {
+            request.cursor = cursor.to_vec();
+            let response = list(&mut framed, request.clone()).await;
+            let first = ObjectId::try_from_binary(&response.objects[0].object_id).unwrap();
+            let expected = object_ids.iter().find(|id| id.data() > cursor).unwrap();
+            assert_eq!(first, *expected);
+        }
+
+        let request = messages::ListRequest {
+            prefix: object_ids[3].data()[..1].to_vec(),
+            min_size: 5,
+            ..Default::default()
+        };
+        assert!(list(&mut framed, request).await.objects.is_empty());
+    }
+
+    // The IDs of the objects listed, in order, and their infos.
+    async fn listed(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+        request: messages::ListRequest,
+    ) -> (Vec<ObjectId>, Vec<messages::ObjectInfo>) {
+        let response = list(framed, request).await;
+        let object_ids = response
+            .objects
+            .iter()
+            .map(|info| ObjectId::try_from_binary(&info.object_id).unwrap())
+            .collect();
+        (object_ids, response.objects)
+    }
+
+    #[tokio::test]
+    async fn list_filters() {
+        let dir = TempDir::new();
+        let store = test_store_with(&dir, |config, _| {
+            config.spill_dir = Some(dir.path().join("spill"));
+        });
+        let mut framed = connect(&store, 0);
+        let (spilled, large, created, counted) = (
+            ObjectId::random(),
+            ObjectId::random(),
+            ObjectId::random(),
+            ObjectId::random(),
+        );
+        put(&mut framed, &spilled, b"data").await;
+        // The oldest object goes first.
+        assert_eq!(evict(&mut framed, 1).await.spilled, 1);
+        put(&mut framed, &large, &[0; 100]).await;
+        let request = messages::UnpinRequest {
+            object_id: large.binary(),
+        };
+        call(&mut framed, Messages::UnpinRequest(request)).await;
+        create(&mut framed, &created, 8, 0).await;
+        let mut other = connect(&store, 1);
+        put_ref_counted(&mut other, &counted, b"data").await;
+
+        let sorted = |mut object_ids: Vec<ObjectId>| {
+            object_ids.sort();
+            object_ids
+        };
+        let with_states = |states: &[messages::ObjectState]| messages::ListRequest {
+            states: states.iter().map(|state| *state as i32).collect(),
+            ..Default::default()
+        };
+
+        let (ids, _) = listed(&mut framed, with_states(&[messages::ObjectState::Spilled])).await;
+        assert_eq!(ids, [spilled]);
+        let (ids, _) = listed(&mut framed, with_states(&[messages::ObjectState::Created])).await;
+        assert_eq!(ids, [created]);
+        let (ids, _) = listed(
+            &mut framed,
+            with_states(&[
+                messages::ObjectState::Sealed,
+                messages::ObjectState::Spilled,
+            ]),
+        )
+        .await;
+        assert_eq!(ids, sorted(vec![spilled, large, counted]));
+
+        let request = messages::ListRequest {
+            min_size: 50,
+            ..Default::default()
+        };
+        assert_eq!(listed(&mut framed, request).await.0, [large]);
+        let request = messages::ListRequest {
+            max_size: 8,
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&mut framed, request).await.0,
+            sorted(vec![spilled, created, counted])
+        );
+        let request = messages::ListRequest {
+            pinned_only: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&mut framed, request).await.0,
+            sorted(vec![spilled, created, counted])
+        );
+
+        // Spilled objects no longer know who created them.
+        let request = messages::ListRequest {
+            owners: vec![0],
+            ..Default::default()
+        };
+        assert_eq!(
+            listed(&mut framed, request).await.0,
+            sorted(vec![large, created])
+        );
+        let request = messages::ListRequest {
+            owners: vec![1],
+            ..Default::default()
+        };
+        let (ids, infos) = listed(&mut framed, request).await;
+        assert_eq!(ids, [counted]);
+        assert_eq!(infos[0].references, 1);
+
+        let request = messages::ListRequest {
+            min_age_ms: 60_000,
+            ..Default::default()
+        };
+        assert!(listed(&mut framed, request).await.0.is_empty());
+        let request = messages::ListRequest {
+            max_age_ms: 60_000,
+            ..Default::default()
+        };
+        assert_eq!(listed(&mut framed, request).await.0.len(), 4);
+
+        let request = messages::ListRequest {
+            prefix: large.data()[..2].to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(listed(&mut framed, request).await.0, [large]);
+    }
+
+    #[tokio::test]
+    async fn connect_with_a_long_namespace() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+
+        let request = messages::ConnectRequest {
+            namespace: "a".repeat(MAX_NAME_LEN + 1),
+        };
+        match call(&mut framed, Messages::ConnectRequest(request)).await {
+            Messages::ErrorResponse(response) => {
+                assert_eq!(response.error(), messages::Error::InvalidRequest)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        let request = messages::ConnectRequest {
+            namespace: "a".repeat(MAX_NAME_LEN),
+        };
+        match call(&mut framed, Messages::ConnectRequest(request)).await {
+            Messages::ConnectResponse(_) => {}
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn connect_to_namespace(
+        store: &CrabStore,
+        client_id: ClientId,
+        namespace: &str,
+    ) -> Framed<DuplexStream, MessageCodec> {
+        let mut framed = connect(store, client_id);
+        let request = messages::ConnectRequest {
+            namespace: namespace.to_string(),
+        };
+        match call(&mut framed, Messages::ConnectRequest(request)).await {
+            Messages::ConnectResponse(_) => framed,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    async fn namespace_stats(
+        framed: &mut Framed<DuplexStream, MessageCodec>,
+    ) -> Vec<messages::NamespaceStats> {
+        match call(framed, Messages::StatsRequest(messages::StatsRequest {})).await {
+            Messages::StatsResponse(response) => response.namespaces,
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn unnamed_clients_are_charged_to_the_default_namespace() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut unnamed = connect_to_namespace(&store, 0, "").await;
+        put(&mut unnamed, &ObjectId::random(), b"data").await;
+        // As are clients that never said.
+        let mut silent = connect(&store, 1);
+        put(&mut silent, &ObjectId::random(), b"data").await;
+
+        let stats = namespace_stats(&mut unnamed).await;
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].namespace, DEFAULT_NAMESPACE);
+        assert_eq!((stats[0].used_bytes, stats[0].num_objects), (8, 2));
+    }
+
+    #[tokio::test]
+    async fn namespaces_without_a_quota_share_the_default_one() {
+        let dir = TempDir::new();
+        let quota = |max_objects| Quota {
+            max_bytes: None,
+            max_objects: Some(max_objects),
+        };
+        let store = test_store_with(&dir, |config, _| {
+            config.quotas = HashMap::from([
+                (DEFAULT_NAMESPACE.to_string(), quota(2)),
+                ("team-a".to_string(), quota(1)),
+            ]);
+        });
+        let mut first = connect_to_namespace(&store, 0, "job-1").await;
+        put(&mut first, &ObjectId::random(), b"data").await;
+        let mut second = connect_to_namespace(&store, 1, "job-2").await;
+        put(&mut second, &ObjectId::random(), b"data").await;
+
+        // A new name brings no fresh quota.
+        let mut third = connect_to_namespace(&store, 2, "job-3").await;
+        let response = create(&mut third, &ObjectId::random(), 4, 0).await;
+        assert_eq!(response.error(), messages::Error::QuotaExceeded);
+        let mut team = connect_to_namespace(&store, 3, "team-a").await;
+        put(&mut team, &ObjectId::random(), b"data").await;
+
+        let stats = namespace_stats(&mut team).await;
+        let names: Vec<&str> = stats.iter().map(|s| s.namespace.as_str()).collect();
+        assert_eq!(names, [DEFAULT_NAMESPACE, "team-a"]);
+        assert_eq!((stats[0].num_objects, stats[0].max_objects), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn client_quota() {
+        let dir = TempDir::new();
+        let store = test_store_with(&dir, |config, _| {
+            config.client_quota = Quota {
+                max_bytes: Some(12),
+                max_objects: Some(2),
+            };
+        });
+        let mut first = connect(&store, 0);
+        put(&mut first, &ObjectId::random(), b"data").await;
+        let response = create(&mut first, &ObjectId::random(), 10, 0).await;
+        assert_eq!(response.error(), messages::Error::QuotaExceeded);
+        put(&mut first, &ObjectId::random(), b"data").await;
+        let response = create(&mut first, &ObjectId::random(), 1, 0).await;
+        assert_eq!(response.error(), messages::Error::QuotaExceeded);
+
+        // Other clients have quotas of their own.
+        let mut second = connect(&store, 1);
+        put(&mut second, &ObjectId::random(), b"12345678").await;
+
+        // Deleting an object gives its creator room again.
+        let object_id = ObjectId::random();
+        put(&mut second, &object_id, b"data").await;
+        let request = messages::DeleteRequest {
+            object_id: object_id.as_ref().to_vec(),
+        };
+        match call(&mut second, Messages::DeleteRequest(request)).await {
+            Messages::DeleteResponse(response) => {
+                assert_eq!(response.error(), messages::Error::Ok)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        let response = create(&mut second, &ObjectId::random(), 4, 0).await;
+        assert_eq!(response.error(), messages::Error::Ok);
+    }
+
+    #[tokio::test]
+    async fn heartbeat_without_peers() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+
+        let request = messages::HeartbeatRequest {
+            node_id: "peer".to_string(),
+            address: "127.0.0.1:1".to_string(),
+            ..Default::default()
+        };
+        match call(&mut framed, Messages::HeartbeatRequest(request)).await {
+            Messages::ErrorResponse(response) => {
+                assert_eq!(response.error(), messages::Error::InvalidRequest);
+                assert_eq!(response.reason, "no peers configured");
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn create_with_overflowing_sizes() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+
+        let response = create(&mut framed, &ObjectId::random(), u64::MAX, 1).await;
+        assert_eq!(response.error(), messages::Error::InvalidRequest);
+        let response = create(&mut framed, &ObjectId::random(), u64::MAX - 8, 0).await;
+        assert_eq!(response.error(), messages::Error::InvalidRequest);
+        let response = create(&mut framed, &ObjectId::random(), u64::MAX - 1024, 0).await;
+        assert_eq!(response.error(), messages::Error::OutOfMemory);
+        let response = create(&mut framed, &ObjectId::random(), 100, 10).await;
+        assert_eq!(response.error(), messages::Error::Ok);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn create_stops_waiting_for_a_stalled_creator() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut first = connect(&store, 0);
+        let mut second = connect(&store, 1);
+        let object_id = ObjectId::random();
+        let response = create(&mut first, &object_id, 8, 0).await;
+        assert_eq!(response.error(), messages::Error::Ok);
+
+        let request = messages::CreateRequest {
+            object_id: object_id.as_ref().to_vec(),
+            data_size: 8,
+            wait_for_seal: true,
+            ..Default::default()
+        };
+        let started = Instant::now();
+        match call(&mut second, Messages::CreateRequest(request)).await {
+            Messages::CreateResponse(response) => {
+                assert_eq!(response.error(), messages::Error::ObjectInUse)
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+        assert!(started.elapsed() >= CREATE_WAIT_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn aborted_clients_are_cleaned_up() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let (client, server) = tokio::io::duplex(1 << 16);
+        let mut clients = JoinSet::new();
+        clients.spawn(handle_client(server, store.context.clone(), 0));
+        let mut framed = Framed::new(client, MessageCodec {});
+
+        let (sealed, unsealed) = (ObjectId::random(), ObjectId::random());
+        put(&mut framed, &sealed, b"abc").await;
+        let request = messages::GetRequest {
+            object_ids: vec![sealed.as_ref().to_vec()],
+            timeout_ms: 0,
+        };
+        match call(&mut framed, Messages::GetRequest(request)).await {
+            Messages::GetResponse(response) => assert_eq!(response.object_ids.len(), 1),
+            other => panic!("unexpected response {:?}", other),
+        }
+        let response = create(&mut framed, &unsealed, 8, 0).await;
+        assert_eq!(response.error(), messages::Error::Ok);
+        let object_store = store.context.object_store.clone();
+        let ref_count = |object_store: &ObjectStore| {
+            object_store
+                .object(&sealed)
+                .unwrap()
+                .info(&sealed)
+                .ref_count
+        };
+        assert_eq!(ref_count(&object_store.locked()), 1);
+
+        // As at the end of a shutdown, with the client still connected.
+        clients.shutdown().await;
+        let object_store = object_store.locked();
+        assert_eq!(ref_count(&object_store), 0);
+        assert_eq!(object_store.state(&unsealed), None);
+    }
+
+    #[tokio::test]
+    async fn put_chunk_with_invalid_id() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+
+        let request = messages::PutChunk {
+            object_id: b"short".to_vec(),
+            data: b"abc".to_vec(),
+        };
+        match call(&mut framed, Messages::PutChunk(request)).await {
+            Messages::PutResponse(response) => {
+                assert_eq!(response.object_id, b"short");
+                assert_eq!(response.error(), messages::Error::InvalidRequest);
+            }
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn read_chunk_out_of_range() {
+        let dir = TempDir::new();
+        let store = test_store(&dir);
+        let mut framed = connect(&store, 0);
+        let object_id = ObjectId::random();
+        put(&mut framed, &object_id, b"abcdef").await;
+
+        let response = read_chunk(&mut framed, &object_id, u64::MAX - 1, 2).await;
+        assert_eq!(response.error(), messages::Error::InvalidRequest);
+        let response = read_chunk(&mut framed, &object_id, 7, 1).await;
+        assert_eq!(response.error(), messages::Error::InvalidRequest);
+        let response = read_chunk(&mut framed, &object_id, 4, u64::MAX).await;
+        assert_eq!(response.error(), messages::Error::Ok);
+        assert_eq!(response.data, b"ef");
+        let response = read_chunk(&mut framed, &object_id, 6, 1).await;
+        assert_eq!(response.error(), messages::Error::Ok);
+        assert!(response.data.is_empty());
+
+        // The store still serves other clients.
+        let mut other = connect(&store, 1);
+        let response = read_chunk(&mut other, &object_id, 0, 0).await;
+        assert_eq!(response.data, b"abcdef");
+    }
+}